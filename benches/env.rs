@@ -0,0 +1,37 @@
+//! How long a call to a script function takes as the config defines more
+//! variables. Run it with `cargo bench`.
+
+use lite::{parse, Editor, Expr};
+use std::time::Instant;
+
+const CALLS: u32 = 10_000;
+
+fn main() {
+    for globals in [10, 100, 1_000, 10_000] {
+        let mut editor = Editor::default();
+        for i in 0..globals {
+            eval(&mut editor, &format!("let var-{i} = {i}"));
+        }
+        eval(&mut editor, "let add-one = x -> x + 1");
+        eval(&mut editor, "let count = n -> { let i = 0; while i < n do let i = add-one i; i }");
+
+        // Calls from the editor, like a key bound to a function
+        let call = parse("add-one 1").unwrap();
+        let start = Instant::now();
+        for _ in 0..CALLS {
+            editor.eval(call.clone()).unwrap();
+        }
+        let from_editor = start.elapsed() / CALLS;
+
+        // Calls from a loop in a script
+        let start = Instant::now();
+        eval(&mut editor, &format!("count {CALLS}"));
+        let from_script = start.elapsed() / CALLS;
+
+        println!("{globals:>6} globals: {from_editor:>10.2?} per call from the editor, {from_script:>10.2?} per call from a script");
+    }
+}
+
+fn eval(editor: &mut Editor, code: &str) -> Expr {
+    editor.eval(parse(code).unwrap()).unwrap()
+}
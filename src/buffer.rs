@@ -1,5 +1,5 @@
-use super::{Change, Direction};
-use std::cmp::min;
+use super::{display_col, Change, DirEntry, DirTree, Direction, HighlightCache, Repl, Span, Syntax, REPL_PROMPT};
+use std::{cell::RefCell, cmp::min};
 
 #[derive(Clone, Debug)]
 pub struct Buffer {
@@ -16,6 +16,18 @@ pub struct Buffer {
     pub redo_stack: Vec<Change>,
 
     edited: bool,
+    read_only: bool,
+    results: bool,
+    tree: Option<DirTree>,
+    /// The background job whose output this buffer shows.
+    job: Option<usize>,
+    repl: Option<Repl>,
+    /// Whether this buffer collects the errors scripts raise.
+    errors: bool,
+    /// Where the view was scrolled to when another buffer was switched to.
+    view_start: (usize, usize),
+    /// Filled in while rendering, so it's behind a `RefCell`.
+    highlights: RefCell<HighlightCache>,
 }
 
 impl Default for Buffer {
@@ -31,12 +43,23 @@ impl Default for Buffer {
             undo_stack: vec![],
             redo_stack: vec![],
             edited: false,
+            read_only: false,
+            results: false,
+            tree: None,
+            job: None,
+            repl: None,
+            errors: false,
+            view_start: (0, 0),
+            highlights: RefCell::default(),
         }
     }
 }
 
 impl Buffer {
     pub fn from_file_name(file: String) -> Self {
+        if std::path::Path::new(&file).is_dir() {
+            return Self::from_dir(file);
+        }
         let mut lines: Vec<_> = std::fs::read_to_string(&file)
             .unwrap_or_else(|_| String::new())
             .lines()
@@ -54,6 +77,62 @@ impl Buffer {
             undo_stack: vec![],
             redo_stack: vec![],
             edited: false,
+            read_only: false,
+            results: false,
+            tree: None,
+            job: None,
+            repl: None,
+            errors: false,
+            view_start: (0, 0),
+            highlights: RefCell::default(),
+        }
+    }
+
+    /// A read-only buffer listing the entries of a directory.
+    pub fn from_dir(dir: String) -> Self {
+        let tree = DirTree::new(&dir);
+        Self {
+            file: Some(dir),
+            lines: tree.lines(),
+            tree: Some(tree),
+            read_only: true,
+            ..Self::default()
+        }
+    }
+
+    /// A read-only buffer of `path:line:col: text` search results.
+    pub fn from_search_results(text: &str) -> Self {
+        Self {
+            read_only: true,
+            results: true,
+            ..Self::from_text(text)
+        }
+    }
+
+    /// A read-only buffer that the output of a background job is added to.
+    pub fn from_job(id: usize, command: &str) -> Self {
+        Self {
+            read_only: true,
+            job: Some(id),
+            ..Self::from_text(&format!("$ {command}"))
+        }
+    }
+
+    /// A buffer for running code typed after a prompt, and seeing what it returns.
+    pub fn from_repl(repl: Repl) -> Self {
+        Self {
+            cursor_col: REPL_PROMPT.len(),
+            repl: Some(repl),
+            ..Self::from_text(REPL_PROMPT)
+        }
+    }
+
+    /// A read-only buffer that errors are added to as scripts raise them.
+    pub fn from_errors() -> Self {
+        Self {
+            read_only: true,
+            errors: true,
+            ..Self::from_text("Errors raised by scripts, newest last:")
         }
     }
 
@@ -71,6 +150,14 @@ impl Buffer {
             undo_stack: vec![],
             redo_stack: vec![],
             edited: false,
+            read_only: false,
+            results: false,
+            tree: None,
+            job: None,
+            repl: None,
+            errors: false,
+            view_start: (0, 0),
+            highlights: RefCell::default(),
         }
     }
 
@@ -105,11 +192,28 @@ impl Buffer {
         self.file.as_deref()
     }
 
+    /// A short name for the buffer: the last component of its file name.
+    pub fn short_name(&self) -> &str {
+        match &self.file {
+            Some(file) => std::path::Path::new(file)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or(file),
+            None if self.is_repl() => "REPL",
+            None if self.errors => "errors",
+            None => "unnamed",
+        }
+    }
+
     pub fn set_file_name(&mut self, file: String) {
         self.file = Some(file);
     }
 
     pub fn save(&mut self, file_name: &str) -> std::io::Result<()> {
+        if self.is_dir() {
+            // There is nothing to write for a directory listing
+            return Ok(());
+        }
         self.edited = false;
         std::fs::write(file_name, self.lines.join("\n"))
     }
@@ -117,6 +221,170 @@ impl Buffer {
     pub fn is_edited(&self) -> bool {
         self.edited
     }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Whether the lines of this buffer are search results that can be
+    /// opened with `Editor::open_result`.
+    pub fn is_results(&self) -> bool {
+        self.results
+    }
+
+    pub fn job(&self) -> Option<usize> {
+        self.job
+    }
+
+    pub fn is_errors(&self) -> bool {
+        self.errors
+    }
+
+    /// The first row and column visible the last time this buffer was shown.
+    pub fn view_start(&self) -> (usize, usize) {
+        self.view_start
+    }
+
+    pub fn set_view_start(&mut self, start: (usize, usize)) {
+        self.view_start = start;
+    }
+
+    pub fn is_repl(&self) -> bool {
+        self.repl.is_some()
+    }
+
+    pub fn repl(&self) -> Option<&Repl> {
+        self.repl.as_ref()
+    }
+
+    pub fn repl_mut(&mut self) -> Option<&mut Repl> {
+        self.repl.as_mut()
+    }
+
+    /// The code typed after the prompt of a REPL buffer.
+    pub fn repl_input(&self) -> Option<String> {
+        let (row, col) = self.repl.as_ref()?.input_start();
+        let mut input = self.lines[row][col..].to_string();
+        for line in &self.lines[row + 1..] {
+            input.push('\n');
+            input.push_str(line);
+        }
+        Some(input)
+    }
+
+    /// Replace the code typed after the prompt of a REPL buffer, leaving
+    /// the cursor at its end.
+    pub fn set_repl_input(&mut self, input: &str) {
+        let Some(row) = self.repl.as_ref().map(|repl| repl.input_row) else {
+            return;
+        };
+        self.lines.truncate(row);
+        self.lines.extend(format!("{REPL_PROMPT}{input}").split('\n').map(String::from));
+        self.highlights.get_mut().invalidate(row);
+        self.cursor_row = self.lines.len() - 1;
+        self.cursor_col = self.cur_line().len();
+        self.unselect();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+    /// Finish the input of a REPL buffer: add what it output after it, and
+    /// a prompt for the next input. The input becomes part of the
+    /// transcript, so it can't be undone or edited any more.
+    pub fn end_repl_input(&mut self, output: &[String]) {
+        let Some(repl) = &mut self.repl else {
+            return;
+        };
+        self.lines.extend(output.iter().cloned());
+        self.lines.push(REPL_PROMPT.to_string());
+        repl.input_row = self.lines.len() - 1;
+        self.highlights.get_mut().invalidate(repl.input_row);
+        self.cursor_row = repl.input_row;
+        self.cursor_col = REPL_PROMPT.len();
+        self.unselect();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.edited = false;
+    }
+
+    /// Whether text can be inserted at the cursor, which is anywhere but
+    /// before the prompt of a REPL buffer.
+    pub fn can_insert(&self) -> bool {
+        !self.read_only && self.repl.as_ref().is_none_or(|repl| self.cur_pos() >= repl.input_start())
+    }
+
+    /// How many characters can be deleted before the cursor, which is only
+    /// as far back as the prompt in a REPL buffer.
+    pub fn deletable(&self, count: usize) -> usize {
+        let Some(repl) = &self.repl else {
+            return count;
+        };
+        let (start_row, start_col) = repl.input_start();
+        if self.cur_pos() < (start_row, start_col) {
+            return 0;
+        }
+        // Each line break counts as a character
+        let mut chars = self.lines[self.cursor_row][..self.cursor_col].chars().count();
+        for row in start_row..self.cursor_row {
+            let line = if row == start_row { &self.lines[row][start_col..] } else { &self.lines[row] };
+            chars += line.chars().count() + 1;
+        }
+        count.min(chars)
+    }
+
+    /// Add a line to the end of the buffer, even if it's read-only,
+    /// without counting it as an edit.
+    pub fn append_line(&mut self, line: &str) {
+        self.lines.push(line.to_string());
+        self.highlights.get_mut().invalidate(self.lines.len() - 1);
+    }
+
+    /// Read the buffer's file from disk again, discarding its history.
+    pub fn reload(&mut self) {
+        if let Some(file) = self.file.clone() {
+            let cursor = self.cur_pos();
+            *self = Self::from_file_name(file);
+            (self.cursor_row, self.cursor_col) = cursor;
+            self.fix_cursor();
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.tree.is_some()
+    }
+
+    pub fn dir_tree(&self) -> Option<&DirTree> {
+        self.tree.as_ref()
+    }
+
+    /// The directory entry on the cursor's line, in a directory buffer.
+    pub fn dir_entry(&self) -> Option<&DirEntry> {
+        self.tree.as_ref().and_then(|tree| tree.entry(self.cursor_row))
+    }
+
+    /// Expand or collapse a subdirectory in a directory buffer.
+    pub fn toggle_dir(&mut self, path: &std::path::Path) {
+        if let Some(tree) = &mut self.tree {
+            tree.toggle(path);
+            self.lines = tree.lines();
+            self.highlights.get_mut().invalidate(0);
+            self.fix_cursor();
+        }
+    }
+
+    /// List a directory buffer's entries again from disk.
+    pub fn refresh_dir(&mut self) {
+        if let Some(tree) = &mut self.tree {
+            tree.refresh();
+            self.lines = tree.lines();
+            self.highlights.get_mut().invalidate(0);
+            self.fix_cursor();
+        }
+    }
     // fn set_screen_rows(&mut self, rows: usize) {
     //     self.screen_rows = rows;
     // }
@@ -140,6 +408,12 @@ impl Buffer {
         (self.cursor_row, self.cursor_col)
     }
 
+    /// The cursor's row, and the cell of its line it's drawn at, which
+    /// differs from its column when there are wide characters before it.
+    pub fn cur_screen_pos(&self) -> (usize, usize) {
+        (self.cursor_row, display_col(self.cur_line(), self.cursor_col))
+    }
+
     pub fn fix_cursor(&mut self) {
         self.cursor_row = min(self.lines.len() - 1, self.cursor_row);
         self.cursor_col = min(self.cur_line().len(), self.cursor_col);
@@ -187,11 +461,11 @@ impl Buffer {
     }
 
     pub fn selection_start(&self) -> Option<(usize, usize)> {
-        self.selection_range().and_then(|(start, _)| Some(start))
+        self.selection_range().map(|(start, _)| start)
     }
 
     pub fn selection_end(&self) -> Option<(usize, usize)> {
-        self.selection_range().and_then(|(_, end)| Some(end))
+        self.selection_range().map(|(_, end)| end)
     }
 
     pub fn selected_lines(&self) -> Option<&[String]> {
@@ -207,6 +481,7 @@ impl Buffer {
     }
     pub fn selected_lines_mut(&mut self) -> Option<&mut [String]> {
         if let Some((selected_row, _)) = self.select_row_col {
+            self.highlights.get_mut().invalidate(selected_row.min(self.cursor_row));
             if selected_row < self.cursor_row {
                 Some(&mut self.lines[selected_row..=self.cursor_row])
             } else {
@@ -248,9 +523,16 @@ impl Buffer {
 
     pub fn cur_line_mut(&mut self) -> &mut String {
         self.edited = true;
+        self.highlights.get_mut().invalidate(self.cursor_row);
         &mut self.lines[self.cursor_row]
     }
 
+    /// The highlighted spans of the lines in `start..end`. Lines are only
+    /// highlighted again after they, or a line above them, are edited.
+    pub fn highlight(&self, syntax: &Syntax, start: usize, end: usize) -> Vec<Vec<Span>> {
+        self.highlights.borrow_mut().highlight(syntax, &self.lines, start, end)
+    }
+
     pub fn insert_str(&mut self, text: &str) {
         self.edited = true;
         for ch in text.chars() {
@@ -278,7 +560,7 @@ impl Buffer {
         let lines_len = self.lines.len();
         let line = self.cur_line_mut();
 
-        if lines_len == 1 && line.len() == 0 {
+        if lines_len == 1 && line.is_empty() {
             None
         } else if col < line.len() {
             // If the cursor is before the end of the string,
@@ -303,11 +585,11 @@ impl Buffer {
                 if self.cursor_row > 0 {
                     self.cursor_row -= 1;
                 }
-                self.cursor_col = min(self.cur_line().len(), self.cursor_col)
+                self.fix_col();
             }
             Direction::Down => {
                 self.cursor_row = min(self.lines.len() - 1, self.cursor_row + 1);
-                self.cursor_col = min(self.cur_line().len(), self.cursor_col)
+                self.fix_col();
             }
             Direction::Left => {
                 if self.cursor_col == 0 {
@@ -317,7 +599,9 @@ impl Buffer {
                         self.cursor_col = self.cur_line().len()
                     }
                 } else {
-                    self.cursor_col -= 1
+                    // Step over a whole character, however many bytes it is
+                    let before = &self.cur_line()[..self.cursor_col];
+                    self.cursor_col -= before.chars().next_back().map_or(1, char::len_utf8)
                 }
             }
             Direction::Right => {
@@ -328,11 +612,22 @@ impl Buffer {
                         self.cursor_col = 0
                     }
                 } else {
-                    self.cursor_col += 1
+                    let after = &self.cur_line()[self.cursor_col..];
+                    self.cursor_col += after.chars().next().map_or(1, char::len_utf8)
                 }
             }
             Direction::Nowhere => {}
         }
         // self.fix_screen()
     }
+
+    /// Keep the cursor within its line, and off the middle of a character.
+    fn fix_col(&mut self) {
+        let line = self.cur_line();
+        let mut col = min(line.len(), self.cursor_col);
+        while !line.is_char_boundary(col) {
+            col -= 1;
+        }
+        self.cursor_col = col;
+    }
 }
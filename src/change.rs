@@ -18,10 +18,7 @@ impl Change {
     }
 
     pub fn modifies_content(&self) -> bool {
-        match self {
-            Self::Insert(_) | Self::Delete(_) => true,
-            _ => false,
-        }
+        matches!(self, Self::Insert(_) | Self::Delete(_))
     }
 
     pub fn goto_cur(pos: (usize, usize), buf: &Buffer) -> Self {
@@ -58,11 +55,7 @@ impl Change {
                 for _ in 0..*count {
                     buf.move_cur(*dir);
                 }
-                if buf.cur_pos() == old_pos {
-                    buf.undo_stack.push(Self::Move(old_pos, Direction::Nowhere, *count));
-                } else {
-                    buf.undo_stack.push(Self::Move(old_pos, Direction::Nowhere, *count));
-                }
+                buf.undo_stack.push(Self::Move(old_pos, Direction::Nowhere, *count));
             }
             Self::Goto((old_row, old_col), (new_row, new_col)) => {
                 buf.cursor_row = *new_row;
@@ -0,0 +1,108 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A file or directory listed in a directory buffer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub is_dir: bool,
+}
+
+/// The contents of a directory buffer: a tree of entries under a root,
+/// where only the expanded subdirectories are listed.
+#[derive(Clone, Debug)]
+pub struct DirTree {
+    root: PathBuf,
+    expanded: BTreeSet<PathBuf>,
+    entries: Vec<DirEntry>,
+}
+
+impl DirTree {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let mut tree = Self {
+            root: root.into(),
+            expanded: BTreeSet::new(),
+            entries: vec![],
+        };
+        tree.refresh();
+        tree
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn entries(&self) -> &[DirEntry] {
+        &self.entries
+    }
+
+    /// The entry shown on a line of the buffer. The first line is the
+    /// root directory itself, so it has no entry.
+    pub fn entry(&self, row: usize) -> Option<&DirEntry> {
+        row.checked_sub(1).and_then(|i| self.entries.get(i))
+    }
+
+    /// Expand a collapsed subdirectory, or collapse an expanded one.
+    pub fn toggle(&mut self, path: &Path) {
+        if !self.expanded.remove(path) {
+            self.expanded.insert(path.to_path_buf());
+        }
+        self.refresh();
+    }
+
+    /// Read the directory again from disk.
+    pub fn refresh(&mut self) {
+        self.entries.clear();
+        let root = self.root.clone();
+        self.list(&root, 0);
+    }
+
+    fn list(&mut self, dir: &Path, depth: usize) {
+        let mut children: Vec<(bool, PathBuf)> = match fs::read_dir(dir) {
+            Ok(children) => children
+                .filter_map(Result::ok)
+                .map(|entry| (entry.path().is_dir(), entry.path()))
+                .collect(),
+            Err(_) => return,
+        };
+        // Directories first, then files, each in alphabetical order
+        children.sort_by(|(a_dir, a), (b_dir, b)| b_dir.cmp(a_dir).then(a.cmp(b)));
+
+        for (is_dir, path) in children {
+            let expanded = is_dir && self.expanded.contains(&path);
+            self.entries.push(DirEntry {
+                path: path.clone(),
+                depth,
+                is_dir,
+            });
+            if expanded {
+                self.list(&path, depth + 1);
+            }
+        }
+    }
+
+    /// The text of the buffer: the root, then one line per entry.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("{}/", self.root.display())];
+        for entry in &self.entries {
+            let name = entry
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let indent = "  ".repeat(entry.depth);
+            lines.push(if !entry.is_dir {
+                format!("{indent}  {name}")
+            } else if self.expanded.contains(&entry.path) {
+                format!("{indent}- {name}/")
+            } else {
+                format!("{indent}+ {name}/")
+            });
+        }
+        lines
+    }
+}
@@ -1,18 +1,93 @@
-use super::{eval, Buffer, Change, Direction, Env, Expr};
-use std::{cmp::min, fmt};
+use super::{
+    byte_col, dict, error_summary, is_complete, parse_file, parse_location, pretty, pretty_error, run, spawn_job, start_memory_limit, finish_memory_limit, check_memory, check_stack, stack_position, string, symbol, Buffer, Change, Direction, Env, Event, EventQueue, Expr,
+    FileWatcher, Hook, Interrupt, Limits, Keymap, Repl, Syntax, Syntaxes, Theme, Themes, Timer, View, HOOK_EVENTS,
+};
+use std::{
+    cmp::min,
+    fmt,
+    path::PathBuf,
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
 
 pub struct Editor {
     buffers: Vec<Buffer>,
     current_buffer_index: usize,
+    view: View,
+    tab_bar: bool,
+    mouse: bool,
+    keymap: Keymap,
+    syntaxes: Syntaxes,
+    themes: Themes,
+    /// The queue events are sent to, until the frontend takes it.
+    events: Option<EventQueue>,
+    sender: Sender<Event>,
+    watcher: FileWatcher,
+    timers: Vec<Timer>,
+    next_timer_id: usize,
+    next_job_id: usize,
+    hooks: Vec<Hook>,
+    next_hook_id: usize,
+    /// Whether hooks are running, so the changes they make don't run more hooks.
+    in_hook: bool,
+    /// The errors raised by hooks that haven't been shown yet.
+    hook_errors: Vec<Expr>,
+    /// When the editor goes idle next, if it's been active since it last did.
+    idle_at: Option<Instant>,
     pub env: Env,
+    limits: Limits,
+    /// Set from another thread to stop the running script.
+    interrupt: Interrupt,
+    /// How many evaluations are running inside each other, so the counts
+    /// below start over only for the outermost one.
+    scripts: usize,
+    steps: u64,
+    depth: usize,
+    /// Where the stack was when the outermost evaluation started.
+    stack_start: usize,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Editor {
+    /// How long the editor waits without any events before running idle callbacks.
+    const IDLE_DELAY: Duration = Duration::from_millis(500);
+
     pub fn new() -> Self {
+        let events = EventQueue::default();
+        let sender = events.sender();
+        let interrupt = events.interrupt();
         Self {
             buffers: vec![Buffer::default()],
             current_buffer_index: 0,
-            env: Env::default(),
+            view: View::default(),
+            tab_bar: false,
+            mouse: true,
+            keymap: Keymap::default(),
+            syntaxes: Syntaxes::default(),
+            themes: Themes::default(),
+            events: Some(events),
+            watcher: FileWatcher::new(sender.clone()),
+            sender,
+            timers: vec![],
+            next_timer_id: 0,
+            next_job_id: 0,
+            hooks: vec![],
+            next_hook_id: 0,
+            in_hook: false,
+            hook_errors: vec![],
+            idle_at: Some(Instant::now() + Self::IDLE_DELAY),
+            env: Env::new(),
+            limits: Limits::default(),
+            interrupt,
+            scripts: 0,
+            steps: 0,
+            depth: 0,
+            stack_start: 0,
         }
     }
 
@@ -31,21 +106,403 @@ impl Editor {
     }
 
     pub fn add_buf(&mut self, buf: Buffer) {
+        let watch = buf.get_file_name().is_some();
         self.buffers.push(buf);
+        if watch {
+            self.watch_open_files();
+        }
+    }
+
+    /// Point a buffer at another file, such as after the file was renamed.
+    pub fn rename_buf(&mut self, id: usize, file: String) {
+        if let Some(buf) = self.buffers.get_mut(id) {
+            buf.set_file_name(file);
+            self.watch_open_files();
+        }
+    }
+
+    /// Find the buffer that is editing the given file, if any.
+    pub fn find_buf(&self, file: &str) -> Option<usize> {
+        let target = std::fs::canonicalize(file).ok();
+        self.buffers.iter().position(|buf| match buf.get_file_name() {
+            Some(name) if name == file => true,
+            Some(name) => target.is_some() && std::fs::canonicalize(name).ok() == target,
+            None => false,
+        })
+    }
+
+    /// Switch to the buffer editing `file`, opening it in a new buffer
+    /// if it isn't open yet.
+    pub fn open_file(&mut self, file: String) {
+        match self.find_buf(&file) {
+            Some(id) => self.set_buf(id),
+            None => {
+                self.add_buf(Buffer::from_file_name(file.clone()));
+                self.set_buf(self.max_buf_id());
+                self.run_hooks("open", &[("buffer", Expr::Int(self.cur_buf_id() as i64)), ("file", string(file))]);
+            }
+        }
+    }
+
+    /// Save the current buffer to `file`, running the save hooks before
+    /// and after.
+    pub fn save(&mut self, file: &str) -> Result<(), String> {
+        let details = [("buffer", Expr::Int(self.cur_buf_id() as i64)), ("file", string(file))];
+        self.run_hooks("before-save", &details);
+        self.cur_buf_mut().ok_or("There is no buffer to save")?.save(file).map_err(|e| e.to_string())?;
+        // A new file can only be watched once it exists
+        self.watch_open_files();
+        self.run_hooks("after-save", &details);
+        Ok(())
     }
 
     pub fn quit_buf(&mut self, save: bool) {
         if save {
-            let buf = self.cur_buf_mut().unwrap();
-            if let Some(file_name) = buf.get_file_name().map(String::from) {
-                buf.save(&file_name).unwrap();
+            if let Some(file_name) = self.cur_buf().unwrap().get_file_name().map(String::from) {
+                self.save(&file_name).unwrap();
             }
         }
-        self.buffers.remove(self.cur_buf_id());
+        let removed = self.cur_buf_id();
+        self.buffers.remove(removed);
+        for repl in self.buffers.iter_mut().filter_map(Buffer::repl_mut) {
+            repl.target = match repl.target {
+                Some(target) if target > removed => Some(target - 1),
+                Some(target) if target == removed => None,
+                target => target,
+            };
+        }
         if self.buffers.is_empty() {
             self.new_buf();
         }
-        self.set_buf(min(self.buffers.len() - 1, self.cur_buf_id() - 1));
+        self.watch_open_files();
+        let id = min(self.buffers.len() - 1, self.cur_buf_id().saturating_sub(1));
+        // The buffer ids after the removed one have shifted down
+        self.view.show(id, self.buffers[id].view_start());
+        self.set_buf(id);
+    }
+
+    /// Every open buffer, indexed by buffer id.
+    pub fn buffers(&self) -> &[Buffer] {
+        &self.buffers
+    }
+
+    pub fn tab_bar(&self) -> bool {
+        self.tab_bar
+    }
+
+    /// Show or hide the list of open buffers along the top of the screen.
+    pub fn set_tab_bar(&mut self, show: bool) {
+        self.tab_bar = show;
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    pub fn keymap_mut(&mut self) -> &mut Keymap {
+        &mut self.keymap
+    }
+
+    pub fn mouse(&self) -> bool {
+        self.mouse
+    }
+
+    /// Turn mouse support on or off. While it's off, the terminal
+    /// handles the mouse itself, so text can be selected and copied there.
+    pub fn set_mouse(&mut self, enabled: bool) {
+        self.mouse = enabled;
+    }
+
+    /// In a directory buffer, expand or collapse the directory under the
+    /// cursor, or open the file under the cursor.
+    pub fn open_dir_entry(&mut self) {
+        let Some(entry) = self.cur_buf().and_then(Buffer::dir_entry).cloned() else {
+            return;
+        };
+        if entry.is_dir {
+            if let Some(buf) = self.cur_buf_mut() {
+                buf.toggle_dir(&entry.path);
+            }
+        } else {
+            self.open_file(entry.path.to_string_lossy().into_owned());
+        }
+    }
+
+    /// In a search results buffer, open the file on the cursor's line
+    /// and jump to the match.
+    pub fn open_result(&mut self) {
+        let Some((file, pos)) = self.cur_buf().and_then(|buf| parse_location(buf.cur_line())) else {
+            return;
+        };
+        self.open_file(file);
+        self.unselect();
+        self.goto_cur(pos);
+        self.center_view();
+    }
+
+    /// Reload the buffer editing `file`, unless it has unsaved changes.
+    pub fn reload_file(&mut self, file: &str) {
+        if let Some(id) = self.find_buf(file) {
+            if !self.buffers[id].is_edited() {
+                self.buffers[id].reload();
+            }
+        }
+    }
+
+    /// Take the queue that file changes and job output are sent to, for
+    /// the frontend to wait on along with its input.
+    pub fn take_event_queue(&mut self) -> Option<EventQueue> {
+        self.events.take()
+    }
+
+    /// Watch the files of the open buffers for changes on disk. The editor
+    /// calls this itself whenever the set of open files changes.
+    pub fn watch_open_files(&mut self) {
+        let files: Vec<PathBuf> = self
+            .buffers
+            .iter()
+            .filter(|buf| !buf.is_dir())
+            .filter_map(|buf| buf.get_file_name().map(PathBuf::from))
+            .collect();
+        self.watcher.watch(files);
+    }
+
+    /// Respond to a file changing on disk: reload its buffer, unless that
+    /// would lose edits. Returns a message for the user, if there is one.
+    pub fn file_changed(&mut self, file: &str) -> Option<String> {
+        let id = self.find_buf(file)?;
+        let buf = &mut self.buffers[id];
+        let name = buf.get_file_name().unwrap_or(file).to_string();
+        let text = std::fs::read_to_string(file).ok()?;
+        if text.lines().eq(buf.content().iter().map(String::as_str)) {
+            // Most likely the editor saved it itself
+            None
+        } else if buf.is_edited() {
+            Some(format!("{} changed on disk; it has unsaved edits, so it wasn't reloaded", name))
+        } else {
+            buf.reload();
+            self.follow_cursor();
+            Some(format!("Reloaded {}", name))
+        }
+    }
+
+    /// Run a shell command in the background, showing its output in a new
+    /// buffer. Returns the job's id.
+    pub fn spawn_job(&mut self, command: &str) -> Result<usize, String> {
+        let id = self.next_job_id;
+        spawn_job(id, command, self.sender.clone())?;
+        self.next_job_id += 1;
+        self.add_buf(Buffer::from_job(id, command));
+        Ok(id)
+    }
+
+    /// Add a line of a job's output to its buffer.
+    pub fn job_output(&mut self, id: usize, line: &str) {
+        if let Some(buf) = self.buffers.iter_mut().find(|buf| buf.job() == Some(id)) {
+            buf.append_line(line);
+        }
+    }
+
+    /// Run `callback` once after `delay`, or every `delay` if `repeat` is set.
+    /// Returns an id for cancelling it.
+    pub fn add_timer(&mut self, delay: Duration, repeat: bool, callback: Expr) -> usize {
+        self.push_timer(Timer {
+            id: self.next_timer_id,
+            at: Some(Instant::now() + delay),
+            every: if repeat { Some(delay) } else { None },
+            idle: false,
+            callback,
+        })
+    }
+
+    /// Run `callback` each time the editor goes idle, after a short
+    /// while without any input or other events.
+    pub fn add_idle_callback(&mut self, callback: Expr) -> usize {
+        self.push_timer(Timer {
+            id: self.next_timer_id,
+            at: Some(Instant::now() + Self::IDLE_DELAY),
+            every: None,
+            idle: true,
+            callback,
+        })
+    }
+
+    fn push_timer(&mut self, timer: Timer) -> usize {
+        self.next_timer_id += 1;
+        self.timers.push(timer);
+        self.next_timer_id - 1
+    }
+
+    /// Stop a timer or idle callback. Returns whether there was one with this id.
+    pub fn cancel_timer(&mut self, id: usize) -> bool {
+        let count = self.timers.len();
+        self.timers.retain(|timer| timer.id != id);
+        self.timers.len() < count
+    }
+
+    /// Call `callback` with the details of each `event`, one of the
+    /// `HOOK_EVENTS`. Returns an id for removing it.
+    pub fn add_hook(&mut self, event: &str, callback: Expr) -> Result<usize, String> {
+        if !HOOK_EVENTS.contains(&event) {
+            return Err(format!("Unknown event {}, expected one of: {}", event, HOOK_EVENTS.join(", ")));
+        }
+        self.hooks.push(Hook {
+            id: self.next_hook_id,
+            event: event.to_string(),
+            callback,
+        });
+        self.next_hook_id += 1;
+        Ok(self.next_hook_id - 1)
+    }
+
+    /// Remove a hook. Returns whether there was one with this id.
+    pub fn remove_hook(&mut self, id: usize) -> bool {
+        let count = self.hooks.len();
+        self.hooks.retain(|hook| hook.id != id);
+        self.hooks.len() < count
+    }
+
+    /// Call the hooks on an event with a dict of its details. Errors are
+    /// kept for `take_hook_errors`, and changes the hooks make to the
+    /// editor don't run any more hooks.
+    pub fn run_hooks(&mut self, event: &str, details: &[(&str, Expr)]) {
+        if self.in_hook || !self.hooks.iter().any(|hook| hook.event == event) {
+            return;
+        }
+        let mut details: Vec<(Expr, Expr)> = details.iter().map(|(key, value)| (symbol(key), value.clone())).collect();
+        details.push((symbol("event"), string(event)));
+        let details = dict(&details);
+
+        let hooks: Vec<Expr> = self
+            .hooks
+            .iter()
+            .filter(|hook| hook.event == event)
+            .map(|hook| hook.callback.clone())
+            .collect();
+        self.in_hook = true;
+        for callback in hooks {
+            if let Err(e) = self.eval(Expr::Apply(Box::new(callback), vec![details.clone()])) {
+                self.hook_errors.push(e);
+            }
+        }
+        self.in_hook = false;
+    }
+
+    /// Add an error to the errors buffer, opening it if it isn't open yet.
+    /// Returns a line about it for the status line.
+    pub fn report_error(&mut self, error: &Expr) -> String {
+        let width = self.view.text_cols();
+        let id = match self.buffers.iter().position(Buffer::is_errors) {
+            Some(id) => id,
+            None => {
+                self.add_buf(Buffer::from_errors());
+                self.max_buf_id()
+            }
+        };
+        let buf = &mut self.buffers[id];
+        buf.append_line("");
+        for line in pretty_error(error, width) {
+            buf.append_line(&line);
+        }
+        format!("{} (see the errors buffer)", error_summary(error))
+    }
+
+    /// Switch to the errors buffer, if any errors have been reported.
+    pub fn show_errors(&mut self) {
+        if let Some(id) = self.buffers.iter().position(Buffer::is_errors) {
+            self.set_buf(id);
+        }
+    }
+
+    /// Take the errors raised by hooks since the last call.
+    pub fn take_hook_errors(&mut self) -> Vec<Expr> {
+        std::mem::take(&mut self.hook_errors)
+    }
+
+    /// When the next timer, idle callback or idle hook is due, if any are.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let idle = self.idle_at.filter(|_| self.hooks.iter().any(|hook| hook.event == "idle"));
+        self.timers.iter().filter_map(|timer| timer.at).chain(idle).min()
+    }
+
+    /// Note that events just happened, so the editor isn't idle anymore.
+    pub fn mark_active(&mut self) {
+        let at = Instant::now() + Self::IDLE_DELAY;
+        self.idle_at = Some(at);
+        for timer in self.timers.iter_mut().filter(|timer| timer.idle) {
+            timer.at = Some(at);
+        }
+    }
+
+    /// Run every timer and idle callback that's due. Returns the errors
+    /// the callbacks raised.
+    pub fn run_timers(&mut self) -> Vec<Expr> {
+        let now = Instant::now();
+        let due: Vec<Timer> = self
+            .timers
+            .iter()
+            .filter(|timer| timer.at.is_some_and(|at| at <= now))
+            .cloned()
+            .collect();
+
+        let mut errors = vec![];
+        for timer in due {
+            // Reschedule first, so the callback can cancel its own timer
+            if let Some(t) = self.timers.iter_mut().find(|t| t.id == timer.id) {
+                t.at = timer.every.map(|every| now + every);
+            }
+            self.timers.retain(|t| t.id != timer.id || t.idle || t.every.is_some());
+            if let Err(e) = self.eval(Expr::Apply(Box::new(timer.callback), vec![])) {
+                errors.push(e);
+            }
+        }
+
+        if self.idle_at.is_some_and(|at| at <= now) {
+            self.idle_at = None;
+            self.run_hooks("idle", &[]);
+        }
+        errors
+    }
+
+    /// List every directory buffer's entries again, after files were
+    /// created, renamed or deleted.
+    pub fn refresh_dirs(&mut self) {
+        for buf in &mut self.buffers {
+            buf.refresh_dir();
+        }
+    }
+
+    pub fn syntaxes(&self) -> &Syntaxes {
+        &self.syntaxes
+    }
+
+    pub fn syntaxes_mut(&mut self) -> &mut Syntaxes {
+        &mut self.syntaxes
+    }
+
+    pub fn themes(&self) -> &Themes {
+        &self.themes
+    }
+
+    pub fn themes_mut(&mut self) -> &mut Themes {
+        &mut self.themes
+    }
+
+    /// The theme the frontend should draw with.
+    pub fn theme(&self) -> &Theme {
+        self.themes.current()
+    }
+
+    /// The syntax to highlight a buffer with, if it's a known language.
+    pub fn syntax(&self, buf: &Buffer) -> Option<&Syntax> {
+        if buf.is_dir() || buf.is_results() {
+            return None;
+        }
+        if buf.is_repl() {
+            // What's typed into the REPL is code in the scripting language
+            return self.syntaxes.detect(Some("repl.lite"), "");
+        }
+        self.syntaxes.detect(buf.get_file_name(), &buf.content()[0])
     }
 
     pub fn max_buf_id(&self) -> usize {
@@ -53,15 +510,118 @@ impl Editor {
     }
 
     pub fn set_buf(&mut self, id: usize) {
+        let from = self.current_buffer_index;
         if id < self.buffers.len() {
             self.current_buffer_index = id;
         }
+        if self.view.buf_id() != self.current_buffer_index {
+            // Each buffer keeps its place while another one is shown
+            let start = self.view.start();
+            if let Some(buf) = self.buffers.get_mut(self.view.buf_id()) {
+                buf.set_view_start(start);
+            }
+            self.view.show(self.current_buffer_index, self.buffers[self.current_buffer_index].view_start());
+        }
+        self.follow_cursor();
+        if from != self.current_buffer_index {
+            self.run_hooks("switch", &[("from", Expr::Int(from as i64)), ("to", Expr::Int(self.current_buffer_index as i64))]);
+        }
+    }
+
+    pub fn view(&self) -> &View {
+        &self.view
+    }
+
+    pub fn resize_view(&mut self, cols: usize, rows: usize) {
+        if (self.view.cols(), self.view.rows()) != (cols, rows) {
+            self.view.resize(cols, rows);
+            self.follow_cursor();
+        }
+    }
+
+    /// Scroll the view so the cursor is visible.
+    pub fn follow_cursor(&mut self) {
+        if let Some(pos) = self.cur_buf().map(Buffer::cur_screen_pos) {
+            self.view.follow(pos);
+        }
+    }
+
+    /// Scroll the view so the cursor is in the middle of the screen.
+    pub fn center_view(&mut self) {
+        if let Some(pos) = self.cur_buf().map(Buffer::cur_screen_pos) {
+            self.view.center(pos);
+        }
+    }
+
+    /// Scroll the view without moving the cursor.
+    pub fn scroll_view(&mut self, dir: Direction, count: usize) {
+        if let Some(line_count) = self.cur_buf().map(|buf| buf.content().len()) {
+            self.view.scroll(dir, count, line_count);
+        }
+    }
+
+    /// Move the cursor and the view together by a screenful.
+    pub fn page(&mut self, dir: Direction) {
+        let rows = self.view.rows();
+        self.scroll_view(dir, rows);
+        self.move_cur_by(dir, rows);
+    }
+
+    /// Click at a position on the view. One click places the cursor,
+    /// two select the word there, and three select the whole line.
+    pub fn click(&mut self, pos: (usize, usize), clicks: usize) {
+        let Some((row, col)) = self.clicked(pos) else {
+            return;
+        };
+        let line = self.cur_buf().unwrap().content()[row].clone();
+        self.unselect();
+        match clicks {
+            0 | 1 => self.goto_cur((row, col)),
+            2 => {
+                let is_word = |ch: char| ch.is_alphanumeric() || ch == '_';
+                let mut col = col.min(line.len());
+                while !line.is_char_boundary(col) {
+                    col -= 1;
+                }
+                let start = line[..col].rfind(|ch| !is_word(ch)).map_or(0, |i| i + 1);
+                let end = line[col..].find(|ch| !is_word(ch)).map_or(line.len(), |i| col + i);
+                self.goto_cur((row, start));
+                self.select();
+                self.goto_cur((row, end));
+            }
+            _ => {
+                self.goto_cur((row, 0));
+                self.select();
+                self.goto_cur((row, line.len()));
+            }
+        }
+    }
+
+    /// Drag the mouse to a position on the view, selecting from where it was pressed.
+    pub fn drag(&mut self, pos: (usize, usize)) {
+        if let Some(pos) = self.clicked(pos) {
+            self.select();
+            self.goto_cur(pos);
+        }
+    }
+
+    /// The buffer position of a position on the view, on a line of the buffer.
+    fn clicked(&self, pos: (usize, usize)) -> Option<(usize, usize)> {
+        let (row, col) = self.view.to_buffer(pos);
+        let lines = self.cur_buf()?.content();
+        let row = row.min(lines.len() - 1);
+        Some((row, byte_col(&lines[row], col)))
     }
 
     fn apply(&mut self, change: Change) {
+        let before = self.cur_buf().map(Buffer::cur_pos);
         if let Some(buf) = self.cur_buf_mut() {
             change.apply(buf)
         }
+        self.follow_cursor();
+        if let Some((row, col)) = self.cur_buf().map(Buffer::cur_pos).filter(|&pos| Some(pos) != before) {
+            self.run_hooks("move", &[("row", Expr::Int(row as i64)), ("col", Expr::Int(col as i64))]);
+        }
     }
 
     fn clear_redo_stack(&mut self) {
@@ -111,13 +671,34 @@ impl Editor {
     }
 
     pub fn insert(&mut self, text: impl ToString) {
-        self.apply(Change::Insert(text.to_string()));
+        let Some((row, col)) = self.cur_buf().filter(|buf| buf.can_insert()).map(Buffer::cur_pos) else {
+            return;
+        };
+        let text = text.to_string();
+        self.apply(Change::Insert(text.clone()));
         self.clear_redo_stack();
+        self.run_hooks("insert", &[("text", string(text)), ("row", Expr::Int(row as i64)), ("col", Expr::Int(col as i64))]);
     }
 
     pub fn delete(&mut self, count: usize) {
+        let Some(count) = self.cur_buf().filter(|buf| !buf.is_read_only()).map(|buf| buf.deletable(count)) else {
+            return;
+        };
+        if count == 0 {
+            return;
+        }
         self.apply(Change::delete(count));
         self.clear_redo_stack();
+        let Some(buf) = self.cur_buf() else {
+            return;
+        };
+        let (row, col) = buf.cur_pos();
+        // The change on the undo stack holds the text that was deleted
+        let text = match buf.undo_stack.last() {
+            Some(Change::Delete(text)) => text.clone(),
+            _ => String::new(),
+        };
+        self.run_hooks("delete", &[("text", string(text)), ("row", Expr::Int(row as i64)), ("col", Expr::Int(col as i64))]);
     }
 
     pub fn move_cur(&mut self, dir: Direction) {
@@ -157,6 +738,10 @@ impl Editor {
         }
     }
 
+    pub fn get_buf_mut(&mut self, id: usize) -> Option<&mut Buffer> {
+        self.buffers.get_mut(id)
+    }
+
     pub fn cur_buf_mut(&mut self) -> Option<&mut Buffer> {
         if self.buffers.is_empty() {
             None
@@ -168,11 +753,167 @@ impl Editor {
 
     pub fn eval(&mut self, expr: Expr) -> Result<Expr, Expr> {
         let mut env = self.env.clone();
-        let result = eval(expr, self, &mut env);
+        let result = run(expr, self, &mut env);
         self.env = env;
         result
     }
 
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// A handle that stops the running script from another thread.
+    pub fn interrupt(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    /// Called as evaluation starts, which may be inside another evaluation,
+    /// like a builtin evaluating its arguments.
+    pub fn start_script(&mut self) {
+        if self.scripts == 0 {
+            self.steps = 0;
+            self.depth = 0;
+            self.stack_start = stack_position();
+            self.interrupt.start();
+            start_memory_limit(self.limits.memory);
+        }
+        self.scripts += 1;
+    }
+
+    pub fn finish_script(&mut self) {
+        self.scripts -= 1;
+        if self.scripts == 0 {
+            self.interrupt.finish();
+            finish_memory_limit();
+        }
+    }
+
+    /// Count a step of evaluation, stopping the script if it's run too long,
+    /// used too much memory, nested too deep for the stack or been
+    /// interrupted. Once it's run too long or been interrupted, every step
+    /// after fails too, so the script can't catch the error and carry on.
+    pub fn step(&mut self) -> Result<(), Expr> {
+        self.steps += 1;
+        if self.steps > self.limits.steps {
+            return self.limits.steps_error();
+        }
+        self.interrupt.check()?;
+        check_stack(self.stack_start)?;
+        if self.steps.is_multiple_of(1024) {
+            check_memory(0)?;
+        }
+        Ok(())
+    }
+
+    /// Count a function call starting, which fails if too many are running.
+    pub fn enter_call(&mut self) -> Result<(), Expr> {
+        if self.depth >= self.limits.depth {
+            return self.limits.depth_error();
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn leave_call(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Evaluate code with another buffer as the current one, then switch
+    /// back, unless the code switched buffers itself.
+    pub fn eval_in(&mut self, id: usize, expr: Expr) -> Result<Expr, Expr> {
+        if id >= self.buffers.len() {
+            return self.eval(expr);
+        }
+        let (home, view, count) = (self.current_buffer_index, self.view, self.buffers.len());
+        self.current_buffer_index = id;
+        self.view.show(id, self.buffers[id].view_start());
+        let result = self.eval(expr);
+        if self.current_buffer_index == id && self.buffers.len() == count {
+            self.current_buffer_index = home;
+            self.view = view;
+        }
+        result
+    }
+
+    /// Switch to the REPL, opening it if it isn't open yet. Code typed
+    /// into it runs against the buffer that was current.
+    pub fn open_repl(&mut self) {
+        let target = self.current_buffer_index;
+        match self.buffers.iter().position(Buffer::is_repl) {
+            Some(id) if id == target => {}
+            Some(id) => {
+                self.buffers[id].repl_mut().unwrap().target = Some(target);
+                self.set_buf(id);
+            }
+            None => {
+                self.add_buf(Buffer::from_repl(Repl::new(target)));
+                self.set_buf(self.max_buf_id());
+            }
+        }
+    }
+
+    /// Make code typed into the REPL run against another buffer.
+    pub fn set_repl_target(&mut self, target: usize) -> Result<(), String> {
+        if target >= self.buffers.len() || self.buffers[target].is_repl() {
+            return Err(format!("There is no buffer #{target} for the REPL to edit"));
+        }
+        let repl = self.buffers.iter_mut().find_map(Buffer::repl_mut).ok_or("The REPL isn't open")?;
+        repl.target = Some(target);
+        Ok(())
+    }
+
+    /// Run the code typed into the current REPL buffer and show what it
+    /// returns after it. Returns false without running anything if the
+    /// code isn't finished yet, so Enter should start another line of it.
+    pub fn run_repl_input(&mut self) -> bool {
+        let Some(buf) = self.cur_buf().filter(|buf| buf.is_repl()) else {
+            return false;
+        };
+        let (input, target) = (buf.repl_input().unwrap(), buf.repl().unwrap().target);
+        if !is_complete(&input) {
+            return false;
+        }
+        let width = self.view.text_cols().saturating_sub(2);
+        let result = if input.trim().is_empty() {
+            Ok(Expr::None)
+        } else {
+            self.cur_buf_mut().unwrap().repl_mut().unwrap().remember(&input);
+            parse_file(&input, "repl").and_then(|expr| match target {
+                Some(target) => self.eval_in(target, expr),
+                None => self.eval(expr),
+            })
+        };
+        let output = match result {
+            Ok(Expr::None) => vec![],
+            Ok(value) => prefix_lines("= ", pretty(&value, width)),
+            Err(error) => prefix_lines("! ", pretty_error(&error, width)),
+        };
+        // The code may have closed the REPL or moved it
+        if let Some(id) = self.buffers.iter().position(Buffer::is_repl) {
+            self.buffers[id].end_repl_input(&output);
+            if id == self.current_buffer_index {
+                self.follow_cursor();
+            }
+        }
+        true
+    }
+
+    /// Replace the code typed into the current REPL buffer with an
+    /// earlier input, or a later one when going forward in the history.
+    pub fn recall_repl_input(&mut self, older: bool) {
+        let Some(buf) = self.cur_buf_mut() else {
+            return;
+        };
+        if let Some(input) = buf.repl_mut().and_then(|repl| repl.recall(older)) {
+            buf.set_repl_input(&input);
+            self.follow_cursor();
+        }
+    }
+
     pub fn next_buf(&mut self) {
         if self.buffers.is_empty() {
             return;
@@ -185,13 +926,23 @@ impl Editor {
             return;
         }
         if self.current_buffer_index > 0 {
-            self.current_buffer_index -= 1;
+            self.set_buf(self.current_buffer_index - 1);
         } else {
-            self.current_buffer_index = self.buffers.len() - 1;
+            self.set_buf(self.buffers.len() - 1);
         }
     }
 }
 
+/// Start the first line with a prefix, and indent the rest to line up with it.
+fn prefix_lines(prefix: &str, lines: Vec<String>) -> Vec<String> {
+    let indent = " ".repeat(prefix.len());
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| format!("{}{}", if i == 0 { prefix } else { &indent }, line))
+        .collect()
+}
+
 impl fmt::Debug for Editor {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if !self.buffers.is_empty() {
@@ -0,0 +1,213 @@
+use super::{Expr, Input, Interrupt};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Something the editor has to respond to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A key press or resize from the frontend.
+    Input(Input),
+    /// A watched file was changed on disk.
+    FileChanged(String),
+    /// A line of output from the background job with this id.
+    JobOutput(usize, String),
+    /// The background job with this id exited, with its exit code if it has one.
+    JobDone(usize, Option<i32>),
+}
+
+/// A script callback that the editor runs later.
+#[derive(Clone, Debug)]
+pub struct Timer {
+    pub id: usize,
+    /// When the callback is due. Idle callbacks aren't due again
+    /// until there's been more activity.
+    pub at: Option<Instant>,
+    /// How long to wait between runs of a repeating timer.
+    pub every: Option<Duration>,
+    /// Whether to run after the editor goes idle instead of at a set time.
+    pub idle: bool,
+    pub callback: Expr,
+}
+
+/// The editor events that scripts can hook into.
+pub const HOOK_EVENTS: &[&str] = &[
+    "open",
+    "before-save",
+    "after-save",
+    "switch",
+    "insert",
+    "delete",
+    "move",
+    "idle",
+    "exit",
+];
+
+/// A script function the editor calls each time one of the `HOOK_EVENTS`
+/// happens, with a dict of the event's details.
+#[derive(Clone, Debug)]
+pub struct Hook {
+    pub id: usize,
+    pub event: String,
+    pub callback: Expr,
+}
+
+/// Collects the events from every source. Sources on other threads,
+/// like the terminal's input reader, send events through `sender`.
+pub struct EventQueue {
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
+    /// Events put aside while waiting for input.
+    deferred: VecDeque<Event>,
+    /// Stops the running script, for input that can't wait for it to finish.
+    interrupt: Interrupt,
+}
+
+impl Default for EventQueue {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            sender,
+            receiver,
+            deferred: VecDeque::new(),
+            interrupt: Interrupt::default(),
+        }
+    }
+}
+
+impl EventQueue {
+    pub fn sender(&self) -> Sender<Event> {
+        self.sender.clone()
+    }
+
+    pub fn interrupt(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    /// Sleep until an event arrives or the deadline passes, then take every
+    /// event that's ready, so they can be handled as one batch. Returns
+    /// nothing if the deadline passed first.
+    pub fn wait(&mut self, deadline: Option<Instant>) -> Vec<Event> {
+        let mut events: Vec<Event> = self.deferred.drain(..).collect();
+        if events.is_empty() {
+            let first = match deadline {
+                Some(deadline) => self
+                    .receiver
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    .ok(),
+                None => self.receiver.recv().ok(),
+            };
+            events.extend(first);
+        }
+        events.extend(self.receiver.try_iter());
+        events
+    }
+
+    /// Sleep until the next input, putting aside any other events until
+    /// the next `wait`. This is for prompts, which only care about keys.
+    pub fn next_input(&mut self) -> Option<Input> {
+        if let Some(i) = self.deferred.iter().position(|e| matches!(e, Event::Input(_))) {
+            if let Some(Event::Input(input)) = self.deferred.remove(i) {
+                return Some(input);
+            }
+        }
+        loop {
+            match self.receiver.recv().ok()? {
+                Event::Input(input) => return Some(input),
+                event => self.deferred.push_back(event),
+            }
+        }
+    }
+}
+
+/// Sends an event when any of a set of files changes on disk.
+pub struct FileWatcher {
+    watcher: Option<RecommendedWatcher>,
+    files: Arc<Mutex<BTreeSet<PathBuf>>>,
+    /// Directories are watched instead of the files in them, so that
+    /// files replaced by renaming over them are still noticed.
+    dirs: BTreeSet<PathBuf>,
+}
+
+impl FileWatcher {
+    /// A watcher that sends `Event::FileChanged` through `sender`. If the
+    /// platform can't watch files, nothing is ever sent.
+    pub fn new(sender: Sender<Event>) -> Self {
+        let files: Arc<Mutex<BTreeSet<PathBuf>>> = Arc::default();
+        let watched = files.clone();
+        let watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+            let Ok(event) = result else {
+                return;
+            };
+            if !(event.kind.is_modify() || event.kind.is_create()) {
+                return;
+            }
+            let watched = watched.lock().unwrap();
+            for path in event.paths.into_iter().filter(|path| watched.contains(path)) {
+                let _ = sender.send(Event::FileChanged(path.to_string_lossy().into_owned()));
+            }
+        })
+        .ok();
+        Self {
+            watcher,
+            files,
+            dirs: BTreeSet::new(),
+        }
+    }
+
+    /// Watch exactly these files, and stop watching any others.
+    pub fn watch(&mut self, files: impl IntoIterator<Item = PathBuf>) {
+        let files: BTreeSet<PathBuf> = files.into_iter().filter_map(|file| file.canonicalize().ok()).collect();
+        let dirs: BTreeSet<PathBuf> = files.iter().filter_map(|file| file.parent().map(PathBuf::from)).collect();
+        if let Some(watcher) = &mut self.watcher {
+            for dir in self.dirs.difference(&dirs) {
+                let _ = watcher.unwatch(dir);
+            }
+            for dir in dirs.difference(&self.dirs) {
+                let _ = watcher.watch(dir, RecursiveMode::NonRecursive);
+            }
+        }
+        self.dirs = dirs;
+        *self.files.lock().unwrap() = files;
+    }
+}
+
+/// Run a shell command in the background, sending each line it prints
+/// and then its exit code as events tagged with `id`.
+pub fn spawn_job(id: usize, command: &str, sender: Sender<Event>) -> Result<(), String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let errors = sender.clone();
+    let stderr = std::thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            let _ = errors.send(Event::JobOutput(id, line));
+        }
+    });
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            let _ = sender.send(Event::JobOutput(id, line));
+        }
+        let _ = stderr.join();
+        let code = child.wait().ok().and_then(|status| status.code());
+        let _ = sender.send(Event::JobDone(id, code));
+    });
+    Ok(())
+}
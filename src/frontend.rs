@@ -1,13 +1,29 @@
-use super::Editor;
+use super::{Editor, Event};
+use std::time::Instant;
 
 pub trait Frontend {
     fn render(&mut self, editor: &Editor, flush: bool) -> Result<(), String>;
-    fn wait_for_input(&mut self, editor: &Editor) -> Result<Input, String>;
+    /// Sleep until there are events to handle or the deadline passes,
+    /// then return every event that's ready.
+    fn wait_for_events(&mut self, deadline: Option<Instant>) -> Result<Vec<Event>, String>;
     fn set_status(&mut self, status: &str) -> Result<(), String>;
 
     fn prompt(&mut self, text: &str, pre_input: Option<String>) -> Result<String, String>;
     fn ask(&mut self, prompt: &str, yes: &str, no: &str) -> Result<bool, String>;
     fn choose(&mut self, prompt: &str, options: Vec<String>) -> Result<String, String>;
+    /// Let the user fuzzy-filter a list of options and pick one.
+    /// Returns the index of the chosen option.
+    fn pick(&mut self, prompt: &str, options: &[String]) -> Result<usize, String> {
+        self.pick_with_preview(prompt, options, &|_| vec![])
+    }
+    /// Like `pick`, but also show the lines returned by `preview`
+    /// for the highlighted option.
+    fn pick_with_preview(
+        &mut self,
+        prompt: &str,
+        options: &[String],
+        preview: &dyn Fn(usize) -> Vec<String>,
+    ) -> Result<usize, String>;
     fn get_num(&mut self, prompt: &str) -> Result<isize, String>;
 
     fn exit(&mut self);
@@ -34,4 +50,35 @@ pub enum Input {
     Control(Box<Self>),
     Shift(Box<Self>),
     Alt(Box<Self>),
+    /// The frontend's dimensions changed.
+    Resize,
+    /// The mouse button was pressed at a row and column of the view.
+    /// `clicks` counts the presses in quick succession, so it's 2 for
+    /// a double click.
+    Click { row: usize, col: usize, clicks: usize },
+    /// The mouse moved to a row and column of the view with the button held.
+    Drag { row: usize, col: usize },
+    ScrollUp,
+    ScrollDown,
+    /// Text pasted into the frontend all at once, with `\n` line endings.
+    Paste(String),
+}
+
+impl Input {
+    /// Whether this is a key press, rather than the mouse, a paste or a resize.
+    pub fn is_key(&self) -> bool {
+        !matches!(
+            self,
+            Input::Resize | Input::Click { .. } | Input::Drag { .. } | Input::ScrollUp | Input::ScrollDown | Input::Paste(_)
+        )
+    }
+
+    /// Shifted characters arrive already in upper case, so for typing
+    /// text the shift can be dropped.
+    pub fn unshifted(self) -> Self {
+        match self {
+            Input::Shift(input) if matches!(*input, Input::Char(_)) => *input,
+            input => input,
+        }
+    }
 }
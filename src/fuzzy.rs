@@ -0,0 +1,86 @@
+/// Score how well `pattern` matches `text` as a case-insensitive subsequence.
+/// Returns `None` if the characters of the pattern don't all appear in order.
+///
+/// Matches that are consecutive, or that start a word or path component,
+/// score higher, so `ex` prefers `lang/expr.rs` over `lang/mod.rs.bak.txt`.
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<i64> {
+    let text: Vec<char> = text.chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last_match: Option<usize> = None;
+
+    for p in pattern.chars().flat_map(char::to_lowercase) {
+        let found = (pos..text.len()).find(|&i| text[i].to_lowercase().eq(Some(p)))?;
+
+        score += 1;
+        if last_match.is_some_and(|last| last + 1 == found) {
+            score += 5;
+        }
+        let starts_word = found == 0
+            || matches!(text[found - 1], '/' | '\\' | '_' | '-' | '.' | ' ')
+            || (text[found].is_uppercase() && text[found - 1].is_lowercase());
+        if starts_word {
+            score += 8;
+        }
+        if let Some(last) = last_match {
+            score -= (found - last - 1).min(5) as i64;
+        }
+
+        last_match = Some(found);
+        pos = found + 1;
+    }
+
+    // Prefer shorter candidates when everything else is equal
+    Some(score * 100 - text.len().min(99) as i64)
+}
+
+/// Rank the candidates that match `pattern`, best first.
+/// Returns indices into `candidates`. An empty pattern keeps every
+/// candidate in its original order.
+pub fn fuzzy_filter<S: AsRef<str>>(pattern: &str, candidates: &[S]) -> Vec<usize> {
+    if pattern.is_empty() {
+        return (0..candidates.len()).collect();
+    }
+
+    let mut scored: Vec<(i64, usize)> = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| fuzzy_score(pattern, c.as_ref()).map(|score| (score, i)))
+        .collect();
+    scored.sort_by(|(a, i), (b, j)| b.cmp(a).then(i.cmp(j)));
+    scored.into_iter().map(|(_, i)| i).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_subsequences() {
+        assert!(fuzzy_score("lx", "lang/expr.rs").is_some());
+        assert!(fuzzy_score("EXPR", "lang/expr.rs").is_some());
+        assert_eq!(fuzzy_score("xe", "lang/expr.rs"), None);
+        assert_eq!(fuzzy_score("z", ""), None);
+        assert!(fuzzy_score("", "anything").is_some());
+    }
+
+    #[test]
+    fn prefers_word_starts_and_runs() {
+        assert!(fuzzy_score("ex", "lang/expr.rs") > fuzzy_score("ex", "lang/mod.rs.bak.txt"));
+        assert!(fuzzy_score("fb", "FooBar") > fuzzy_score("fb", "fabric"));
+        assert!(fuzzy_score("abc", "abcx") > fuzzy_score("abc", "axbxcx"));
+        // Shorter candidates win ties
+        assert!(fuzzy_score("main", "main.rs") > fuzzy_score("main", "main.rs.orig"));
+    }
+
+    #[test]
+    fn filters_best_first() {
+        let candidates = ["src/main.rs", "README.md", "src/lang/mod.rs", "src/editor.rs"];
+        assert_eq!(fuzzy_filter("", &candidates), vec![0, 1, 2, 3]);
+        assert_eq!(fuzzy_filter("mod", &candidates), vec![2]);
+        assert_eq!(fuzzy_filter("rs", &candidates)[..1], [0]);
+        assert_eq!(fuzzy_filter("qq", &candidates), Vec::<usize>::new());
+        // Equal scores keep their original order
+        assert_eq!(fuzzy_filter("a", &["b/a", "c/a"]), vec![0, 1]);
+    }
+}
@@ -0,0 +1,483 @@
+use super::{Expr, Input, VI_INSERT_BINDINGS, VI_NORMAL_BINDINGS, VI_VISUAL_BINDINGS};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+/// The editor's built-in actions that keys can be bound to, with what they do.
+pub const ACTIONS: &[(&str, &str)] = &[
+    ("quit", "Leave the editor"),
+    ("save", "Save the current buffer to disk"),
+    ("close-buffer", "Quit the current buffer, asking whether to save it if it's edited"),
+    ("copy", "Copy the selected text to the clipboard"),
+    ("cut", "Cut the selected text to the clipboard"),
+    ("paste", "Paste the text from the clipboard"),
+    ("delete-selection", "Delete the selection, or the character to the right of the cursor"),
+    ("undo", "Undo the last change"),
+    ("redo", "Redo the last undone change"),
+    ("find", "Find the next instance of text in the buffer"),
+    ("new-buffer", "Create a new, empty buffer"),
+    ("open-file", "Open a file with a fuzzy finder over the project"),
+    ("open-path", "Open a file by typing its path"),
+    ("select-all", "Select all text in the buffer"),
+    ("center", "Scroll the view so the cursor is in the middle of the screen"),
+    ("next-buffer", "Switch to the next buffer"),
+    ("prev-buffer", "Switch to the previous buffer"),
+    ("pick-buffer", "Pick a buffer to switch to from a list"),
+    ("buffer-0", "Switch to buffer #0"),
+    ("buffer-1", "Switch to buffer #1"),
+    ("buffer-2", "Switch to buffer #2"),
+    ("buffer-3", "Switch to buffer #3"),
+    ("buffer-4", "Switch to buffer #4"),
+    ("buffer-5", "Switch to buffer #5"),
+    ("buffer-6", "Switch to buffer #6"),
+    ("buffer-7", "Switch to buffer #7"),
+    ("buffer-8", "Switch to buffer #8"),
+    ("buffer-9", "Switch to buffer #9"),
+    ("toggle-tab-bar", "Show or hide the tab bar"),
+    ("search-project", "Search every file in the project for a regex"),
+    ("replace-project", "Replace the last project search across files"),
+    ("shell-command", "Run a shell command and show its output in a new buffer"),
+    ("eval", "Run a command in the scripting language"),
+    ("command-palette", "Pick a builtin or script function from a list and run it"),
+    ("repl", "Open the scripting REPL, running code against the current buffer"),
+    ("repl-older", "Bring back the REPL's previous input"),
+    ("repl-newer", "Bring back the REPL's next input"),
+    ("enter", "Insert a newline, or open the entry or result under the cursor"),
+    ("tab", "Insert four spaces"),
+    ("backspace", "Delete the selection, or the character to the left of the cursor"),
+    ("delete", "Delete the selection, or the character under the cursor"),
+    ("left", "Move the cursor left"),
+    ("right", "Move the cursor right"),
+    ("up", "Move the cursor up"),
+    ("down", "Move the cursor down"),
+    ("home", "Move the cursor to the start of the line"),
+    ("end", "Move the cursor to the end of the line"),
+    ("page-up", "Move the cursor and the view up a screenful"),
+    ("page-down", "Move the cursor and the view down a screenful"),
+    ("select-left", "Move the cursor left, selecting text"),
+    ("select-right", "Move the cursor right, selecting text"),
+    ("select-up", "Move the cursor up, selecting text"),
+    ("select-down", "Move the cursor down, selecting text"),
+    ("select-home", "Select to the start of the line"),
+    ("select-end", "Select to the end of the line"),
+    ("select-page-up", "Select up a screenful"),
+    ("select-page-down", "Select down a screenful"),
+    ("vi-normal", "Go back to vi's normal mode"),
+    ("vi-insert", "Start inserting text before the cursor"),
+    ("vi-append", "Start inserting text after the cursor"),
+    ("vi-insert-start", "Start inserting text at the start of the line"),
+    ("vi-append-end", "Start inserting text at the end of the line"),
+    ("vi-open-below", "Insert a new line below the cursor"),
+    ("vi-open-above", "Insert a new line above the cursor"),
+    ("vi-visual", "Start selecting text in visual mode"),
+    ("vi-delete", "Delete the text a motion moves over, or the selection"),
+    ("vi-change", "Replace the text a motion moves over, or the selection"),
+    ("vi-yank", "Copy the text a motion moves over, or the selection"),
+    ("vi-delete-char", "Delete the character under the cursor"),
+    ("vi-delete-to-end", "Delete to the end of the line"),
+    ("vi-change-to-end", "Replace the text up to the end of the line"),
+    ("vi-paste-after", "Paste the copied text after the cursor"),
+    ("vi-paste-before", "Paste the copied text before the cursor"),
+    ("vi-repeat", "Repeat the last change"),
+    ("vi-zero", "Move to the start of the line, or add a 0 to the count"),
+    ("vi-count-1", "Add a 1 to the count"),
+    ("vi-count-2", "Add a 2 to the count"),
+    ("vi-count-3", "Add a 3 to the count"),
+    ("vi-count-4", "Add a 4 to the count"),
+    ("vi-count-5", "Add a 5 to the count"),
+    ("vi-count-6", "Add a 6 to the count"),
+    ("vi-count-7", "Add a 7 to the count"),
+    ("vi-count-8", "Add an 8 to the count"),
+    ("vi-count-9", "Add a 9 to the count"),
+    ("vi-left", "Move left"),
+    ("vi-right", "Move right"),
+    ("vi-up", "Move up a line"),
+    ("vi-down", "Move down a line"),
+    ("vi-word", "Move to the start of the next word"),
+    ("vi-back-word", "Move to the start of the previous word"),
+    ("vi-word-end", "Move to the end of the word"),
+    ("vi-line-start", "Move to the first character of the line that isn't a space"),
+    ("vi-line-end", "Move to the end of the line"),
+    ("vi-first-line", "Move to the first line, or the line of the count"),
+    ("vi-last-line", "Move to the last line, or the line of the count"),
+];
+
+/// The keys of the default mode, and the actions they run.
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("Alt-q", "quit"),
+    ("Ctrl-s", "save"),
+    ("Ctrl-q", "close-buffer"),
+    ("Ctrl-c", "copy"),
+    ("Ctrl-x", "cut"),
+    ("Ctrl-v", "paste"),
+    ("Ctrl-d", "delete-selection"),
+    ("Ctrl-z", "undo"),
+    ("Ctrl-y", "redo"),
+    ("Ctrl-f", "find"),
+    ("Ctrl-n", "new-buffer"),
+    ("Ctrl-o", "open-file"),
+    ("Ctrl-a", "select-all"),
+    ("Ctrl-l", "center"),
+    ("Alt-n", "next-buffer"),
+    ("Alt-p", "prev-buffer"),
+    ("Alt-b", "pick-buffer"),
+    ("Alt-0", "buffer-0"),
+    ("Alt-1", "buffer-1"),
+    ("Alt-2", "buffer-2"),
+    ("Alt-3", "buffer-3"),
+    ("Alt-4", "buffer-4"),
+    ("Alt-5", "buffer-5"),
+    ("Alt-6", "buffer-6"),
+    ("Alt-7", "buffer-7"),
+    ("Alt-8", "buffer-8"),
+    ("Alt-9", "buffer-9"),
+    ("Alt-t", "toggle-tab-bar"),
+    ("Alt-o", "open-path"),
+    ("Alt-f", "search-project"),
+    ("Alt-r", "replace-project"),
+    ("Alt-!", "shell-command"),
+    ("Alt-e", "eval"),
+    ("Ctrl-p", "command-palette"),
+    ("Alt-i", "repl"),
+    ("Alt-Up", "repl-older"),
+    ("Alt-Down", "repl-newer"),
+    ("Enter", "enter"),
+    ("Tab", "tab"),
+    ("Backspace", "backspace"),
+    ("Delete", "delete"),
+    ("Left", "left"),
+    ("Right", "right"),
+    ("Up", "up"),
+    ("Down", "down"),
+    ("Home", "home"),
+    ("End", "end"),
+    ("PageUp", "page-up"),
+    ("PageDown", "page-down"),
+    ("Shift-Left", "select-left"),
+    ("Shift-Right", "select-right"),
+    ("Shift-Up", "select-up"),
+    ("Shift-Down", "select-down"),
+    ("Shift-Home", "select-home"),
+    ("Shift-End", "select-end"),
+    ("Shift-PageUp", "select-page-up"),
+    ("Shift-PageDown", "select-page-down"),
+];
+
+/// What a key binding runs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// One of the editor's built-in `ACTIONS`.
+    Action(String),
+    /// A script function, called with no arguments.
+    Script(Expr),
+}
+
+/// The result of pressing a key.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyPress {
+    /// The keys pressed so far are bound to a command.
+    Run(Command),
+    /// The keys pressed so far start a longer binding, so more are needed.
+    Pending(Vec<Input>),
+    /// The keys pressed so far aren't bound to anything.
+    Unbound(Vec<Input>),
+}
+
+/// The mode the editor starts in, with the bindings in `DEFAULT_BINDINGS`.
+pub const DEFAULT_MODE: &str = "default";
+
+/// The tables of key bindings, one for each mode the editor can be in.
+/// A binding is a sequence of keys, so chords like `Ctrl-x Ctrl-s` can
+/// be bound as well as single keys.
+///
+/// Other modes only bind the keys they change. Keys they don't bind that
+/// are held with Ctrl or Alt do what they do in the default mode, and
+/// so does every key in the modes that inherit the default bindings.
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    modes: BTreeMap<String, BTreeMap<Vec<Input>, Command>>,
+    /// The modes that fall back to the default bindings for every key.
+    inheriting: BTreeSet<String>,
+    mode: String,
+    /// The keys of a chord pressed so far.
+    pending: Vec<Input>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Self {
+            modes: BTreeMap::new(),
+            inheriting: BTreeSet::new(),
+            mode: DEFAULT_MODE.to_string(),
+            pending: vec![],
+        };
+        let modes = [
+            (DEFAULT_MODE, DEFAULT_BINDINGS),
+            ("normal", VI_NORMAL_BINDINGS),
+            ("visual", VI_VISUAL_BINDINGS),
+            ("insert", VI_INSERT_BINDINGS),
+        ];
+        for (mode, bindings) in modes {
+            keymap.add_mode(mode, mode == "insert");
+            for (keys, action) in bindings {
+                keymap.bind(mode, parse_keys(keys).unwrap(), Command::Action(action.to_string()));
+            }
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    /// Add a mode with no bindings of its own, unless it already exists.
+    pub fn add_mode(&mut self, mode: &str, inherit: bool) {
+        self.modes.entry(mode.to_string()).or_default();
+        if inherit {
+            self.inheriting.insert(mode.to_string());
+        }
+    }
+
+    /// The name of the mode keys are looked up in.
+    pub fn mode(&self) -> &str {
+        &self.mode
+    }
+
+    /// Switch to another mode, dropping any unfinished chord.
+    pub fn set_mode(&mut self, mode: &str) -> Result<(), String> {
+        if !self.modes.contains_key(mode) {
+            return Err(format!("Unknown mode {}", mode));
+        }
+        self.mode = mode.to_string();
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Bind a sequence of keys in a mode to a command, replacing any
+    /// binding it had.
+    pub fn bind(&mut self, mode: &str, keys: Vec<Input>, command: Command) {
+        self.modes.entry(mode.to_string()).or_default().insert(keys, command);
+    }
+
+    /// Remove the binding of a sequence of keys in a mode. Returns whether it was bound.
+    pub fn unbind(&mut self, mode: &str, keys: &[Input]) -> bool {
+        self.modes.get_mut(mode).is_some_and(|bindings| bindings.remove(keys).is_some())
+    }
+
+    pub fn get(&self, mode: &str, keys: &[Input]) -> Option<&Command> {
+        self.modes.get(mode)?.get(keys)
+    }
+
+    /// Every binding of a mode, in order of their keys.
+    pub fn bindings(&self, mode: &str) -> impl Iterator<Item = (&[Input], &Command)> {
+        self.modes.get(mode).into_iter().flatten().map(|(keys, command)| (keys.as_slice(), command))
+    }
+
+    /// Press a key, following on from the keys of an unfinished chord.
+    /// A sequence that starts a longer binding waits for the rest of it,
+    /// even if it's bound itself. Input that isn't a key, like a click,
+    /// is never bound and leaves the chord waiting.
+    pub fn press(&mut self, input: Input) -> KeyPress {
+        if !input.is_key() {
+            return KeyPress::Unbound(vec![input]);
+        }
+        self.pending.push(input);
+        match self.find(&self.pending, true) {
+            Some(KeyPress::Pending(keys)) => KeyPress::Pending(keys),
+            Some(found) => {
+                self.pending.clear();
+                found
+            }
+            None => KeyPress::Unbound(std::mem::take(&mut self.pending)),
+        }
+    }
+
+    /// What a key does on its own, without waiting for it to start a chord.
+    /// This is how the first key of a chord that wasn't finished is run.
+    pub fn press_alone(&self, input: Input) -> KeyPress {
+        self.find(std::slice::from_ref(&input), false).unwrap_or(KeyPress::Unbound(vec![input]))
+    }
+
+    /// What the keys do in the current mode, falling back to the default
+    /// mode if it inherits from it or they start with Ctrl or Alt.
+    fn find(&self, keys: &[Input], chords: bool) -> Option<KeyPress> {
+        let inherits = self.inheriting.contains(&self.mode) || matches!(keys[0], Input::Control(_) | Input::Alt(_));
+        self.lookup(&self.mode, keys, chords).or_else(|| {
+            if inherits {
+                self.lookup(DEFAULT_MODE, keys, chords)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// What the keys do in a mode, if they're bound or, when looking for
+    /// chords, start a binding.
+    fn lookup(&self, mode: &str, keys: &[Input], chords: bool) -> Option<KeyPress> {
+        let bindings = self.modes.get(mode)?;
+        let longer = bindings
+            .range(keys.to_vec()..)
+            .take_while(|(bound, _)| bound.starts_with(keys))
+            .any(|(bound, _)| bound.len() > keys.len());
+        if chords && longer {
+            return Some(KeyPress::Pending(keys.to_vec()));
+        }
+        bindings.get(keys).map(|command| KeyPress::Run(command.clone()))
+    }
+}
+
+/// Parse a sequence of keys written like `Ctrl-x Ctrl-s`. Each key is a
+/// character or a name like `Enter` or `PageUp`, after any of the
+/// modifiers `Ctrl-`, `Alt-` and `Shift-`.
+pub fn parse_keys(text: &str) -> Result<Vec<Input>, String> {
+    let keys = text.split_whitespace().map(parse_key).collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err("No keys given".to_string());
+    }
+    Ok(keys)
+}
+
+fn parse_key(text: &str) -> Result<Input, String> {
+    let (mut ctrl, mut alt, mut shift) = (false, false, false);
+    let mut rest = text;
+    // A trailing `-` is the key itself, as in `Ctrl--`
+    while let Some((modifier, key)) = rest.split_once('-').filter(|(_, key)| !key.is_empty()) {
+        match modifier.to_lowercase().as_str() {
+            "ctrl" | "c" => ctrl = true,
+            "alt" | "m" => alt = true,
+            "shift" | "s" => shift = true,
+            _ => break,
+        }
+        rest = key;
+    }
+
+    let mut chars = rest.chars();
+    let mut input = match (chars.next(), chars.next()) {
+        (Some(ch), None) => Input::Char(ch),
+        _ => match rest.to_lowercase().as_str() {
+            "esc" | "escape" => Input::Esc,
+            "enter" | "return" => Input::Enter,
+            "tab" => Input::Tab,
+            "space" => Input::Char(' '),
+            "backspace" => Input::Backspace,
+            "delete" | "del" => Input::Delete,
+            "home" => Input::Home,
+            "end" => Input::End,
+            "left" => Input::Left,
+            "right" => Input::Right,
+            "up" => Input::Up,
+            "down" => Input::Down,
+            "pageup" => Input::PageUp,
+            "pagedown" => Input::PageDown,
+            _ => return Err(format!("Unknown key {}", text)),
+        },
+    };
+    // Modifiers nest in the same order the terminal reports them in
+    if shift {
+        input = match input {
+            // Shifted characters arrive in upper case, and without the
+            // shift if nothing else is held
+            Input::Char(ch) if !ctrl && !alt => Input::Char(ch.to_ascii_uppercase()),
+            Input::Char(ch) => Input::Shift(Box::new(Input::Char(ch.to_ascii_uppercase()))),
+            input => Input::Shift(Box::new(input)),
+        };
+    }
+    if ctrl {
+        input = Input::Control(Box::new(input));
+    }
+    if alt {
+        input = Input::Alt(Box::new(input));
+    }
+    Ok(input)
+}
+
+/// Write a sequence of keys the way `parse_keys` reads them.
+pub fn format_keys(keys: &[Input]) -> String {
+    keys.iter().map(ToString::to_string).collect::<Vec<_>>().join(" ")
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Input::Control(input) => write!(f, "Ctrl-{}", input),
+            Input::Alt(input) => write!(f, "Alt-{}", input),
+            Input::Shift(input) => write!(f, "Shift-{}", input),
+            Input::Char(' ') => write!(f, "Space"),
+            Input::Char(ch) => write!(f, "{}", ch),
+            Input::Esc => write!(f, "Esc"),
+            Input::PageUp => write!(f, "PageUp"),
+            Input::PageDown => write!(f, "PageDown"),
+            Input::Paste(_) => write!(f, "Paste"),
+            Input::Click { .. } => write!(f, "Click"),
+            Input::Drag { .. } => write!(f, "Drag"),
+            input => write!(f, "{:?}", input),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(text: &str) -> Vec<Input> {
+        parse_keys(text).unwrap()
+    }
+
+    fn action(name: &str) -> KeyPress {
+        KeyPress::Run(Command::Action(name.to_string()))
+    }
+
+    #[test]
+    fn parses_keys() {
+        assert_eq!(keys("a Space"), vec![Input::Char('a'), Input::Char(' ')]);
+        assert_eq!(keys("Ctrl-x C-s"), vec![Input::Control(Box::new(Input::Char('x'))), Input::Control(Box::new(Input::Char('s')))]);
+        assert_eq!(keys("Shift-a"), vec![Input::Char('A')]);
+        assert_eq!(keys("Alt-Shift-Left"), vec![Input::Alt(Box::new(Input::Shift(Box::new(Input::Left))))]);
+        assert_eq!(keys("Ctrl--"), vec![Input::Control(Box::new(Input::Char('-')))]);
+        assert_eq!(keys("pagedown"), vec![Input::PageDown]);
+        assert!(parse_keys("").is_err());
+        assert!(parse_keys("Ctrl-Nope").is_err());
+        // Keys are written back the way they're read
+        assert_eq!(format_keys(&keys("Ctrl-x Space Esc PageUp")), "Ctrl-x Space Esc PageUp");
+    }
+
+    #[test]
+    fn presses_chords() {
+        let mut keymap = Keymap::default();
+        keymap.bind(DEFAULT_MODE, keys("Ctrl-k Ctrl-d"), Command::Action("delete".to_string()));
+        keymap.bind(DEFAULT_MODE, keys("g"), Command::Action("home".to_string()));
+        keymap.bind(DEFAULT_MODE, keys("g g"), Command::Action("end".to_string()));
+
+        assert_eq!(keymap.press(keys("Ctrl-s")[0].clone()), action("save"));
+        assert_eq!(keymap.press(keys("Ctrl-k")[0].clone()), KeyPress::Pending(keys("Ctrl-k")));
+        // Input that isn't a key passes through without ending the chord
+        assert_eq!(keymap.press(Input::Resize), KeyPress::Unbound(vec![Input::Resize]));
+        assert_eq!(keymap.press(keys("Ctrl-d")[0].clone()), action("delete"));
+
+        // A bound key that starts a longer binding waits for the rest
+        assert_eq!(keymap.press(Input::Char('g')), KeyPress::Pending(keys("g")));
+        assert_eq!(keymap.press(Input::Char('g')), action("end"));
+        assert_eq!(keymap.press(Input::Char('g')), KeyPress::Pending(keys("g")));
+        assert_eq!(keymap.press(Input::Char('x')), KeyPress::Unbound(keys("g x")));
+        // Then the first key can run on its own
+        assert_eq!(keymap.press_alone(Input::Char('g')), action("home"));
+        assert_eq!(keymap.press_alone(Input::Char('x')), KeyPress::Unbound(keys("x")));
+        assert_eq!(keymap.press(Input::Char('x')), KeyPress::Unbound(keys("x")));
+    }
+
+    #[test]
+    fn looks_keys_up_by_mode() {
+        let mut keymap = Keymap::default();
+        keymap.set_mode("normal").unwrap();
+        assert_eq!(keymap.press(Input::Char('x')), action("vi-delete-char"));
+        // Ctrl and Alt keys fall back to the default mode
+        assert_eq!(keymap.press(keys("Ctrl-s")[0].clone()), action("save"));
+        assert!(keymap.set_mode("nope").is_err());
+
+        keymap.add_mode("mine", false);
+        keymap.set_mode("mine").unwrap();
+        assert_eq!(keymap.press(Input::Left), KeyPress::Unbound(vec![Input::Left]));
+        keymap.add_mode("theirs", true);
+        keymap.set_mode("theirs").unwrap();
+        assert_eq!(keymap.press(Input::Left), action("left"));
+    }
+}
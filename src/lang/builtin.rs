@@ -1,4 +1,4 @@
-use super::{Env, Expr};
+use super::{check_arity, Env, Expr};
 use crate::Editor;
 use std::{
     cmp::Ordering,
@@ -9,6 +9,9 @@ use std::{
 #[derive(Clone)]
 pub struct Builtin {
     pub name: String,
+    /// The names of the arguments it takes, like `row` or `[count]` for an
+    /// optional one or `text...` for any number of them.
+    pub params: Vec<String>,
     pub help: String,
     pub help_long: String,
     code: fn(Vec<Expr>, &mut Editor, &mut Env) -> Result<Expr, Expr>,
@@ -17,18 +20,28 @@ pub struct Builtin {
 impl Builtin {
     pub fn new(
         name: impl ToString,
+        params: &[&str],
         help: impl ToString,
         help_long: impl ToString,
         code: fn(Vec<Expr>, &mut Editor, &mut Env) -> Result<Expr, Expr>,
     ) -> Self {
         Self {
             name: name.to_string(),
+            params: params.iter().map(ToString::to_string).collect(),
             help: help.to_string(),
             help_long: help_long.to_string(),
             code,
         }
     }
 
+    /// Raise an `ArityMismatch` unless its params allow `given` arguments.
+    pub fn check_arity(&self, name: &str, given: usize) -> Result<(), Expr> {
+        let required = self.params.iter().filter(|param| !param.starts_with('[') && !param.ends_with("...")).count();
+        let rest = self.params.iter().any(|param| param.ends_with("..."));
+        let optional = self.params.len() - required - rest as usize;
+        check_arity(name, required, optional, rest, given)
+    }
+
     pub fn call(&self, args: Vec<Expr>, buf: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
         (self.code)(args, buf, env)
     }
@@ -56,7 +69,7 @@ impl Ord for Builtin {
 
 impl PartialOrd for Builtin {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.name.partial_cmp(&other.name)
+        Some(self.cmp(other))
     }
 }
 
@@ -0,0 +1,344 @@
+use super::{function_name, BinaryOp, Expr, Location};
+
+/// An instruction for the VM. Constants are indexes into the constants of
+/// the chunk, and targets are indexes of the instruction to jump to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Push a constant.
+    Const(usize),
+    /// Push the value of the symbol constant.
+    Load(usize),
+    /// Push the lambda constant, capturing the scope it's in.
+    Closure(usize),
+    /// Pop a value and bind the pattern constant to it.
+    Bind(usize),
+    Pop,
+    /// Pop that many values into a list.
+    List(usize),
+    /// Pop a value for each key in the list constant into a dict.
+    Dict(usize),
+    Binary(BinaryOp),
+    Neg,
+    Not,
+    /// Pop a value and index it with the key constant.
+    Get(usize),
+    /// Check the first operand of `&`, whose second is the constant. If it's
+    /// `False`, leave it and jump to the target.
+    And(usize, usize),
+    /// Check the second operand of `&` is a bool.
+    AndEnd,
+    /// Check the first operand of `|`, whose second is the constant. If it's
+    /// `True`, leave it and jump to the target.
+    Or(usize, usize),
+    /// Check the second operand of `|` is a bool.
+    OrEnd,
+    Jump(usize),
+    /// Pop a condition, and jump to the target if it's `False`.
+    JumpUnless(usize),
+    /// Go into a scope nested in the current one.
+    EnterScope,
+    /// Go back out to the scope around it.
+    ExitScope,
+    /// Pop a dict, list or string, and push a list of its items and the
+    /// index of the next one.
+    Iter,
+    /// Bind the pattern constant to the next item, or pop the items, push
+    /// `None` and jump to the target when there are none left.
+    Next(usize, usize),
+    /// Bind the pattern constant to the value on top, popping it, or jump to
+    /// the target if it doesn't match.
+    Match(usize, usize),
+    /// Pop a value that matched none of the patterns in the list constant.
+    NoMatch(usize),
+    /// Pop a value and raise it.
+    Raise,
+    /// Until `EndTry`, handle errors by jumping to the target with the error
+    /// on the stack.
+    Try(usize),
+    EndTry,
+    /// Get ready to call the function on top, named by the string constant.
+    /// Builtins take their arguments unevaluated, so they're called with the
+    /// list constant right away, skipping to the target.
+    Prepare {
+        args: usize,
+        name: usize,
+        skip: usize,
+    },
+    /// Pop that many arguments and call the function under them.
+    Call { argc: usize, name: usize },
+    /// Pop a function, then the error to call it with.
+    Catch { name: usize },
+}
+
+/// Compiled code, which leaves the value of the expression on the stack.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub constants: Vec<Expr>,
+    /// Where the code from each instruction on was parsed from, until the next.
+    pub locations: Vec<(usize, Option<Location>)>,
+}
+
+/// Compile an expression to run on the VM.
+pub fn compile(expr: &Expr) -> Chunk {
+    let mut chunk = Chunk::default();
+    chunk.expr(expr);
+    chunk
+}
+
+impl Chunk {
+    /// Where the code of an instruction was parsed from, if it's known.
+    pub fn location(&self, pc: usize) -> Option<&Location> {
+        let after = self.locations.partition_point(|(start, _)| *start <= pc);
+        after.checked_sub(1).and_then(|i| self.locations[i].1.as_ref())
+    }
+
+    /// Mark the code from the next instruction on as parsed from `location`.
+    fn locate(&mut self, location: Option<Location>) {
+        let here = self.code.len();
+        match self.locations.last_mut() {
+            Some((start, last)) if *start == here => *last = location,
+            _ => self.locations.push((here, location)),
+        }
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    fn constant(&mut self, expr: Expr) -> usize {
+        self.constants.push(expr);
+        self.constants.len() - 1
+    }
+
+    /// Point the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let here = self.code.len();
+        match &mut self.code[at] {
+            Op::And(_, target)
+            | Op::Or(_, target)
+            | Op::Jump(target)
+            | Op::JumpUnless(target)
+            | Op::Next(_, target)
+            | Op::Match(_, target)
+            | Op::Try(target)
+            | Op::Prepare { skip: target, .. } => *target = here,
+            op => unreachable!("{op:?} doesn't jump"),
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, a: &Expr, b: &Expr) {
+        self.expr(a);
+        self.expr(b);
+        self.emit(Op::Binary(op));
+    }
+
+    fn call_name(&mut self, f: &Expr) -> usize {
+        self.constant(Expr::String(function_name(f)))
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Quote(e) => {
+                let e = self.constant(*e.clone());
+                self.emit(Op::Const(e));
+            }
+            Expr::Symbol(_) => {
+                let symbol = self.constant(expr.clone());
+                self.emit(Op::Load(symbol));
+            }
+            Expr::Group(e) => self.expr(e),
+            Expr::At(location, e) => {
+                let outer = self.locations.last().and_then(|(_, location)| location.clone());
+                self.locate(Some(location.clone()));
+                self.expr(e);
+                self.locate(outer);
+            }
+            Expr::List(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                self.emit(Op::List(items.len()));
+            }
+            Expr::Dict(items) => {
+                for val in items.values() {
+                    self.expr(val);
+                }
+                let keys = self.constant(Expr::List(items.keys().cloned().collect()));
+                self.emit(Op::Dict(keys));
+            }
+
+            Expr::To(a, b) => self.binary(BinaryOp::To, a, b),
+            Expr::Get(val, key) => {
+                self.expr(val);
+                let key = self.constant(*key.clone());
+                self.emit(Op::Get(key));
+            }
+            Expr::Neg(val) => {
+                self.expr(val);
+                self.emit(Op::Neg);
+            }
+            Expr::Add(a, b) => self.binary(BinaryOp::Add, a, b),
+            Expr::Sub(a, b) => self.binary(BinaryOp::Sub, a, b),
+            Expr::Mul(a, b) => self.binary(BinaryOp::Mul, a, b),
+            Expr::Div(a, b) => self.binary(BinaryOp::Div, a, b),
+            Expr::Rem(a, b) => self.binary(BinaryOp::Rem, a, b),
+            Expr::Pow(a, b) => self.binary(BinaryOp::Pow, a, b),
+            Expr::Eq(a, b) => self.binary(BinaryOp::Eq, a, b),
+            Expr::Ne(a, b) => self.binary(BinaryOp::Ne, a, b),
+            Expr::Lt(a, b) => self.binary(BinaryOp::Lt, a, b),
+            Expr::Le(a, b) => self.binary(BinaryOp::Le, a, b),
+            Expr::Gt(a, b) => self.binary(BinaryOp::Gt, a, b),
+            Expr::Ge(a, b) => self.binary(BinaryOp::Ge, a, b),
+
+            Expr::And(a, b) => {
+                self.expr(a);
+                let operand = self.constant(*b.clone());
+                let check = self.emit(Op::And(operand, 0));
+                self.expr(b);
+                self.emit(Op::AndEnd);
+                self.patch(check);
+            }
+            Expr::Or(a, b) => {
+                self.expr(a);
+                let operand = self.constant(*b.clone());
+                let check = self.emit(Op::Or(operand, 0));
+                self.expr(b);
+                self.emit(Op::OrEnd);
+                self.patch(check);
+            }
+            Expr::Not(val) => {
+                self.expr(val);
+                self.emit(Op::Not);
+            }
+
+            Expr::Assign(pattern, val) => {
+                self.expr(val);
+                let pattern = self.constant(*pattern.clone());
+                self.emit(Op::Bind(pattern));
+                let none = self.constant(Expr::None);
+                self.emit(Op::Const(none));
+            }
+            Expr::Let(pattern, val, ret) => {
+                self.expr(val);
+                self.emit(Op::EnterScope);
+                let pattern = self.constant(*pattern.clone());
+                self.emit(Op::Bind(pattern));
+                self.expr(ret);
+                self.emit(Op::ExitScope);
+            }
+            Expr::If(c, t, e) => {
+                self.expr(c);
+                let to_else = self.emit(Op::JumpUnless(0));
+                self.expr(t);
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_else);
+                self.expr(e);
+                self.patch(to_end);
+            }
+            Expr::Match(val, arms) => {
+                self.expr(val);
+                let mut to_end = vec![];
+                for (pattern, body) in arms {
+                    // The arm's bindings are only in scope in its body
+                    let pattern = self.constant(pattern.clone());
+                    self.emit(Op::EnterScope);
+                    let to_next = self.emit(Op::Match(pattern, 0));
+                    self.expr(body);
+                    self.emit(Op::ExitScope);
+                    to_end.push(self.emit(Op::Jump(0)));
+                    self.patch(to_next);
+                    self.emit(Op::ExitScope);
+                }
+                let patterns = self.constant(Expr::List(arms.iter().map(|(pattern, _)| pattern.clone()).collect()));
+                self.emit(Op::NoMatch(patterns));
+                for jump in to_end {
+                    self.patch(jump);
+                }
+            }
+            Expr::Raise(e) => {
+                self.expr(e);
+                self.emit(Op::Raise);
+            }
+            Expr::Try(t, c) => {
+                let to_catch = self.emit(Op::Try(0));
+                self.expr(t);
+                self.emit(Op::EndTry);
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_catch);
+                self.expr(c);
+                let name = self.call_name(c);
+                self.emit(Op::Catch { name });
+                self.patch(to_end);
+            }
+            Expr::While(cond, body) => {
+                let start = self.code.len();
+                self.expr(cond);
+                let to_end = self.emit(Op::JumpUnless(0));
+                self.expr(body);
+                self.emit(Op::Pop);
+                self.emit(Op::Jump(start));
+                self.patch(to_end);
+                let none = self.constant(Expr::None);
+                self.emit(Op::Const(none));
+            }
+            Expr::For(pattern, vals, body) => {
+                self.expr(vals);
+                self.emit(Op::Iter);
+                let pattern = self.constant(*pattern.clone());
+                let next = self.emit(Op::Next(pattern, 0));
+                self.expr(body);
+                self.emit(Op::Pop);
+                self.emit(Op::Jump(next));
+                self.patch(next);
+            }
+            Expr::Do(exprs) => {
+                if exprs.is_empty() {
+                    let none = self.constant(Expr::None);
+                    self.emit(Op::Const(none));
+                }
+                for (i, expr) in exprs.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(Op::Pop);
+                    }
+                    self.expr(expr);
+                }
+            }
+
+            Expr::Fn(_, _, captured) if captured.is_detached() => {
+                let lambda = self.constant(expr.clone());
+                self.emit(Op::Closure(lambda));
+            }
+            Expr::Apply(f, args) => {
+                self.expr(f);
+                let name = self.call_name(f);
+                let unevaluated = self.constant(Expr::List(args.clone()));
+                let prepare = self.emit(Op::Prepare {
+                    args: unevaluated,
+                    name,
+                    skip: 0,
+                });
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(Op::Call { argc: args.len(), name });
+                self.patch(prepare);
+            }
+
+            Expr::Int(_)
+            | Expr::Float(_)
+            | Expr::Bool(_)
+            | Expr::String(_)
+            | Expr::None
+            | Expr::Builtin(_)
+            | Expr::Fn(..)
+            | Expr::Proc(..)
+            | Expr::Macro(..)
+            | Expr::Rest(_) => {
+                let val = self.constant(expr.clone());
+                self.emit(Op::Const(val));
+            }
+        }
+    }
+}
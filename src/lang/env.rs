@@ -1,32 +1,211 @@
 use super::Expr;
-use std::collections::BTreeMap;
-// use crate::Buffer;
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt,
+    hash::{Hash, Hasher},
+    rc::{Rc, Weak},
+};
 
-#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// The variables in scope: a frame of bindings, linked to the frame of the
+/// scope it's nested in. Cloning an env is cheap, and the clone shares its
+/// frames, so a closure sees later changes to the scope it was defined in.
+///
+/// A closure stored in the scope it captured, like `let f = x -> ...`,
+/// would keep its own frame alive. While it's stored there it holds the
+/// frame weakly, and it's given a strong hold again when it's read out.
+/// Cycles through other frames, like a closure made by a call and stored
+/// in the caller's scope, or through lists and dicts, still keep their
+/// frames alive until the binding is replaced.
+#[derive(Clone, Default)]
 pub struct Env {
-    pub scope: BTreeMap<Expr, Expr>,
+    /// `None` until the env is used, like the env of a lambda before it's
+    /// evaluated and captures the scope it's in.
+    frame: Option<Rc<Frame>>,
+    /// The frame of a closure stored in it, held in place of `frame`.
+    own: Option<Weak<Frame>>,
+}
+
+#[derive(Default)]
+struct Frame {
+    vars: RefCell<BTreeMap<Expr, Expr>>,
+    parent: Option<Env>,
 }
 
 impl Env {
     pub fn new() -> Self {
         Self {
-            scope: BTreeMap::new(),
+            frame: Some(Rc::default()),
+            own: None,
+        }
+    }
+
+    /// A new scope nested in this one. Variables set in it shadow the ones
+    /// outside, which it can still see.
+    pub fn child(&self) -> Self {
+        Self {
+            frame: Some(Rc::new(Frame {
+                vars: RefCell::default(),
+                parent: Some(self.clone()),
+            })),
+            own: None,
         }
     }
 
-    pub fn get(&self, key: &Expr) -> Option<&Expr> {
-        self.scope.get(key)
+    /// Whether this env hasn't been given a frame yet.
+    pub fn is_detached(&self) -> bool {
+        self.frame.is_none() && self.own.is_none()
     }
 
+    pub fn get(&self, key: &Expr) -> Option<Expr> {
+        let mut env = self;
+        loop {
+            let frame = env.frame.as_ref()?;
+            if let Some(value) = frame.vars.borrow().get(key) {
+                return Some(frame.read(value));
+            }
+            env = frame.parent.as_ref()?;
+        }
+    }
+
+    /// Set a variable in the innermost scope.
     pub fn set(&mut self, key: Expr, value: Expr) {
-        self.scope.insert(key, value);
+        let frame = self.frame.get_or_insert_with(Rc::default);
+        let value = match value {
+            Expr::Fn(params, body, captured) if captured.frame.as_ref().is_some_and(|own| Rc::ptr_eq(own, frame)) => {
+                let captured = Env {
+                    frame: None,
+                    own: Some(Rc::downgrade(frame)),
+                };
+                Expr::Fn(params, body, captured)
+            }
+            value => value,
+        };
+        frame.vars.borrow_mut().insert(key, value);
+    }
+
+    pub fn extend(&mut self, bindings: impl IntoIterator<Item = (Expr, Expr)>) {
+        for (key, value) in bindings {
+            self.set(key, value);
+        }
     }
 
     pub fn remove(&mut self, key: &Expr) {
-        self.scope.remove(key);
+        if let Some(frame) = &self.frame {
+            frame.vars.borrow_mut().remove(key);
+        }
     }
 
     pub fn alias(&mut self, key: Expr, value: Expr) {
-        self.scope.insert(key, value);
+        self.set(key, value);
+    }
+
+    /// Every variable in scope, with the ones in inner scopes shadowing the
+    /// ones outside them.
+    pub fn bindings(&self) -> BTreeMap<Expr, Expr> {
+        let mut frames = vec![];
+        let mut env = self;
+        while let Some(frame) = &env.frame {
+            frames.push(frame);
+            match &frame.parent {
+                Some(parent) => env = parent,
+                None => break,
+            }
+        }
+        let mut bindings = BTreeMap::new();
+        for frame in frames.into_iter().rev() {
+            bindings.extend(frame.vars.borrow().iter().map(|(key, value)| (key.clone(), frame.read(value))));
+        }
+        bindings
+    }
+
+    /// Identifies the innermost frame, so envs compare equal when they're
+    /// the same scope rather than when they hold the same variables.
+    fn id(&self) -> usize {
+        match (&self.frame, &self.own) {
+            (Some(frame), _) => Rc::as_ptr(frame) as usize,
+            (None, Some(own)) => Weak::as_ptr(own) as usize,
+            (None, None) => 0,
+        }
+    }
+}
+
+impl Frame {
+    /// A variable's value as it's read out of this frame, with a closure
+    /// stored in the scope it captured holding that scope again.
+    fn read(self: &Rc<Self>, value: &Expr) -> Expr {
+        match value {
+            Expr::Fn(params, body, captured) if captured.own.is_some() => Expr::Fn(
+                params.clone(),
+                body.clone(),
+                Env {
+                    frame: Some(self.clone()),
+                    own: None,
+                },
+            ),
+            value => value.clone(),
+        }
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Debug for Env {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.bindings().keys()).finish()
+    }
+}
+
+impl PartialEq for Env {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl Eq for Env {}
+
+impl Ord for Env {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id().cmp(&other.id())
+    }
+}
+
+impl PartialOrd for Env {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for Env {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol;
+
+    fn closure(env: &Env) -> Expr {
+        Expr::Fn(vec![], Rc::new(symbol("x")), env.clone())
+    }
+
+    #[test]
+    fn closures_dont_keep_their_own_scope_alive() {
+        let mut scope = Env::new().child();
+        let frame = Rc::downgrade(scope.frame.as_ref().unwrap());
+        scope.set(symbol("x"), Expr::Int(1));
+        scope.set(symbol("f"), closure(&scope));
+
+        // Read out, the closure holds its scope again
+        let Some(Expr::Fn(_, _, captured)) = scope.get(&symbol("f")) else {
+            panic!("f should be a closure");
+        };
+        assert_eq!(captured, scope);
+        assert_eq!(scope.bindings()[&symbol("f")], closure(&scope));
+        drop(scope);
+        assert_eq!(captured.get(&symbol("x")), Some(Expr::Int(1)));
+        drop(captured);
+        assert!(frame.upgrade().is_none());
+    }
+}
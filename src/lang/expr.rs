@@ -1,6 +1,6 @@
 use super::*;
 use crate::{Buffer, Direction, Editor};
-use std::{collections::BTreeMap, fmt};
+use std::{cmp::Ordering, collections::BTreeMap, fmt, rc::Rc};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Float(u64);
@@ -45,20 +45,36 @@ pub enum Expr {
     Or(Box<Self>, Box<Self>),
     Not(Box<Self>),
 
+    Eq(Box<Self>, Box<Self>),
+    Ne(Box<Self>, Box<Self>),
+    Lt(Box<Self>, Box<Self>),
+    Le(Box<Self>, Box<Self>),
+    Gt(Box<Self>, Box<Self>),
+    Ge(Box<Self>, Box<Self>),
+
     Do(Vec<Self>),
 
-    Macro(Vec<Self>, Box<Self>),
-    Proc(Vec<Self>, Box<Self>),
-    Fn(Vec<Self>, Box<Self>, Env),
+    // Function bodies are shared, so that passing functions around is cheap
+    Macro(Vec<Self>, Rc<Self>),
+    Proc(Vec<Self>, Rc<Self>),
+    Fn(Vec<Self>, Rc<Self>, Env),
     Apply(Box<Self>, Vec<Self>),
     Let(Box<Self>, Box<Self>, Box<Self>),
     Assign(Box<Self>, Box<Self>),
+    /// A `...name` parameter, which collects the rest of the arguments into a list.
+    Rest(Box<Self>),
 
     If(Box<Self>, Box<Self>, Box<Self>),
     Try(Box<Self>, Box<Self>),
     Raise(Box<Self>),
     For(Box<Self>, Box<Self>, Box<Self>),
     While(Box<Self>, Box<Self>),
+    /// A value and the patterns to try against it in turn, each with the
+    /// expression to evaluate if it matches.
+    Match(Box<Self>, Vec<(Self, Self)>),
+    /// An expression and where it was parsed from, so errors raised while
+    /// evaluating it can say where they are.
+    At(Location, Box<Self>),
 }
 
 impl fmt::Display for Expr {
@@ -102,6 +118,13 @@ impl fmt::Debug for Expr {
             Self::Or(a, b) => write!(f, "{a:?} | {b:?}"),
             Self::Not(e) => write!(f, "!{e:?}"),
 
+            Self::Eq(a, b) => write!(f, "{a:?} == {b:?}"),
+            Self::Ne(a, b) => write!(f, "{a:?} != {b:?}"),
+            Self::Lt(a, b) => write!(f, "{a:?} < {b:?}"),
+            Self::Le(a, b) => write!(f, "{a:?} <= {b:?}"),
+            Self::Gt(a, b) => write!(f, "{a:?} > {b:?}"),
+            Self::Ge(a, b) => write!(f, "{a:?} >= {b:?}"),
+
             Self::Do(exprs) => {
                 write!(f, "{{ ")?;
                 for expr in exprs {
@@ -143,20 +166,199 @@ impl fmt::Debug for Expr {
 
             Self::Let(var, val, ret) => write!(f, "let {var:?} = {val:?} in {ret:?}"),
             Self::Assign(var, val) => write!(f, "{var:?} = {val:?}"),
+            Self::Rest(var) => write!(f, "...{var:?}"),
             Self::For(var, items, body) => write!(f, "for {var:?} in {items:?} do {body:?}"),
             Self::While(cond, body) => write!(f, "while {cond:?} {body:?}"),
+            Self::Match(val, arms) => {
+                write!(f, "match {val:?} with {{ ")?;
+                for (pattern, body) in arms {
+                    write!(f, "{pattern:?} -> {body:?}, ")?
+                }
+                write!(f, "}}")
+            }
             Self::Try(var, val) => write!(f, "try {var:?} catch {val:?}"),
             Self::Raise(e) => write!(f, "raise {e:?}"),
             Self::If(c, t, e) => write!(f, "if {c:?} then {t:?} else {e:?}"),
+            Self::At(_, e) => write!(f, "{e:?}"),
+        }
+    }
+}
+
+impl Expr {
+    /// The same expression without the locations it was parsed with.
+    pub fn strip_locations(self) -> Self {
+        let strip = |e: Box<Self>| Box::new(e.strip_locations());
+        let strip_all = |exprs: Vec<Self>| exprs.into_iter().map(Self::strip_locations).collect();
+        match self {
+            Self::At(_, e) => e.strip_locations(),
+            Self::Quote(e) => Self::Quote(strip(e)),
+            Self::Group(e) => Self::Group(strip(e)),
+            Self::List(items) => Self::List(strip_all(items)),
+            Self::Dict(items) => Self::Dict(items.into_iter().map(|(k, v)| (k.strip_locations(), v.strip_locations())).collect()),
+            Self::To(a, b) => Self::To(strip(a), strip(b)),
+            Self::Get(a, b) => Self::Get(strip(a), strip(b)),
+            Self::Neg(e) => Self::Neg(strip(e)),
+            Self::Add(a, b) => Self::Add(strip(a), strip(b)),
+            Self::Sub(a, b) => Self::Sub(strip(a), strip(b)),
+            Self::Mul(a, b) => Self::Mul(strip(a), strip(b)),
+            Self::Div(a, b) => Self::Div(strip(a), strip(b)),
+            Self::Rem(a, b) => Self::Rem(strip(a), strip(b)),
+            Self::Pow(a, b) => Self::Pow(strip(a), strip(b)),
+            Self::And(a, b) => Self::And(strip(a), strip(b)),
+            Self::Or(a, b) => Self::Or(strip(a), strip(b)),
+            Self::Not(e) => Self::Not(strip(e)),
+            Self::Eq(a, b) => Self::Eq(strip(a), strip(b)),
+            Self::Ne(a, b) => Self::Ne(strip(a), strip(b)),
+            Self::Lt(a, b) => Self::Lt(strip(a), strip(b)),
+            Self::Le(a, b) => Self::Le(strip(a), strip(b)),
+            Self::Gt(a, b) => Self::Gt(strip(a), strip(b)),
+            Self::Ge(a, b) => Self::Ge(strip(a), strip(b)),
+            Self::Do(exprs) => Self::Do(strip_all(exprs)),
+            Self::Macro(params, body) => Self::Macro(strip_all(params), Rc::new((*body).clone().strip_locations())),
+            Self::Proc(params, body) => Self::Proc(strip_all(params), Rc::new((*body).clone().strip_locations())),
+            Self::Fn(params, body, env) => Self::Fn(strip_all(params), Rc::new((*body).clone().strip_locations()), env),
+            Self::Apply(f, args) => Self::Apply(strip(f), strip_all(args)),
+            Self::Let(a, b, c) => Self::Let(strip(a), strip(b), strip(c)),
+            Self::Assign(a, b) => Self::Assign(strip(a), strip(b)),
+            Self::Rest(e) => Self::Rest(strip(e)),
+            Self::If(a, b, c) => Self::If(strip(a), strip(b), strip(c)),
+            Self::Try(a, b) => Self::Try(strip(a), strip(b)),
+            Self::Raise(e) => Self::Raise(strip(e)),
+            Self::For(a, b, c) => Self::For(strip(a), strip(b), strip(c)),
+            Self::While(a, b) => Self::While(strip(a), strip(b)),
+            Self::Match(val, arms) => Self::Match(
+                strip(val),
+                arms.into_iter().map(|(pattern, body)| (pattern.strip_locations(), body.strip_locations())).collect(),
+            ),
+            Self::Symbol(_) | Self::Int(_) | Self::Float(_) | Self::Bool(_) | Self::String(_) | Self::Builtin(_) | Self::None => self,
+        }
+    }
+}
+
+/// Whether a value matches a pattern, adding the symbols it binds to
+/// `bindings` if it does.
+pub fn match_pattern(pattern: &Expr, val: &Expr, bindings: &mut Vec<(Expr, Expr)>) -> bool {
+    match (pattern, val) {
+        (Expr::Symbol(name), _) if name == "_" => true,
+        (Expr::Symbol(_), _) => {
+            bindings.push((pattern.clone(), val.clone()));
+            true
         }
+        (Expr::List(patterns), Expr::List(items)) => {
+            let (patterns, rest) = match patterns.split_last() {
+                Some((Expr::Rest(rest), patterns)) => (patterns, Some(rest)),
+                _ => (patterns.as_slice(), None),
+            };
+            if items.len() < patterns.len() || (rest.is_none() && items.len() > patterns.len()) {
+                return false;
+            }
+            if !patterns.iter().zip(items).all(|(pattern, item)| match_pattern(pattern, item, bindings)) {
+                return false;
+            }
+            match rest {
+                Some(rest) => match_pattern(rest, &Expr::List(items[patterns.len()..].to_vec()), bindings),
+                None => true,
+            }
+        }
+        (Expr::Dict(patterns), Expr::Dict(dict)) => patterns.iter().all(|(key, pattern)| match dict.get(key) {
+            Some(val) => match_pattern(pattern, val, bindings),
+            None => false,
+        }),
+        // Type tests, like `Int n`
+        (Expr::Apply(type_name, args), _) => {
+            let matches_type = match (type_name.as_ref(), val) {
+                (Expr::Symbol(name), Expr::Int(_)) => name == "Int" || name == "Number",
+                (Expr::Symbol(name), Expr::Float(_)) => name == "Float" || name == "Number",
+                (Expr::Symbol(name), Expr::String(_)) => name == "String",
+                (Expr::Symbol(name), Expr::Bool(_)) => name == "Bool",
+                (Expr::Symbol(name), Expr::List(_)) => name == "List",
+                (Expr::Symbol(name), Expr::Dict(_)) => name == "Dict",
+                (Expr::Symbol(name), Expr::Symbol(_)) => name == "Symbol",
+                (Expr::Symbol(name), Expr::Fn(..) | Expr::Proc(..) | Expr::Macro(..) | Expr::Builtin(_)) => name == "Fn",
+                _ => false,
+            };
+            matches_type && args.iter().all(|arg| match_pattern(arg, val, bindings))
+        }
+        (Expr::List(_) | Expr::Dict(_), _) => false,
+        _ => equals(pattern, val),
+    }
+}
+
+/// Bind the symbols in a pattern, as `let` and `for` do, raising a
+/// `PatternMismatch` if the value doesn't match it.
+pub fn bind_pattern(pattern: Expr, val: Expr, env: &mut Env) -> Result<(), Expr> {
+    let mut bindings = vec![];
+    if !match_pattern(&pattern, &val, &mut bindings) {
+        err("PatternMismatch", dict(&[(symbol("pattern"), pattern), (symbol("value"), val)]))?;
     }
+    env.extend(bindings);
+    Ok(())
+}
+
+pub fn eval_args(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Vec<Expr>, Expr> {
+    args.into_iter().map(|arg| eval(arg, editor, env)).collect()
 }
 
-pub fn get_nth_arg(args: &Vec<Expr>, n: usize) -> Result<Expr, Expr> {
-    if let Some(e) = args.iter().nth(n) {
+/// Bind the arguments of a call to a function's parameters in `scope`.
+/// Defaults are evaluated in `scope`, so they can use the parameters before them.
+pub fn bind_params(name: &str, params: Vec<Expr>, args: Vec<Expr>, editor: &mut Editor, scope: &mut Env) -> Result<(), Expr> {
+    // A lone `_` marks a function that takes nothing, but may be given something
+    if let [Expr::Symbol(param)] = params.as_slice() {
+        if param == "_" {
+            check_arity(name, 0, 1, false, args.len())?;
+            return Ok(());
+        }
+    }
+    let required = params.iter().filter(|param| !matches!(param, Expr::Assign(..) | Expr::Rest(_))).count();
+    let rest = params.iter().any(|param| matches!(param, Expr::Rest(_)));
+    let optional = params.len() - required - rest as usize;
+    check_arity(name, required, optional, rest, args.len())?;
+
+    let mut args = args.into_iter();
+    for param in params {
+        match param {
+            Expr::Assign(param, default) => {
+                let val = match args.next() {
+                    Some(arg) => arg,
+                    None => eval(*default, editor, scope)?,
+                };
+                scope.set(*param, val);
+            }
+            Expr::Rest(param) => {
+                scope.set(*param, Expr::List(args.by_ref().collect()));
+            }
+            param => {
+                scope.set(param, args.next().unwrap_or(Expr::None));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Raise an `ArityMismatch` unless a function taking `required` arguments,
+/// then up to `optional` more, or any number more if it has a `rest`
+/// parameter, can be given `given` of them.
+pub fn check_arity(name: &str, required: usize, optional: usize, rest: bool, given: usize) -> Result<(), Expr> {
+    if given >= required && (rest || given <= required + optional) {
+        return Ok(());
+    }
+    let takes = if rest {
+        format!("at least {required}")
+    } else if optional > 0 {
+        format!("{required} to {}", required + optional)
+    } else {
+        required.to_string()
+    };
+    let plural = if optional == 0 && required == 1 { "" } else { "s" };
+    err("ArityMismatch", Expr::String(format!("{name} takes {takes} argument{plural} but was given {given}")))?;
+    Ok(())
+}
+
+pub fn get_nth_arg(args: &[Expr], n: usize) -> Result<Expr, Expr> {
+    if let Some(e) = args.get(n) {
         Ok(e.clone())
     } else {
-        return err("TooFewArgs", Expr::List(args.clone()));
+        err("TooFewArgs", Expr::List(args.to_vec()))
     }
 }
 
@@ -275,7 +477,7 @@ pub fn move_cursor(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Resul
                 } else {
                     Direction::Left
                 };
-                editor.move_cur_by(dir, count.abs() as usize)
+                editor.move_cur_by(dir, count.unsigned_abs() as usize)
             },
             other => return err("TypeMismatch", other),
         }
@@ -283,6 +485,59 @@ pub fn move_cursor(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Resul
     Ok(Expr::None)
 }
 
+pub fn scroll_view(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    if args.len() > 2 {
+        return err("TooManyArgs", Expr::List(args));
+    }
+
+    let dir = match eval(get_nth_arg(&args, 0)?, editor, env)? {
+        Expr::String(dir) | Expr::Symbol(dir) => match dir.as_str() {
+            "left" => Direction::Left,
+            "right" => Direction::Right,
+            "up" => Direction::Up,
+            "down" => Direction::Down,
+            "nowhere" => Direction::Nowhere,
+            _ => return err("InvalidArg", Expr::String(dir)),
+        },
+        Expr::Int(count) => {
+            let dir = if count > 0 {
+                Direction::Down
+            } else {
+                Direction::Up
+            };
+            editor.scroll_view(dir, count.unsigned_abs() as usize);
+            return Ok(Expr::None);
+        }
+        other => return err("TypeMismatch", other),
+    };
+
+    let count = match args.get(1) {
+        Some(arg) => match eval(arg.clone(), editor, env)? {
+            Expr::Int(count) if count >= 0 => count as usize,
+            other => return err("TypeMismatch", other),
+        },
+        None => 1,
+    };
+    editor.scroll_view(dir, count);
+    Ok(Expr::None)
+}
+
+pub fn center_view(_args: Vec<Expr>, editor: &mut Editor, _env: &mut Env) -> Result<Expr, Expr> {
+    editor.center_view();
+    Ok(Expr::None)
+}
+
+pub fn get_view(_args: Vec<Expr>, editor: &mut Editor, _env: &mut Env) -> Result<Expr, Expr> {
+    let view = editor.view();
+    Ok(dict(&[
+        (symbol("buf"), Expr::Int(view.buf_id() as i64)),
+        (symbol("row"), Expr::Int(view.start_row() as i64)),
+        (symbol("col"), Expr::Int(view.start_col() as i64)),
+        (symbol("rows"), Expr::Int(view.rows() as i64)),
+        (symbol("cols"), Expr::Int(view.cols() as i64)),
+    ]))
+}
+
 pub fn goto_cursor(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
     if args.len() > 2 {
         return err("TooManyArgs", Expr::List(args));
@@ -386,22 +641,336 @@ pub fn get_selected_lines(
     // }
 
     Ok(if let Some(selected) = editor.get_selected_lines() {
-        Expr::List(selected.to_owned().into_iter().map(Expr::String).collect())
+        Expr::List(selected.iter().cloned().map(Expr::String).collect())
     } else {
         Expr::None
     })
 }
 
-pub fn eval(mut expr: Expr, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
-    loop {
-        if let Some(e) = env.scope.get(&expr) {
-            return Ok(e.clone());
+/// Order two values: numbers by their value, whether they're ints or
+/// floats, and strings and lists item by item. Other values can't be ordered.
+pub fn compare(a: &Expr, b: &Expr) -> Option<Ordering> {
+    match (a, b) {
+        (Expr::Int(m), Expr::Int(n)) => Some(m.cmp(n)),
+        (Expr::Int(m), Expr::Float(n)) => (*m as f64).partial_cmp(&f64::from(*n)),
+        (Expr::Float(m), Expr::Int(n)) => f64::from(*m).partial_cmp(&(*n as f64)),
+        (Expr::Float(m), Expr::Float(n)) => f64::from(*m).partial_cmp(&f64::from(*n)),
+        (Expr::String(a), Expr::String(b)) => Some(a.cmp(b)),
+        (Expr::Bool(a), Expr::Bool(b)) => Some(a.cmp(b)),
+        (Expr::List(a), Expr::List(b)) => {
+            for (a, b) in a.iter().zip(b) {
+                match compare(a, b)? {
+                    Ordering::Equal => {}
+                    order => return Some(order),
+                }
+            }
+            Some(a.len().cmp(&b.len()))
+        }
+        _ => None,
+    }
+}
+
+/// Whether two values are equal, counting `1` and `1.0` as the same.
+pub fn equals(a: &Expr, b: &Expr) -> bool {
+    match compare(a, b) {
+        Some(order) => order.is_eq(),
+        None => a == b,
+    }
+}
+
+/// Evaluate a comparison of two values that have to be ordered.
+fn ordered(a: Expr, b: Expr, test: fn(Ordering) -> bool, op: fn(Box<Expr>, Box<Expr>) -> Expr) -> Result<Expr, Expr> {
+    match compare(&a, &b) {
+        Some(order) => Ok(Expr::Bool(test(order))),
+        None => err("InvalidCompare", op(Box::new(a), Box::new(b))),
+    }
+}
+
+/// An operator on two values, which are evaluated before it applies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    To,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Apply an operator to two evaluated operands.
+pub fn binary_op(op: BinaryOp, a: Expr, b: Expr) -> Result<Expr, Expr> {
+    Ok(match op {
+        BinaryOp::Add => match (a, b) {
+            (Expr::Int(m), Expr::Int(n)) => int_result(m.checked_add(n), Expr::Add, m, n)?,
+            (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m) + n as f64).into()),
+            (Expr::Int(m), Expr::Float(n)) => Expr::Float((m as f64 + f64::from(n)).into()),
+            (Expr::Float(m), Expr::Float(n)) => {
+                Expr::Float((f64::from(m) + f64::from(n)).into())
+            }
+
+            (Expr::String(mut text1), Expr::String(text2)) => {
+                text1 += &text2;
+                Expr::String(text1)
+            }
+
+            (Expr::String(mut text1), Expr::Int(n)) => {
+                text1 += &n.to_string();
+                Expr::String(text1)
+            }
+
+            (Expr::String(mut text1), Expr::Float(n)) => {
+                text1 += &f64::from(n).to_string();
+                Expr::String(text1)
+            }
+
+            (Expr::List(mut items1), Expr::List(items2)) => {
+                items1.extend(items2);
+                Expr::List(items1)
+            }
+
+            (Expr::Dict(mut items1), Expr::Dict(items2)) => {
+                items1.extend(items2);
+                Expr::Dict(items1)
+            }
+
+            (a, b) => return err("InvalidAdd", Expr::Add(Box::new(a), Box::new(b))),
+        },
+        BinaryOp::Sub => match (a, b) {
+            (Expr::Int(m), Expr::Int(n)) => int_result(m.checked_sub(n), Expr::Sub, m, n)?,
+            (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m) - n as f64).into()),
+            (Expr::Int(m), Expr::Float(n)) => Expr::Float((m as f64 - f64::from(n)).into()),
+            (Expr::Float(m), Expr::Float(n)) => {
+                Expr::Float((f64::from(m) - f64::from(n)).into())
+            }
+
+            (a, b) => return err("InvalidSub", Expr::Sub(Box::new(a), Box::new(b))),
+        },
+        BinaryOp::Mul => match (a, b) {
+            (Expr::Int(m), Expr::Int(n)) => int_result(m.checked_mul(n), Expr::Mul, m, n)?,
+            (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m) * n as f64).into()),
+            (Expr::Int(m), Expr::Float(n)) => Expr::Float((m as f64 * f64::from(n)).into()),
+            (Expr::Float(m), Expr::Float(n)) => {
+                Expr::Float((f64::from(m) * f64::from(n)).into())
+            }
+
+            (text @ (Expr::String(_) | Expr::List(_)), Expr::Int(n)) if n < 0 => {
+                return err("InvalidMul", Expr::Mul(Box::new(text), Box::new(Expr::Int(n))))
+            }
+            (Expr::String(text1), Expr::Int(n)) => {
+                check_memory(text1.len().saturating_mul(n.max(0) as usize))?;
+                Expr::String(text1.repeat(n as usize))
+            }
+
+            (Expr::List(items1), Expr::Int(n)) => {
+                check_memory(items1.len().saturating_mul(n.max(0) as usize).saturating_mul(size_of::<Expr>()))?;
+                let mut result = vec![];
+                for _ in 0..n {
+                    result.extend(items1.clone())
+                }
+                Expr::List(result)
+            }
+
+            (a, b) => return err("InvalidMul", Expr::Mul(Box::new(a), Box::new(b))),
+        },
+        BinaryOp::Div => match (a, b) {
+            (Expr::Int(m), Expr::Int(0)) => return err("DivideByZero", Expr::Div(Box::new(Expr::Int(m)), Box::new(Expr::Int(0)))),
+            (Expr::Int(m), Expr::Int(n)) => int_result(m.checked_div(n), Expr::Div, m, n)?,
+            (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m) / n as f64).into()),
+            (Expr::Int(m), Expr::Float(n)) => Expr::Float((m as f64 / f64::from(n)).into()),
+            (Expr::Float(m), Expr::Float(n)) => {
+                Expr::Float((f64::from(m) / f64::from(n)).into())
+            }
+
+            (a, b) => return err("InvalidDiv", Expr::Div(Box::new(a), Box::new(b))),
+        },
+        BinaryOp::Rem => match (a, b) {
+            (Expr::Int(m), Expr::Int(0)) => return err("DivideByZero", Expr::Rem(Box::new(Expr::Int(m)), Box::new(Expr::Int(0)))),
+            (Expr::Int(m), Expr::Int(n)) => int_result(m.checked_rem(n), Expr::Rem, m, n)?,
+            (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m) % n as f64).into()),
+            (Expr::Int(m), Expr::Float(n)) => Expr::Float((m as f64 % f64::from(n)).into()),
+            (Expr::Float(m), Expr::Float(n)) => {
+                Expr::Float((f64::from(m) % f64::from(n)).into())
+            }
+
+            (a, b) => return err("InvalidRem", Expr::Rem(Box::new(a), Box::new(b))),
+        },
+        BinaryOp::Pow => match (a, b) {
+            // A negative power of a whole number usually isn't one
+            (Expr::Int(m), Expr::Int(n)) if n < 0 => return err("InvalidPow", Expr::Pow(Box::new(Expr::Int(m)), Box::new(Expr::Int(n)))),
+            (Expr::Int(m), Expr::Int(n)) => int_result(u32::try_from(n).ok().and_then(|n| m.checked_pow(n)), Expr::Pow, m, n)?,
+            (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m).powf(n as f64)).into()),
+            (Expr::Int(m), Expr::Float(n)) => {
+                Expr::Float(((m as f64).powf(f64::from(n))).into())
+            }
+            (Expr::Float(m), Expr::Float(n)) => {
+                Expr::Float((f64::from(m).powf(f64::from(n))).into())
+            }
+
+            (a, b) => return err("InvalidPow", Expr::Pow(Box::new(a), Box::new(b))),
+        },
+        BinaryOp::To => match (a, b) {
+            (Expr::Int(start), Expr::Int(end)) => {
+                check_memory((end.saturating_sub(start).max(0) as usize).saturating_mul(size_of::<Expr>()))?;
+                Expr::List((start..end).map(Expr::Int).collect::<Vec<Expr>>())
+            }
+
+            (start, end) => return err("InvalidTo", Expr::To(Box::new(start), Box::new(end))),
+        },
+        BinaryOp::Eq => Expr::Bool(equals(&a, &b)),
+        BinaryOp::Ne => Expr::Bool(!equals(&a, &b)),
+        BinaryOp::Lt => ordered(a, b, Ordering::is_lt, Expr::Lt)?,
+        BinaryOp::Le => ordered(a, b, Ordering::is_le, Expr::Le)?,
+        BinaryOp::Gt => ordered(a, b, Ordering::is_gt, Expr::Gt)?,
+        BinaryOp::Ge => ordered(a, b, Ordering::is_ge, Expr::Ge)?,
+    })
+}
+
+/// The result of arithmetic on two whole numbers, or an error if it
+/// doesn't fit in one.
+fn int_result(result: Option<i64>, op: fn(Box<Expr>, Box<Expr>) -> Expr, m: i64, n: i64) -> Result<Expr, Expr> {
+    match result {
+        Some(result) => Ok(Expr::Int(result)),
+        None => err("Overflow", op(Box::new(Expr::Int(m)), Box::new(Expr::Int(n)))),
+    }
+}
+
+/// Negate a number, or reverse a list or string.
+pub fn negate(val: Expr) -> Result<Expr, Expr> {
+    Ok(match val {
+        Expr::Int(n) => match n.checked_neg() {
+            Some(n) => Expr::Int(n),
+            None => return err("Overflow", Expr::Neg(Box::new(Expr::Int(n)))),
+        },
+        Expr::Float(n) => Expr::Float((-f64::from(n)).into()),
+        Expr::List(mut items) => {
+            items.reverse();
+            Expr::List(items)
+        }
+        Expr::String(text) => Expr::String(text.chars().rev().collect()),
+        x => return err("InvalidNeg", Expr::Neg(Box::new(x))),
+    })
+}
+
+pub fn not(val: Expr) -> Result<Expr, Expr> {
+    Ok(match val {
+        Expr::Bool(b) => Expr::Bool(!b),
+        val => return err("InvalidNot", Expr::Not(Box::new(val))),
+    })
+}
+
+/// Index a dict, list or string. A dict is indexed by the key as it was
+/// written first, so `event@file` gets `file` rather than its value, and
+/// `eval_key` evaluates the key otherwise.
+pub fn get_item(val: Expr, key: Expr, mut eval_key: impl FnMut(Expr) -> Result<Expr, Expr>) -> Result<Expr, Expr> {
+    Ok(match (val, key.strip_locations()) {
+        (Expr::Dict(items), key) => {
+            if let Some(item) = items.get(&key) {
+                item.clone()
+            } else if let Some(item) = items.get(&eval_key(key)?) {
+                item.clone()
+            } else {
+                Expr::None
+            }
+        }
+        (Expr::List(items), key) => {
+            if let Expr::Int(n) = eval_key(key.clone())? {
+                if let Some(item) = items.into_iter().nth(n as usize) {
+                    item
+                } else {
+                    Expr::None
+                }
+            } else {
+                return err(
+                    "InvalidGet",
+                    Expr::Get(Box::new(Expr::List(items)), Box::new(key)),
+                );
+            }
         }
+        (Expr::String(text), key) => {
+            if let Expr::Int(n) = eval_key(key.clone())? {
+                if let Some(ch) = text.chars().nth(n as usize) {
+                    string(ch)
+                } else {
+                    string("")
+                }
+            } else {
+                return err(
+                    "InvalidGet",
+                    Expr::Get(Box::new(Expr::String(text)), Box::new(key)),
+                );
+            }
+        }
+        (val, idx) => return err("InvalidGet", Expr::Get(Box::new(val), Box::new(idx))),
+    })
+}
+
+/// What to call a function in errors, from the expression it's called by.
+pub fn function_name(f: &Expr) -> String {
+    match f {
+        Expr::Symbol(name) => name.clone(),
+        Expr::At(_, f) => function_name(f),
+        _ => String::from("the function"),
+    }
+}
+
+pub fn eval(expr: Expr, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    if env.is_detached() {
+        *env = Env::new();
+    }
+    editor.start_script();
+    // The clone shares the caller's scope until a call replaces it with the
+    // scope of the function, whose body is evaluated without recursing
+    let mut scope = env.clone();
+    let mut place = Place::default();
+    let result = eval_calls(expr, editor, &mut scope, &mut place).map_err(|error| {
+        let error = locate_error(error, place.location.as_ref());
+        match &place.call {
+            Some((name, call_site)) => unwind_error(error, name, call_site.as_ref()),
+            None => error,
+        }
+    });
+    if place.call.is_some() {
+        editor.leave_call();
+    }
+    editor.finish_script();
+    result
+}
+
+/// Where `eval` has got to, for errors raised along the way.
+#[derive(Default)]
+struct Place {
+    /// The innermost expression being evaluated that has a location.
+    location: Option<Location>,
+    /// The function whose body is being evaluated in place of its call, and
+    /// where it was called from.
+    call: Option<(String, Option<Location>)>,
+}
+
+/// Start evaluating the body of a function in place of the call, counting
+/// it as one deeper the first time.
+fn tail_call(editor: &mut Editor, place: &mut Place, name: String) -> Result<(), Expr> {
+    if place.call.is_none() {
+        editor.enter_call()?;
+    }
+    place.call = Some((name, place.location.clone()));
+    Ok(())
+}
+
+fn eval_calls(mut expr: Expr, editor: &mut Editor, env: &mut Env, place: &mut Place) -> Result<Expr, Expr> {
+    loop {
+        editor.step()?;
         return Ok(match expr {
             Expr::Quote(e) => *e,
             Expr::Symbol(_) => {
-                if let Some(e) = env.scope.get(&expr) {
-                    e.clone()
+                if let Some(e) = env.get(&expr) {
+                    e
                 } else {
                     return err("SymbolNotDefined", expr);
                 }
@@ -417,6 +986,11 @@ pub fn eval(mut expr: Expr, editor: &mut Editor, env: &mut Env) -> Result<Expr,
                 expr = *e;
                 continue;
             }
+            Expr::At(location, e) => {
+                place.location = Some(location);
+                expr = *e;
+                continue;
+            }
             Expr::List(items) => Expr::List(
                 items
                     .into_iter()
@@ -430,189 +1004,74 @@ pub fn eval(mut expr: Expr, editor: &mut Editor, env: &mut Env) -> Result<Expr,
                     .collect::<Result<BTreeMap<Expr, Expr>, Expr>>()?,
             ),
 
-            Expr::To(start, end) => match (eval(*start, editor, env)?, eval(*end, editor, env)?) {
-                (Expr::Int(start), Expr::Int(end)) => Expr::List(
-                    (start..end)
-                        .into_iter()
-                        .map(|n| Expr::Int(n as i64))
-                        .collect::<Vec<Expr>>(),
-                ),
-
-                (start, end) => return err("InvalidTo", Expr::To(Box::new(start), Box::new(end))),
-            },
-            Expr::Get(val, idx) => match (eval(*val, editor, env)?, *idx) {
-                (Expr::Dict(items), key) => {
-                    if let Some(item) = items.get(&key) {
-                        item.clone()
-                    } else if let Some(item) = items.get(&eval(key, editor, env)?) {
-                        item.clone()
-                    } else {
-                        Expr::None
-                    }
-                }
-                (Expr::List(items), key) => {
-                    if let Expr::Int(n) = eval(key.clone(), editor, env)? {
-                        if let Some(item) = items.into_iter().nth(n as usize) {
-                            item
-                        } else {
-                            Expr::None
-                        }
-                    } else {
-                        return err(
-                            "InvalidGet",
-                            Expr::Get(Box::new(Expr::List(items)), Box::new(key)),
-                        );
-                    }
-                }
-                (Expr::String(text), key) => {
-                    if let Expr::Int(n) = eval(key.clone(), editor, env)? {
-                        if let Some(ch) = text.chars().nth(n as usize) {
-                            string(ch)
-                        } else {
-                            string("")
-                        }
-                    } else {
-                        return err(
-                            "InvalidGet",
-                            Expr::Get(Box::new(Expr::String(text)), Box::new(key)),
-                        );
-                    }
-                }
-                (val, idx) => return err("InvalidGet", Expr::Get(Box::new(val), Box::new(idx))),
-            },
-
-            Expr::Neg(val) => match eval(*val, editor, env)? {
-                Expr::Int(n) => Expr::Int(-n),
-                Expr::Float(n) => Expr::Float((-f64::from(n)).into()),
-                Expr::List(mut items) => {
-                    items.reverse();
-                    Expr::List(items)
-                }
-                Expr::String(text) => Expr::String(text.chars().rev().collect()),
-                x => return err("InvalidNeg", Expr::Neg(Box::new(x))),
-            },
-            Expr::Add(a, b) => match (eval(*a, editor, env)?, eval(*b, editor, env)?) {
-                (Expr::Int(m), Expr::Int(n)) => Expr::Int(m + n),
-                (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m) + n as f64).into()),
-                (Expr::Int(m), Expr::Float(n)) => Expr::Float((m as f64 + f64::from(n)).into()),
-                (Expr::Float(m), Expr::Float(n)) => {
-                    Expr::Float((f64::from(m) + f64::from(n)).into())
-                }
-
-                (Expr::String(mut text1), Expr::String(text2)) => {
-                    text1 += &text2;
-                    Expr::String(text1)
-                }
-
-                (Expr::String(mut text1), Expr::Int(n)) => {
-                    text1 += &n.to_string();
-                    Expr::String(text1)
-                }
-
-                (Expr::String(mut text1), Expr::Float(n)) => {
-                    text1 += &f64::from(n).to_string();
-                    Expr::String(text1)
-                }
-
-                (Expr::List(mut items1), Expr::List(items2)) => {
-                    items1.extend(items2.into_iter());
-                    Expr::List(items1)
-                }
-
-                (Expr::Dict(mut items1), Expr::Dict(items2)) => {
-                    items1.extend(items2.into_iter());
-                    Expr::Dict(items1)
-                }
-
-                (a, b) => return err("InvalidAdd", Expr::Add(Box::new(a), Box::new(b))),
+            Expr::To(start, end) => binary_op(BinaryOp::To, eval(*start, editor, env)?, eval(*end, editor, env)?)?,
+            Expr::Get(val, idx) => get_item(eval(*val, editor, env)?, *idx, |key| eval(key, editor, env))?,
+
+            Expr::Neg(val) => negate(eval(*val, editor, env)?)?,
+            Expr::Add(a, b) => binary_op(BinaryOp::Add, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
+            Expr::Sub(a, b) => binary_op(BinaryOp::Sub, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
+            Expr::Mul(a, b) => binary_op(BinaryOp::Mul, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
+            Expr::Div(a, b) => binary_op(BinaryOp::Div, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
+            Expr::Rem(a, b) => binary_op(BinaryOp::Rem, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
+            Expr::Pow(a, b) => binary_op(BinaryOp::Pow, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
+
+            // The second operand is only evaluated if it decides the result
+            Expr::And(a, b) => match eval(*a, editor, env)? {
+                Expr::Bool(false) => Expr::Bool(false),
+                Expr::Bool(true) => match eval(*b, editor, env)? {
+                    Expr::Bool(b) => Expr::Bool(b),
+                    b => return err("InvalidAnd", Expr::And(Box::new(Expr::Bool(true)), Box::new(b))),
+                },
+                a => return err("InvalidAnd", Expr::And(Box::new(a), b)),
             },
-            Expr::Sub(a, b) => match (eval(*a, editor, env)?, eval(*b, editor, env)?) {
-                (Expr::Int(m), Expr::Int(n)) => Expr::Int(m - n),
-                (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m) - n as f64).into()),
-                (Expr::Int(m), Expr::Float(n)) => Expr::Float((m as f64 - f64::from(n)).into()),
-                (Expr::Float(m), Expr::Float(n)) => {
-                    Expr::Float((f64::from(m) - f64::from(n)).into())
-                }
-
-                (a, b) => return err("InvalidSub", Expr::Sub(Box::new(a), Box::new(b))),
-            },
-            Expr::Mul(a, b) => match (eval(*a, editor, env)?, eval(*b, editor, env)?) {
-                (Expr::Int(m), Expr::Int(n)) => Expr::Int(m * n),
-                (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m) * n as f64).into()),
-                (Expr::Int(m), Expr::Float(n)) => Expr::Float((m as f64 * f64::from(n)).into()),
-                (Expr::Float(m), Expr::Float(n)) => {
-                    Expr::Float((f64::from(m) * f64::from(n)).into())
-                }
-
-                (Expr::String(text1), Expr::Int(n)) => Expr::String(text1.repeat(n as usize)),
-
-                (Expr::List(items1), Expr::Int(n)) => {
-                    let mut result = vec![];
-                    for _ in 0..n {
-                        result.extend(items1.clone().into_iter())
-                    }
-                    Expr::List(result)
-                }
-
-                (a, b) => return err("InvalidMul", Expr::Mul(Box::new(a), Box::new(b))),
+            Expr::Or(a, b) => match eval(*a, editor, env)? {
+                Expr::Bool(true) => Expr::Bool(true),
+                Expr::Bool(false) => match eval(*b, editor, env)? {
+                    Expr::Bool(b) => Expr::Bool(b),
+                    b => return err("InvalidOr", Expr::Or(Box::new(Expr::Bool(false)), Box::new(b))),
+                },
+                a => return err("InvalidOr", Expr::Or(Box::new(a), b)),
             },
-            Expr::Div(a, b) => match (eval(*a, editor, env)?, eval(*b, editor, env)?) {
-                (Expr::Int(m), Expr::Int(n)) => Expr::Int(m / n),
-                (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m) / n as f64).into()),
-                (Expr::Int(m), Expr::Float(n)) => Expr::Float((m as f64 / f64::from(n)).into()),
-                (Expr::Float(m), Expr::Float(n)) => {
-                    Expr::Float((f64::from(m) / f64::from(n)).into())
-                }
+            Expr::Not(val) => not(eval(*val, editor, env)?)?,
 
-                (a, b) => return err("InvalidDiv", Expr::Div(Box::new(a), Box::new(b))),
-            },
-            Expr::Rem(a, b) => match (eval(*a, editor, env)?, eval(*b, editor, env)?) {
-                (Expr::Int(m), Expr::Int(n)) => Expr::Int(m % n),
-                (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m) % n as f64).into()),
-                (Expr::Int(m), Expr::Float(n)) => Expr::Float((m as f64 % f64::from(n)).into()),
-                (Expr::Float(m), Expr::Float(n)) => {
-                    Expr::Float((f64::from(m) % f64::from(n)).into())
-                }
-
-                (a, b) => return err("InvalidRem", Expr::Rem(Box::new(a), Box::new(b))),
-            },
-            Expr::Pow(base, power) => match (eval(*base, editor, env)?, eval(*power, editor, env)?)
-            {
-                (Expr::Int(m), Expr::Int(n)) => Expr::Int(m.pow(n as u32)),
-                (Expr::Float(m), Expr::Int(n)) => Expr::Float((f64::from(m).powf(n as f64)).into()),
-                (Expr::Int(m), Expr::Float(n)) => {
-                    Expr::Float(((m as f64).powf(f64::from(n))).into())
-                }
-                (Expr::Float(m), Expr::Float(n)) => {
-                    Expr::Float((f64::from(m).powf(f64::from(n))).into())
-                }
-
-                (a, b) => return err("InvalidPow", Expr::Pow(Box::new(a), Box::new(b))),
-            },
-
-            Expr::And(a, b) => match (eval(*a, editor, env)?, eval(*b, editor, env)?) {
-                (Expr::Bool(a), Expr::Bool(b)) => Expr::Bool(a && b),
-                (a, b) => return err("InvalidAnd", Expr::And(Box::new(a), Box::new(b))),
-            },
-            Expr::Or(a, b) => match (eval(*a, editor, env)?, eval(*b, editor, env)?) {
-                (Expr::Bool(a), Expr::Bool(b)) => Expr::Bool(a || b),
-                (a, b) => return err("InvalidOr", Expr::Or(Box::new(a), Box::new(b))),
-            },
-            Expr::Not(val) => match eval(*val, editor, env)? {
-                Expr::Bool(b) => Expr::Bool(!b),
-                val => return err("InvalidNot", Expr::Not(Box::new(val))),
-            },
+            Expr::Eq(a, b) => binary_op(BinaryOp::Eq, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
+            Expr::Ne(a, b) => binary_op(BinaryOp::Ne, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
+            Expr::Lt(a, b) => binary_op(BinaryOp::Lt, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
+            Expr::Le(a, b) => binary_op(BinaryOp::Le, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
+            Expr::Gt(a, b) => binary_op(BinaryOp::Gt, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
+            Expr::Ge(a, b) => binary_op(BinaryOp::Ge, eval(*a, editor, env)?, eval(*b, editor, env)?)?,
 
             Expr::Assign(var, val) => {
                 let val = eval(*val, editor, env)?;
-                env.scope.insert(*var, val);
+                bind_pattern(*var, val, env)?;
                 Expr::None
             }
             Expr::Let(var, val, ret) => {
-                let mut new_env = env.clone();
-                new_env.scope.insert(*var, eval(*val, editor, env)?);
+                let mut new_env = env.child();
+                bind_pattern(*var, eval(*val, editor, env)?, &mut new_env)?;
                 eval(*ret, editor, &mut new_env)?
             }
+            Expr::Match(val, arms) => {
+                let val = eval(*val, editor, env)?;
+                let mut matched = None;
+                for (pattern, body) in &arms {
+                    let mut bindings = vec![];
+                    if match_pattern(pattern, &val, &mut bindings) {
+                        matched = Some((bindings, body.clone()));
+                        break;
+                    }
+                }
+                let Some((bindings, body)) = matched else {
+                    let patterns = arms.into_iter().map(|(pattern, _)| pattern).collect();
+                    return err("PatternMismatch", dict(&[(symbol("patterns"), Expr::List(patterns)), (symbol("value"), val)]));
+                };
+                // The arm's bindings are only in scope in its body
+                let mut scope = env.child();
+                scope.extend(bindings);
+                *env = scope;
+                expr = body;
+                continue;
+            }
             Expr::If(c, t, e) => {
                 expr = match eval(*c, editor, env)? {
                     Expr::Bool(true) => *t,
@@ -625,7 +1084,8 @@ pub fn eval(mut expr: Expr, editor: &mut Editor, env: &mut Env) -> Result<Expr,
             Expr::Try(t, c) => match eval(*t, editor, env) {
                 Ok(result) => result,
                 Err(e) => {
-                    expr = Expr::Apply(c, vec![e]);
+                    // The error is already a value, so it's passed as it is
+                    expr = Expr::Apply(c, vec![Expr::Quote(Box::new(e))]);
                     continue;
                 }
             },
@@ -644,21 +1104,21 @@ pub fn eval(mut expr: Expr, editor: &mut Editor, env: &mut Env) -> Result<Expr,
             Expr::For(var, vals, body) => match eval(*vals, editor, env)? {
                 Expr::Dict(dict) => {
                     for (key, val) in dict.into_iter() {
-                        env.scope.insert(*var.clone(), Expr::List(vec![key, val]));
+                        bind_pattern(*var.clone(), Expr::List(vec![key, val]), env)?;
                         eval(*body.clone(), editor, env)?;
                     }
                     Expr::None
                 }
                 Expr::List(list) => {
                     for val in list {
-                        env.scope.insert(*var.clone(), val);
+                        bind_pattern(*var.clone(), val, env)?;
                         eval(*body.clone(), editor, env)?;
                     }
                     Expr::None
                 }
                 Expr::String(s) => {
                     for val in s.chars() {
-                        env.scope.insert(*var.clone(), string(val));
+                        bind_pattern(*var.clone(), string(val), env)?;
                         eval(*body.clone(), editor, env)?;
                     }
                     Expr::None
@@ -666,47 +1126,59 @@ pub fn eval(mut expr: Expr, editor: &mut Editor, env: &mut Env) -> Result<Expr,
                 iter => return err("InvalidIter", iter),
             },
 
-            Expr::Do(exprs) => {
-                let exprs_len = exprs.len();
-                for (i, expr) in exprs.into_iter().enumerate() {
-                    let expr = eval(expr, editor, env)?;
-                    if i == exprs_len - 1 {
-                        return Ok(expr);
-                    }
+            Expr::Do(mut exprs) => {
+                let Some(last) = exprs.pop() else {
+                    return Ok(Expr::None);
+                };
+                for expr in exprs {
+                    eval(expr, editor, env)?;
                 }
-                Expr::None
+                expr = last;
+                continue;
             }
 
-            Expr::Fn(args, ret, mut captured) => {
-                captured.scope.extend(env.scope.clone().into_iter());
-                Expr::Fn(args, ret, captured)
-            }
+            // A lambda captures the scope it's evaluated in, and a closure
+            // that already has evaluates to itself
+            Expr::Fn(args, ret, captured) if captured.is_detached() => Expr::Fn(args, ret, env.clone()),
+            Expr::Fn(args, ret, captured) => Expr::Fn(args, ret, captured),
             Expr::Macro(args, ret) => Expr::Macro(args, ret),
             Expr::Proc(args, ret) => Expr::Proc(args, ret),
-            Expr::Apply(f, args) => match eval(*f, editor, env)? {
-                Expr::Fn(params, ret, mut captured) => {
-                    for (param, arg) in params.into_iter().zip(args.into_iter()) {
-                        captured.scope.insert(param, eval(arg, editor, env)?);
+            Expr::Rest(var) => Expr::Rest(var),
+            Expr::Apply(f, args) => {
+                let name = function_name(&f);
+                match eval(*f, editor, env)? {
+                    Expr::Fn(params, ret, captured) => {
+                        let args = eval_args(args, editor, env)?;
+                        let mut scope = captured.child();
+                        bind_params(&name, params, args, editor, &mut scope)?;
+                        tail_call(editor, place, name)?;
+                        *env = scope;
+                        expr = (*ret).clone();
+                        continue;
                     }
-                    eval(*ret, editor, &mut captured)?
-                }
-                Expr::Proc(params, ret) => {
-                    let mut new_env = Env::default();
-                    for (param, arg) in params.into_iter().zip(args.into_iter()) {
-                        new_env.scope.insert(param, eval(arg, editor, env)?);
+                    Expr::Proc(params, ret) => {
+                        let args = eval_args(args, editor, env)?;
+                        let mut new_env = Env::new();
+                        bind_params(&name, params, args, editor, &mut new_env)?;
+                        tail_call(editor, place, name)?;
+                        *env = new_env;
+                        expr = (*ret).clone();
+                        continue;
                     }
-                    eval(*ret, editor, &mut new_env)?
-                }
-                Expr::Macro(params, ret) => {
-                    for (param, arg) in params.into_iter().zip(args.into_iter()) {
-                        let val = eval(arg, editor, env)?;
-                        env.scope.insert(param, val);
+                    Expr::Macro(params, ret) => {
+                        let args = eval_args(args, editor, env)?;
+                        bind_params(&name, params, args, editor, env)?;
+                        tail_call(editor, place, name)?;
+                        expr = (*ret).clone();
+                        continue;
                     }
-                    eval(*ret, editor, env)?
+                    Expr::Builtin(builtin) => {
+                        builtin.check_arity(&name, args.len())?;
+                        builtin.call(args, editor, env)?
+                    }
+                    f => return err("InvalidFn", f),
                 }
-                Expr::Builtin(builtin) => builtin.call(args, editor, env)?,
-                f => return err("InvalidFn", f),
-            },
+            }
         });
     }
 }
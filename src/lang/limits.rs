@@ -0,0 +1,174 @@
+use super::{err, Expr};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// How much a script can do before it's stopped, so that a runaway loop or
+/// recursion can't freeze the editor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Steps of evaluation, each an expression evaluated or an instruction run.
+    pub steps: u64,
+    /// How many function calls can be running inside each other.
+    pub depth: usize,
+    /// Bytes the script can allocate on top of what was in use when it started.
+    pub memory: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            steps: 100_000_000,
+            depth: 1_000,
+            memory: 1 << 30,
+        }
+    }
+}
+
+impl Limits {
+    pub fn steps_error(&self) -> Result<(), Expr> {
+        err("StepLimit", Expr::String(format!("The script ran for more than {} steps", self.steps)))?;
+        Ok(())
+    }
+
+    pub fn depth_error(&self) -> Result<(), Expr> {
+        err("DepthLimit", Expr::String(format!("Functions were called more than {} deep", self.depth)))?;
+        Ok(())
+    }
+}
+
+/// The size of stack scripts should run on. Evaluation recurses into
+/// nested expressions and the arguments of builtins, so even calls within
+/// the depth limit can go deeper than a thread's usual stack, especially
+/// in debug builds.
+pub const SCRIPT_STACK: usize = 256 << 20;
+
+/// How much of `SCRIPT_STACK` a script can use, leaving room for the frames
+/// between one step of evaluation and the next.
+const STACK_LIMIT: usize = SCRIPT_STACK / 4 * 3;
+
+/// Run `f` on a thread with a stack of `SCRIPT_STACK` bytes, and wait for it.
+pub fn with_script_stack<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    let thread = std::thread::Builder::new().stack_size(SCRIPT_STACK).spawn(f).expect("Couldn't start a thread");
+    thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+/// Where the stack has got to. It grows down, so deeper calls are lower.
+pub fn stack_position() -> usize {
+    let here = 0u8;
+    std::hint::black_box(&here) as *const u8 as usize
+}
+
+/// Raise a `DepthLimit` if evaluation has used too much of the stack since
+/// it was at `start`.
+pub fn check_stack(start: usize) -> Result<(), Expr> {
+    if start.saturating_sub(stack_position()) > STACK_LIMIT {
+        err("DepthLimit", Expr::String(String::from("Expressions were nested too deep for the stack")))?;
+    }
+    Ok(())
+}
+
+/// Lets another thread stop the script that's running, like the terminal's
+/// input reader does when Ctrl-C is pressed.
+#[derive(Clone, Debug, Default)]
+pub struct Interrupt {
+    running: Arc<AtomicBool>,
+    requested: Arc<AtomicBool>,
+}
+
+impl Interrupt {
+    /// Ask the running script to stop. Returns whether there was one.
+    pub fn request(&self) -> bool {
+        let running = self.running.load(Ordering::Relaxed);
+        if running {
+            self.requested.store(true, Ordering::Relaxed);
+        }
+        running
+    }
+
+    pub fn start(&self) {
+        self.requested.store(false, Ordering::Relaxed);
+        self.running.store(true, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+
+    /// Raise `Interrupted` if the script has been asked to stop. It stays
+    /// asked until it finishes, so it can't catch the error and carry on.
+    pub fn check(&self) -> Result<(), Expr> {
+        if self.requested.load(Ordering::Relaxed) {
+            err("Interrupted", Expr::String(String::from("The script was stopped with Ctrl-C")))?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    /// The bytes allocated by `CountingAllocator` on this thread, less the
+    /// ones freed on it. Each thread counts its own, so a script is only
+    /// held to what it allocates, and not to what other threads do.
+    static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+
+    /// How high `ALLOCATED` can go before the script running on this thread
+    /// is stopped, and its memory limit for the error.
+    static MEMORY_CAP: Cell<(isize, usize)> = const { Cell::new((isize::MAX, usize::MAX)) };
+}
+
+/// Add to this thread's count of allocated bytes. The count is left alone
+/// while the thread is being torn down.
+fn count_allocated(bytes: isize) {
+    let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get().wrapping_add(bytes)));
+}
+
+fn allocated() -> isize {
+    ALLOCATED.try_with(Cell::get).unwrap_or(0)
+}
+
+/// An allocator that counts the bytes in use, so that scripts can be held
+/// to their memory limit. The editor installs it as the global allocator.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocated(layout.size() as isize);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        count_allocated(-(layout.size() as isize));
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocated(new_size as isize - layout.size() as isize);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// Let a script allocate `limit` more bytes than are in use now.
+pub fn start_memory_limit(limit: usize) {
+    let limit_bytes = isize::try_from(limit).unwrap_or(isize::MAX);
+    MEMORY_CAP.set((allocated().saturating_add(limit_bytes), limit));
+}
+
+pub fn finish_memory_limit() {
+    MEMORY_CAP.set((isize::MAX, usize::MAX));
+}
+
+/// Raise a `MemoryLimit` if the running script can't allocate `bytes` more.
+/// Operators that build big values check this first, and evaluation checks
+/// it now and then with nothing more to allocate.
+pub fn check_memory(bytes: usize) -> Result<(), Expr> {
+    let (cap, limit) = MEMORY_CAP.get();
+    if allocated().saturating_add(isize::try_from(bytes).unwrap_or(isize::MAX)) > cap {
+        err("MemoryLimit", Expr::String(format!("The script used more than {limit} bytes of memory")))?;
+    }
+    Ok(())
+}
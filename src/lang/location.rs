@@ -0,0 +1,129 @@
+use super::{string, symbol, Expr};
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+/// Code that was parsed, and the file or other place it came from.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Source {
+    pub name: String,
+    pub code: String,
+}
+
+/// Where an expression starts in the code it was parsed from. Locations don't
+/// take part in comparing expressions, so code is equal wherever it's from.
+#[derive(Clone)]
+pub struct Location {
+    source: Rc<Source>,
+    start: usize,
+}
+
+impl Location {
+    pub fn new(source: Rc<Source>, start: usize) -> Self {
+        Self { source, start }
+    }
+
+    /// The line and column of the start, counting from 1.
+    pub fn line_col(&self) -> (usize, usize) {
+        let before = &self.source.code[..self.start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (line, before[line_start..].chars().count() + 1)
+    }
+
+    /// The text of the line the location is on.
+    pub fn line_text(&self) -> &str {
+        let code = &self.source.code;
+        let line_start = code[..self.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = code[self.start..].find('\n').map_or(code.len(), |i| self.start + i);
+        &code[line_start..line_end]
+    }
+}
+
+/// Shown as `file:line:col`.
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (line, col) = self.line_col();
+        write!(f, "{}:{line}:{col}", self.source.name)
+    }
+}
+
+impl fmt::Debug for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl PartialEq for Location {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Location {}
+
+impl Ord for Location {
+    fn cmp(&self, _: &Self) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Location {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for Location {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+/// Say where an error was raised: its `at` location and the `source` line
+/// it's on. An error that already says where it was raised is left alone,
+/// as are values raised that aren't errors from `err`.
+pub fn locate_error(error: Expr, location: Option<&Location>) -> Expr {
+    match (error, location) {
+        (Expr::Dict(mut dict), Some(location)) if dict.contains_key(&symbol("kind")) && !dict.contains_key(&symbol("at")) => {
+            dict.insert(symbol("at"), string(location));
+            dict.insert(symbol("source"), string(location.line_text()));
+            Expr::Dict(dict)
+        }
+        (error, _) => error,
+    }
+}
+
+/// Add a function the error passed out of to its `stack`, innermost first,
+/// with where it was called from. The same call repeated, as in recursion,
+/// is counted rather than listed again, like `f called at input:1:40 (×1000)`.
+pub fn unwind_error(error: Expr, name: &str, call_site: Option<&Location>) -> Expr {
+    match error {
+        Expr::Dict(mut dict) if dict.contains_key(&symbol("kind")) => {
+            let frame = match call_site {
+                Some(location) => format!("{name} called at {location}"),
+                None => name.to_string(),
+            };
+            if let Expr::List(stack) = dict.entry(symbol("stack")).or_insert_with(|| Expr::List(vec![])) {
+                match stack.last().and_then(|last| repeats(last, &frame)) {
+                    Some(count) => *stack.last_mut().unwrap() = string(format!("{frame} (×{})", count + 1)),
+                    None => stack.push(string(frame)),
+                }
+            }
+            Expr::Dict(dict)
+        }
+        error => error,
+    }
+}
+
+/// How many times a line of a stack repeats a frame, if it's that frame.
+fn repeats(line: &Expr, frame: &str) -> Option<usize> {
+    let Expr::String(line) = line else {
+        return None;
+    };
+    match line.strip_prefix(frame)? {
+        "" => Some(1),
+        count => count.strip_prefix(" (×")?.strip_suffix(')')?.parse().ok(),
+    }
+}
@@ -8,6 +8,16 @@ mod builtin;
 pub use builtin::*;
 mod parse;
 pub use parse::*;
+mod compile;
+pub use compile::*;
+mod vm;
+pub use vm::*;
+mod limits;
+pub use limits::*;
+mod location;
+pub use location::*;
+mod strings;
+pub use strings::*;
 
 pub fn symbol(s: impl ToString) -> Expr {
     Expr::Symbol(s.to_string())
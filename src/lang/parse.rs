@@ -1,32 +1,124 @@
-use super::{err, Env, Expr};
-use pest::iterators::Pair;
-use std::collections::BTreeMap;
+use super::{err, locate_error, Env, Expr, Source, Location};
+use pest::{
+    error::InputLocation,
+    iterators::Pair,
+    pratt_parser::{Assoc, Op, PrattParser},
+};
+use std::{collections::BTreeMap, rc::Rc, sync::LazyLock};
 
 #[derive(Parser)]
 #[grammar = "lang/grammar.pest"]
 pub struct LangParser;
 
+/// How tightly each operator binds, from the loosest to the tightest.
+static OPERATORS: LazyLock<PrattParser<Rule>> = LazyLock::new(|| {
+    PrattParser::new()
+        .op(Op::infix(Rule::or_op, Assoc::Left))
+        .op(Op::infix(Rule::and_op, Assoc::Left))
+        .op(Op::infix(Rule::eq_op, Assoc::Left)
+            | Op::infix(Rule::ne_op, Assoc::Left)
+            | Op::infix(Rule::lt_op, Assoc::Left)
+            | Op::infix(Rule::le_op, Assoc::Left)
+            | Op::infix(Rule::gt_op, Assoc::Left)
+            | Op::infix(Rule::ge_op, Assoc::Left))
+        .op(Op::infix(Rule::to_op, Assoc::Left))
+        .op(Op::infix(Rule::add_op, Assoc::Left) | Op::infix(Rule::sub_op, Assoc::Left))
+        .op(Op::infix(Rule::mul_op, Assoc::Left) | Op::infix(Rule::div_op, Assoc::Left) | Op::infix(Rule::rem_op, Assoc::Left))
+        // Negation binds looser than `^`, so `-x ^ 2` is `-(x ^ 2)` as in maths
+        .op(Op::prefix(Rule::neg_op) | Op::prefix(Rule::not_op))
+        .op(Op::infix(Rule::pow_op, Assoc::Right))
+        .op(Op::infix(Rule::get_op, Assoc::Left))
+});
+
 pub fn parse(code: &str) -> Result<Expr, Expr> {
+    parse_file(code, "input")
+}
+
+/// Parse code from a file, or from somewhere else `name` describes, so that
+/// errors in it can say where they are.
+pub fn parse_file(code: &str, name: &str) -> Result<Expr, Expr> {
     use pest::Parser;
+    let source = Rc::new(Source {
+        name: name.to_string(),
+        code: code.to_string(),
+    });
     match LangParser::parse(Rule::program, code) {
         Ok(pairs) => {
             let mut exprs = vec![];
             for pair in pairs {
-                match process_expr(pair) {
+                let start = pair.as_span().start();
+                match process_expr(pair, &source) {
                     Ok(e) => exprs.push(e),
-                    Err(_e) => return err("InvalidSyntax", Expr::String(code.to_owned())),
+                    Err(message) => return syntax_error(message, Location::new(source, start)),
                 }
             }
             Ok(Expr::Do(exprs))
         }
-        Err(e) => err("InvalidSyntax", Expr::String(format!("parse error: {}", e))),
+        Err(e) => {
+            let start = match e.location {
+                InputLocation::Pos(start) | InputLocation::Span((start, _)) => start,
+            };
+            syntax_error(e.variant.message().to_string(), Location::new(source, start))
+        }
     }
 }
 
-fn process_expr(pair: Pair<Rule>) -> Result<Expr, String> {
-    Ok(match pair.as_rule() {
+fn syntax_error(message: String, location: Location) -> Result<Expr, Expr> {
+    err("InvalidSyntax", Expr::String(message)).map_err(|error| locate_error(error, Some(&location)))
+}
+
+/// The parameters of a function: symbols, `name = default` assignments, and
+/// a `...rest` to collect any more arguments. Arguments fill them in order,
+/// so the rest has to come last, and the ones with defaults after the others.
+fn process_params(pair: Pair<Rule>, source: &Rc<Source>) -> Result<Vec<Expr>, String> {
+    let params = pair
+        .into_inner()
+        .map(|pair| {
+            Ok(match process_expr(pair, source)? {
+                default @ Expr::Assign(..) => default,
+                param => param.strip_locations(),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    for pair in params.windows(2) {
+        match pair {
+            [Expr::Rest(rest), _] => return Err(format!("The rest parameter ...{:?} has to come last", rest)),
+            [Expr::Assign(..), Expr::Symbol(param)] => {
+                return Err(format!("The parameter {} has to come before the ones with defaults", param))
+            }
+            _ => {}
+        }
+    }
+    Ok(params)
+}
+
+/// Patterns are matched by their shape, so they don't keep locations.
+fn process_pattern(pair: Pair<Rule>, source: &Rc<Source>) -> Result<Expr, String> {
+    Ok(process_expr(pair, source)?.strip_locations())
+}
+
+/// Whether expressions parsed by a rule keep a location, for errors raised in
+/// them to say where they are. Operators keep the location of the operator.
+fn is_located(rule: Rule) -> bool {
+    matches!(
+        rule,
+        Rule::symbol
+            | Rule::apply
+            | Rule::if_else
+            | Rule::while_loop
+            | Rule::for_loop
+            | Rule::let_var_in
+            | Rule::set_var
+            | Rule::match_with
+            | Rule::exception
+    )
+}
+
+fn process_expr(pair: Pair<Rule>, source: &Rc<Source>) -> Result<Expr, String> {
+    let (rule, start) = (pair.as_rule(), pair.as_span().start());
+    let expr = match rule {
         Rule::value | Rule::complex_value | Rule::simple_value | Rule::program => {
-            process_expr(pair.into_inner().next().unwrap())?
+            process_expr(pair.into_inner().next().unwrap(), source)?
         }
         Rule::number => {
             if let Ok(n) = pair.as_str().parse::<i64>() {
@@ -49,7 +141,7 @@ fn process_expr(pair: Pair<Rule>) -> Result<Expr, String> {
         Rule::suite => {
             let mut list = Vec::new();
             for pair in pair.into_inner() {
-                list.push(process_expr(pair)?);
+                list.push(process_expr(pair, source)?);
             }
             if list.len() == 1 {
                 list[0].clone()
@@ -61,7 +153,7 @@ fn process_expr(pair: Pair<Rule>) -> Result<Expr, String> {
         Rule::list => {
             let mut list = Vec::new();
             for pair in pair.into_inner() {
-                list.push(process_expr(pair)?);
+                list.push(process_expr(pair, source)?);
             }
             Expr::List(list)
         }
@@ -69,102 +161,197 @@ fn process_expr(pair: Pair<Rule>) -> Result<Expr, String> {
             let mut dict = BTreeMap::new();
             for pair in pair.into_inner() {
                 let mut pairs = pair.into_inner();
-                let key = process_expr(pairs.next().unwrap())?;
-                let value = process_expr(pairs.next().unwrap())?;
+                let key = process_pattern(pairs.next().unwrap(), source)?;
+                let value = process_expr(pairs.next().unwrap(), source)?;
                 dict.insert(key, value);
             }
             Expr::Dict(dict)
         }
-        Rule::group => Expr::Group(Box::new(process_expr(pair.into_inner().next().unwrap())?)),
+        Rule::list_pattern => Expr::List(pair.into_inner().map(|pair| process_pattern(pair, source)).collect::<Result<_, _>>()?),
+        Rule::dict_pattern => {
+            let mut dict = BTreeMap::new();
+            for pair in pair.into_inner() {
+                let mut pairs = pair.into_inner();
+                let key = process_pattern(pairs.next().unwrap(), source)?;
+                let pattern = match pairs.next() {
+                    Some(pattern) => process_pattern(pattern, source)?,
+                    None => key.clone(),
+                };
+                dict.insert(key, pattern);
+            }
+            Expr::Dict(dict)
+        }
+        Rule::type_pattern => {
+            let mut pairs = pair.into_inner();
+            let type_name = Expr::Symbol(pairs.next().unwrap().as_str().to_string());
+            let binding = match pairs.next() {
+                Some(symbol) => process_pattern(symbol, source)?,
+                None => Expr::Symbol(String::from("_")),
+            };
+            Expr::Apply(Box::new(type_name), vec![binding])
+        }
+        Rule::match_with => {
+            let mut pairs = pair.into_inner();
+            let val = process_expr(pairs.next().unwrap(), source)?;
+            let mut arms = vec![];
+            for arm in pairs {
+                let mut pairs = arm.into_inner();
+                let pattern = process_pattern(pairs.next().unwrap(), source)?;
+                let body = process_expr(pairs.next().unwrap(), source)?;
+                arms.push((pattern, body));
+            }
+            Expr::Match(Box::new(val), arms)
+        }
+        Rule::group => Expr::Group(Box::new(process_expr(pair.into_inner().next().unwrap(), source)?)),
         Rule::boolean => {
-            if pair.as_str() == "true" {
+            if pair.as_str() == "True" {
                 Expr::Bool(true)
             } else {
                 Expr::Bool(false)
             }
         }
 
-        Rule::quote => Expr::Quote(Box::new(process_expr(pair.into_inner().next().unwrap())?)),
+        Rule::quote => Expr::Quote(Box::new(process_pattern(pair.into_inner().next().unwrap(), source)?)),
 
         Rule::let_var_in => {
             let mut pairs = pair.into_inner();
-            let var = process_expr(pairs.next().unwrap())?;
-            let val = process_expr(pairs.next().unwrap())?;
-            let ret = process_expr(pairs.next().unwrap())?;
+            let var = process_pattern(pairs.next().unwrap(), source)?;
+            let val = process_expr(pairs.next().unwrap(), source)?;
+            let ret = process_expr(pairs.next().unwrap(), source)?;
             Expr::Let(Box::new(var), Box::new(val), Box::new(ret))
         }
 
         Rule::set_var => {
             let mut pairs = pair.into_inner();
-            let var = process_expr(pairs.next().unwrap())?;
-            let val = process_expr(pairs.next().unwrap())?;
+            let var = process_pattern(pairs.next().unwrap(), source)?;
+            let val = process_expr(pairs.next().unwrap(), source)?;
             Expr::Assign(Box::new(var), Box::new(val))
         }
 
         Rule::if_else => {
             let mut pairs = pair.into_inner();
-            let cond = process_expr(pairs.next().unwrap())?;
-            let then = process_expr(pairs.next().unwrap())?;
-            let else_ = process_expr(pairs.next().unwrap())?;
+            let cond = process_expr(pairs.next().unwrap(), source)?;
+            let then = process_expr(pairs.next().unwrap(), source)?;
+            let else_ = process_expr(pairs.next().unwrap(), source)?;
             Expr::If(Box::new(cond), Box::new(then), Box::new(else_))
         }
 
+        Rule::while_loop => {
+            let mut pairs = pair.into_inner();
+            let cond = process_expr(pairs.next().unwrap(), source)?;
+            let body = process_expr(pairs.next().unwrap(), source)?;
+            Expr::While(Box::new(cond), Box::new(body))
+        }
+
         Rule::for_loop => {
             let mut pairs = pair.into_inner();
-            let name = process_expr(pairs.next().unwrap())?;
-            let list = process_expr(pairs.next().unwrap())?;
-            let e = process_expr(pairs.next().unwrap())?;
+            let name = process_pattern(pairs.next().unwrap(), source)?;
+            let list = process_expr(pairs.next().unwrap(), source)?;
+            let e = process_expr(pairs.next().unwrap(), source)?;
             Expr::For(Box::new(name), Box::new(list), Box::new(e))
         }
 
+        Rule::operation => OPERATORS
+            .map_primary(|pair| process_expr(pair, source))
+            .map_prefix(|op, val| {
+                let val = Box::new(val?);
+                let location = Location::new(source.clone(), op.as_span().start());
+                let expr = match op.as_rule() {
+                    Rule::neg_op => Expr::Neg(val),
+                    _ => Expr::Not(val),
+                };
+                Ok(Expr::At(location, Box::new(expr)))
+            })
+            .map_infix(|a, op, b| {
+                // A key after `@` is looked up as it's written, so it's kept as a pattern is
+                let b = if op.as_rule() == Rule::get_op { b?.strip_locations() } else { b? };
+                let (a, b) = (Box::new(a?), Box::new(b));
+                let location = Location::new(source.clone(), op.as_span().start());
+                let expr = match op.as_rule() {
+                    Rule::or_op => Expr::Or(a, b),
+                    Rule::and_op => Expr::And(a, b),
+                    Rule::eq_op => Expr::Eq(a, b),
+                    Rule::ne_op => Expr::Ne(a, b),
+                    Rule::lt_op => Expr::Lt(a, b),
+                    Rule::le_op => Expr::Le(a, b),
+                    Rule::gt_op => Expr::Gt(a, b),
+                    Rule::ge_op => Expr::Ge(a, b),
+                    Rule::to_op => Expr::To(a, b),
+                    Rule::add_op => Expr::Add(a, b),
+                    Rule::sub_op => Expr::Sub(a, b),
+                    Rule::mul_op => Expr::Mul(a, b),
+                    Rule::div_op => Expr::Div(a, b),
+                    Rule::rem_op => Expr::Rem(a, b),
+                    Rule::pow_op => Expr::Pow(a, b),
+                    _ => Expr::Get(a, b),
+                };
+                Ok(Expr::At(location, Box::new(expr)))
+            })
+            .parse(pair.into_inner())?,
+
         Rule::apply => {
             let mut pairs = pair.into_inner();
             // println!("{:?}", pairs);
-            let f = process_expr(pairs.next().unwrap())?;
+            let f = process_expr(pairs.next().unwrap(), source)?;
             let mut args = vec![];
+            let mut empty_parens = false;
             for pair in pairs {
-                args.push(process_expr(pair)?)
+                empty_parens = pair.as_str().split_whitespace().collect::<String>() == "()";
+                args.push(process_expr(pair, source)?)
+            }
+            // `f ()` calls `f` with no arguments, while `f None` passes None
+            if empty_parens && args.len() == 1 {
+                args.clear();
             }
             Expr::Apply(Box::new(f), args)
         }
 
         Rule::lambda_fn => {
             let mut pairs = pair.into_inner();
-            let arg = process_expr(pairs.next().unwrap())?;
-            let ret = process_expr(pairs.next().unwrap())?;
-            Expr::Fn(vec![arg], Box::new(ret), Env::default())
+            let params = process_params(pairs.next().unwrap(), source)?;
+            let ret = process_expr(pairs.next().unwrap(), source)?;
+            Expr::Fn(params, Rc::new(ret), Env::default())
         }
         Rule::macro_fn => {
             let mut pairs = pair.into_inner();
-            let arg = process_expr(pairs.next().unwrap())?;
-            let ret = process_expr(pairs.next().unwrap())?;
-            Expr::Macro(vec![arg], Box::new(ret))
+            let params = process_params(pairs.next().unwrap(), source)?;
+            let ret = process_expr(pairs.next().unwrap(), source)?;
+            Expr::Macro(params, Rc::new(ret))
         }
         Rule::proc_fn => {
             let mut pairs = pair.into_inner();
-            let arg = process_expr(pairs.next().unwrap())?;
-            let ret = process_expr(pairs.next().unwrap())?;
-            Expr::Proc(vec![arg], Box::new(ret))
+            let params = process_params(pairs.next().unwrap(), source)?;
+            let ret = process_expr(pairs.next().unwrap(), source)?;
+            Expr::Proc(params, Rc::new(ret))
+        }
+        Rule::default_param => {
+            let mut pairs = pair.into_inner();
+            let param = process_pattern(pairs.next().unwrap(), source)?;
+            let default = process_expr(pairs.next().unwrap(), source)?;
+            Expr::Assign(Box::new(param), Box::new(default))
         }
+        Rule::rest_param => Expr::Rest(Box::new(process_pattern(pair.into_inner().next().unwrap(), source)?)),
 
         Rule::try_catch => {
             let mut pairs = pair.into_inner();
-            let t = process_expr(pairs.next().unwrap())?;
-            let c = process_expr(pairs.next().unwrap())?;
+            let t = process_expr(pairs.next().unwrap(), source)?;
+            let c = process_expr(pairs.next().unwrap(), source)?;
             Expr::Try(Box::new(t), Box::new(c))
         }
 
         Rule::exception => {
             let mut pairs = pair.into_inner();
-            let e = process_expr(pairs.next().unwrap())?;
+            let e = process_expr(pairs.next().unwrap(), source)?;
             Expr::Raise(Box::new(e))
         }
 
         Rule::none => Expr::None,
 
-        _ => {
-            eprintln!("unexpected: {:?}", pair);
-            unimplemented!()
-        }
+        _ => return Err(format!("Unexpected {:?} at {:?}", rule, pair.as_str())),
+    };
+    Ok(if is_located(rule) {
+        Expr::At(Location::new(source.clone(), start), Box::new(expr))
+    } else {
+        expr
     })
 }
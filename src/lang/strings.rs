@@ -0,0 +1,278 @@
+use super::{err, eval, get_nth_arg, parse_file, string, Builtin, Env, Expr, Float};
+use crate::Editor;
+
+/// The builtins for working with text. They count in characters rather than
+/// bytes, so they're safe to use on any text, like the output of `get-select`.
+pub fn string_builtins() -> Vec<Builtin> {
+    vec![
+        Builtin::new(
+            "length",
+            &["val"],
+            "Count the characters in a string",
+            "Returns how many characters are in a string, or how many items are in a list or dict",
+            length,
+        ),
+        Builtin::new(
+            "split",
+            &["text", "[sep]"],
+            "Split a string into a list",
+            "Splits a string at each separator, like `split \"a,b\" \",\"`. Without one it splits into words at whitespace, and an empty separator splits into characters",
+            split,
+        ),
+        Builtin::new(
+            "join",
+            &["items", "[sep]"],
+            "Join a list into a string",
+            "Joins the items of a list into one string, with the separator between each of them if one is given",
+            join,
+        ),
+        Builtin::new(
+            "trim",
+            &["text"],
+            "Trim whitespace from a string",
+            "Returns a string without the whitespace at its start and end",
+            trim,
+        ),
+        Builtin::new(
+            "replace",
+            &["text", "from", "to"],
+            "Replace text in a string",
+            "Replaces every occurrence of `from` in a string with `to`",
+            replace,
+        ),
+        Builtin::new(
+            "contains",
+            &["val", "part"],
+            "Check whether a string contains another",
+            "Returns whether a string contains another string, a list contains an item, or a dict contains a key",
+            contains,
+        ),
+        Builtin::new(
+            "starts-with",
+            &["text", "prefix"],
+            "Check the start of a string",
+            "Returns whether a string starts with the prefix",
+            starts_with,
+        ),
+        Builtin::new(
+            "ends-with",
+            &["text", "suffix"],
+            "Check the end of a string",
+            "Returns whether a string ends with the suffix",
+            ends_with,
+        ),
+        Builtin::new(
+            "upper",
+            &["text"],
+            "Make a string uppercase",
+            "Returns a string with all of its letters in uppercase",
+            upper,
+        ),
+        Builtin::new(
+            "lower",
+            &["text"],
+            "Make a string lowercase",
+            "Returns a string with all of its letters in lowercase",
+            lower,
+        ),
+        Builtin::new(
+            "substring",
+            &["text", "start", "[end]"],
+            "Take part of a string",
+            "Returns the characters of a string from `start` up to but not including `end`, or to the end of the string. Negative indices count back from the end",
+            substring,
+        ),
+        Builtin::new(
+            "format",
+            &["template", "values..."],
+            "Fill in a template string",
+            "Replaces each `{}` in the template with the next value, and each `{code}` with the value of the code, like `format \"{} is {x + 1}\" name`. Use `{{` and `}}` for braces",
+            format,
+        ),
+        Builtin::new(
+            "to-number",
+            &["text"],
+            "Convert a string to a number",
+            "Reads an integer or a decimal number from a string, ignoring whitespace around it. Raises an InvalidNumber error if it isn't one",
+            to_number,
+        ),
+        Builtin::new(
+            "to-string",
+            &["val"],
+            "Convert a value to a string",
+            "Returns a value as a string, the same way `insert` would write it",
+            to_string,
+        ),
+    ]
+}
+
+/// A `TypeMismatch` error, for an argument of the wrong type.
+fn mismatch(message: String) -> Expr {
+    err("TypeMismatch", Expr::String(message)).unwrap_err()
+}
+
+/// Evaluate the nth argument, which should be a string.
+fn string_arg(builtin: &str, args: &[Expr], n: usize, editor: &mut Editor, env: &mut Env) -> Result<String, Expr> {
+    match eval(get_nth_arg(args, n)?, editor, env)? {
+        Expr::String(text) => Ok(text),
+        other => Err(mismatch(format!("{builtin} expected a string, not {other:?}"))),
+    }
+}
+
+/// Evaluate the nth argument, which should be an integer.
+fn int_arg(builtin: &str, args: &[Expr], n: usize, editor: &mut Editor, env: &mut Env) -> Result<i64, Expr> {
+    match eval(get_nth_arg(args, n)?, editor, env)? {
+        Expr::Int(n) => Ok(n),
+        other => Err(mismatch(format!("{builtin} expected an integer, not {other:?}"))),
+    }
+}
+
+pub fn length(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    let len = match eval(get_nth_arg(&args, 0)?, editor, env)? {
+        Expr::String(text) => text.chars().count(),
+        Expr::List(items) => items.len(),
+        Expr::Dict(items) => items.len(),
+        other => return Err(mismatch(format!("length expected a string, list or dict, not {other:?}"))),
+    };
+    Ok(Expr::Int(len as i64))
+}
+
+pub fn split(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    let text = string_arg("split", &args, 0, editor, env)?;
+    let parts: Vec<Expr> = if args.len() < 2 {
+        text.split_whitespace().map(string).collect()
+    } else {
+        let sep = string_arg("split", &args, 1, editor, env)?;
+        if sep.is_empty() {
+            text.chars().map(string).collect()
+        } else {
+            text.split(sep.as_str()).map(string).collect()
+        }
+    };
+    Ok(Expr::List(parts))
+}
+
+pub fn join(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    let Expr::List(items) = eval(get_nth_arg(&args, 0)?, editor, env)? else {
+        return Err(mismatch(String::from("join expected a list")));
+    };
+    let sep = if args.len() < 2 { String::new() } else { string_arg("join", &args, 1, editor, env)? };
+    Ok(Expr::String(items.iter().map(ToString::to_string).collect::<Vec<_>>().join(&sep)))
+}
+
+pub fn trim(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    Ok(string(string_arg("trim", &args, 0, editor, env)?.trim()))
+}
+
+pub fn replace(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    let text = string_arg("replace", &args, 0, editor, env)?;
+    let from = string_arg("replace", &args, 1, editor, env)?;
+    let to = string_arg("replace", &args, 2, editor, env)?;
+    if from.is_empty() {
+        return err("InvalidReplace", Expr::String(String::from("Can't replace an empty string")));
+    }
+    Ok(Expr::String(text.replace(&from, &to)))
+}
+
+pub fn contains(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    let found = match eval(get_nth_arg(&args, 0)?, editor, env)? {
+        Expr::String(text) => text.contains(&string_arg("contains", &args, 1, editor, env)?),
+        Expr::List(items) => items.contains(&eval(get_nth_arg(&args, 1)?, editor, env)?),
+        // Like `get`, a key can be given as it's written in the dict
+        Expr::Dict(items) => {
+            let key = get_nth_arg(&args, 1)?.strip_locations();
+            items.contains_key(&key) || items.contains_key(&eval(key, editor, env)?)
+        }
+        other => return Err(mismatch(format!("contains expected a string, list or dict, not {other:?}"))),
+    };
+    Ok(Expr::Bool(found))
+}
+
+pub fn starts_with(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    let text = string_arg("starts-with", &args, 0, editor, env)?;
+    let prefix = string_arg("starts-with", &args, 1, editor, env)?;
+    Ok(Expr::Bool(text.starts_with(&prefix)))
+}
+
+pub fn ends_with(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    let text = string_arg("ends-with", &args, 0, editor, env)?;
+    let suffix = string_arg("ends-with", &args, 1, editor, env)?;
+    Ok(Expr::Bool(text.ends_with(&suffix)))
+}
+
+pub fn upper(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    Ok(Expr::String(string_arg("upper", &args, 0, editor, env)?.to_uppercase()))
+}
+
+pub fn lower(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    Ok(Expr::String(string_arg("lower", &args, 0, editor, env)?.to_lowercase()))
+}
+
+pub fn substring(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    let text = string_arg("substring", &args, 0, editor, env)?;
+    let len = text.chars().count() as i64;
+    // Negative indices count back from the end, and both are kept in range
+    let index = |n: i64| if n < 0 { (len + n).max(0) } else { n.min(len) } as usize;
+    let start = index(int_arg("substring", &args, 1, editor, env)?);
+    let end = if args.len() < 3 { len as usize } else { index(int_arg("substring", &args, 2, editor, env)?) };
+    Ok(Expr::String(text.chars().skip(start).take(end.saturating_sub(start)).collect()))
+}
+
+pub fn format(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    let template = string_arg("format", &args, 0, editor, env)?;
+    let mut values = args[1..].iter();
+    let mut result = String::new();
+    let mut chars = template.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                result.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                result.push('}');
+            }
+            '{' => {
+                let Some(end) = chars.as_str().find('}') else {
+                    return err("InvalidFormat", Expr::String(format!("A {{ in {template:?} isn't closed")));
+                };
+                let rest = chars.as_str();
+                let code = &rest[..end];
+                let val = if code.trim().is_empty() {
+                    let Some(val) = values.next() else {
+                        return err("InvalidFormat", Expr::String(format!("Not enough values for {template:?}")));
+                    };
+                    eval(val.clone(), editor, env)?
+                } else {
+                    eval(parse_file(code, "format")?, editor, env)?
+                };
+                result += &val.to_string();
+                chars = rest[end + 1..].chars();
+            }
+            '}' => return err("InvalidFormat", Expr::String(format!("A }} in {template:?} wasn't opened"))),
+            ch => result.push(ch),
+        }
+    }
+    Ok(Expr::String(result))
+}
+
+pub fn to_number(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    let text = match eval(get_nth_arg(&args, 0)?, editor, env)? {
+        Expr::String(text) => text,
+        number @ (Expr::Int(_) | Expr::Float(_)) => return Ok(number),
+        other => return Err(mismatch(format!("to-number expected a string, not {other:?}"))),
+    };
+    let trimmed = text.trim();
+    if let Ok(n) = trimmed.parse::<i64>() {
+        Ok(Expr::Int(n))
+    } else if let Some(n) = trimmed.parse::<f64>().ok().filter(|n| n.is_finite()) {
+        Ok(Expr::Float(Float::from(n)))
+    } else {
+        err("InvalidNumber", Expr::String(text))
+    }
+}
+
+pub fn to_string(args: Vec<Expr>, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    Ok(Expr::String(eval(get_nth_arg(&args, 0)?, editor, env)?.to_string()))
+}
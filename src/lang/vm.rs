@@ -0,0 +1,362 @@
+use super::*;
+use crate::Editor;
+use std::{collections::HashMap, rc::Rc};
+
+/// Compile an expression and run it on the VM. This gives the same results
+/// as `eval`, without walking the tree each time a loop or function runs.
+pub fn run(expr: Expr, editor: &mut Editor, env: &mut Env) -> Result<Expr, Expr> {
+    if env.is_detached() {
+        *env = Env::new();
+    }
+    editor.start_script();
+    let mut vm = Vm::default();
+    vm.frames.push(Frame::new(Rc::new(compile(&expr)), env.clone(), String::new(), None));
+    let result = vm.run(editor);
+    editor.finish_script();
+    result
+}
+
+#[derive(Default)]
+struct Vm {
+    stack: Vec<Expr>,
+    frames: Vec<Frame>,
+    /// Function bodies compiled so far, by where they're stored. The body
+    /// is kept alongside so that its address isn't reused.
+    bodies: HashMap<*const Expr, (Rc<Expr>, Rc<Chunk>)>,
+}
+
+/// A chunk being run, which is a function body unless it's the first.
+/// Each frame after the first counts as a call toward the depth limit.
+struct Frame {
+    chunk: Rc<Chunk>,
+    pc: usize,
+    env: Env,
+    /// The scopes `env` is nested in since the frame started.
+    scopes: Vec<Env>,
+    handlers: Vec<Handler>,
+    /// The function running, and where it was called from, for errors.
+    name: String,
+    call_site: Option<Location>,
+}
+
+/// Where to go when an error is raised in a `try`.
+struct Handler {
+    target: usize,
+    stack_len: usize,
+    env: Env,
+    scopes: usize,
+}
+
+impl Frame {
+    fn new(chunk: Rc<Chunk>, env: Env, name: String, call_site: Option<Location>) -> Self {
+        Self {
+            chunk,
+            pc: 0,
+            env,
+            scopes: vec![],
+            handlers: vec![],
+            name,
+            call_site,
+        }
+    }
+
+    /// Where the instruction running was parsed from.
+    fn location(&self) -> Option<&Location> {
+        self.chunk.location(self.pc.saturating_sub(1))
+    }
+}
+
+impl Vm {
+    fn run(&mut self, editor: &mut Editor) -> Result<Expr, Expr> {
+        while let Some(frame) = self.frames.last_mut() {
+            let Some(&op) = frame.chunk.code.get(frame.pc) else {
+                // The frame's value is on top of the stack, for the one that called it
+                self.pop_frame(editor);
+                continue;
+            };
+            frame.pc += 1;
+            if let Err(error) = editor.step().and_then(|_| self.step(op, editor)) {
+                let frame = self.frames.last().unwrap();
+                self.unwind(locate_error(error, frame.location()), editor)?;
+            }
+        }
+        Ok(self.stack.pop().unwrap_or(Expr::None))
+    }
+
+    /// Go back to the innermost `try`, or give up on running the code.
+    fn unwind(&mut self, mut error: Expr, editor: &mut Editor) -> Result<(), Expr> {
+        while !self.frames.is_empty() {
+            let nested = self.frames.len() > 1;
+            let frame = self.frames.last_mut().unwrap();
+            if let Some(handler) = frame.handlers.pop() {
+                frame.pc = handler.target;
+                frame.env = handler.env;
+                frame.scopes.truncate(handler.scopes);
+                self.stack.truncate(handler.stack_len);
+                self.stack.push(error);
+                return Ok(());
+            }
+            if nested {
+                error = unwind_error(error, &frame.name, frame.call_site.as_ref());
+            }
+            self.pop_frame(editor);
+        }
+        Err(error)
+    }
+
+    fn pop_frame(&mut self, editor: &mut Editor) {
+        self.frames.pop();
+        if !self.frames.is_empty() {
+            editor.leave_call();
+        }
+    }
+
+    fn pop(&mut self) -> Expr {
+        self.stack.pop().expect("the stack is empty")
+    }
+
+    fn step(&mut self, op: Op, editor: &mut Editor) -> Result<(), Expr> {
+        let frame = self.frames.last_mut().unwrap();
+        let constant = |i: usize| &frame.chunk.constants[i];
+        match op {
+            Op::Const(i) => {
+                let val = constant(i).clone();
+                self.stack.push(val);
+            }
+            Op::Load(i) => match frame.env.get(constant(i)) {
+                Some(val) => self.stack.push(val),
+                None => return err("SymbolNotDefined", constant(i).clone()).map(drop),
+            },
+            Op::Closure(i) => {
+                let Expr::Fn(params, body, _) = constant(i).clone() else {
+                    unreachable!("closures are made from lambdas")
+                };
+                self.stack.push(Expr::Fn(params, body, frame.env.clone()));
+            }
+            Op::Bind(i) => {
+                let pattern = constant(i).clone();
+                let val = self.stack.pop().unwrap();
+                bind_pattern(pattern, val, &mut frame.env)?;
+            }
+            Op::Pop => {
+                self.pop();
+            }
+            Op::List(len) => {
+                let items = self.stack.split_off(self.stack.len() - len);
+                self.stack.push(Expr::List(items));
+            }
+            Op::Dict(i) => {
+                let Expr::List(keys) = constant(i).clone() else {
+                    unreachable!("dict keys are a list")
+                };
+                let vals = self.stack.split_off(self.stack.len() - keys.len());
+                self.stack.push(Expr::Dict(keys.into_iter().zip(vals).collect()));
+            }
+            Op::Binary(op) => {
+                let b = self.pop();
+                let a = self.pop();
+                self.stack.push(binary_op(op, a, b)?);
+            }
+            Op::Neg => {
+                let val = self.pop();
+                self.stack.push(negate(val)?);
+            }
+            Op::Not => {
+                let val = self.pop();
+                self.stack.push(not(val)?);
+            }
+            Op::Get(i) => {
+                let key = constant(i).clone();
+                let val = self.stack.pop().unwrap();
+                let env = &mut frame.env;
+                self.stack.push(get_item(val, key, |key| eval(key, editor, env))?);
+            }
+
+            Op::And(i, target) => match self.stack.last() {
+                Some(Expr::Bool(false)) => frame.pc = target,
+                Some(Expr::Bool(true)) => {
+                    self.stack.pop();
+                }
+                _ => {
+                    let a = self.stack.pop().unwrap();
+                    return err("InvalidAnd", Expr::And(Box::new(a), Box::new(constant(i).clone()))).map(drop);
+                }
+            },
+            Op::AndEnd => {
+                if !matches!(self.stack.last(), Some(Expr::Bool(_))) {
+                    let b = self.pop();
+                    return err("InvalidAnd", Expr::And(Box::new(Expr::Bool(true)), Box::new(b))).map(drop);
+                }
+            }
+            Op::Or(i, target) => match self.stack.last() {
+                Some(Expr::Bool(true)) => frame.pc = target,
+                Some(Expr::Bool(false)) => {
+                    self.stack.pop();
+                }
+                _ => {
+                    let a = self.stack.pop().unwrap();
+                    return err("InvalidOr", Expr::Or(Box::new(a), Box::new(constant(i).clone()))).map(drop);
+                }
+            },
+            Op::OrEnd => {
+                if !matches!(self.stack.last(), Some(Expr::Bool(_))) {
+                    let b = self.pop();
+                    return err("InvalidOr", Expr::Or(Box::new(Expr::Bool(false)), Box::new(b))).map(drop);
+                }
+            }
+            Op::Jump(target) => frame.pc = target,
+            Op::JumpUnless(target) => match self.stack.pop().unwrap() {
+                Expr::Bool(true) => {}
+                Expr::Bool(false) => frame.pc = target,
+                cond => return err("InvalidCond", cond).map(drop),
+            },
+            Op::EnterScope => {
+                let scope = frame.env.child();
+                frame.scopes.push(std::mem::replace(&mut frame.env, scope));
+            }
+            Op::ExitScope => frame.env = frame.scopes.pop().expect("a scope to exit"),
+
+            Op::Iter => {
+                let items = match self.stack.pop().unwrap() {
+                    Expr::Dict(dict) => dict.into_iter().map(|(key, val)| Expr::List(vec![key, val])).collect(),
+                    Expr::List(list) => list,
+                    Expr::String(s) => s.chars().map(string).collect(),
+                    iter => return err("InvalidIter", iter).map(drop),
+                };
+                self.stack.push(Expr::List(items));
+                self.stack.push(Expr::Int(0));
+            }
+            Op::Next(i, target) => {
+                let len = self.stack.len();
+                let [Expr::List(items), Expr::Int(next)] = &mut self.stack[len - 2..] else {
+                    unreachable!("iterating over a list")
+                };
+                match items.get(*next as usize).cloned() {
+                    Some(item) => {
+                        *next += 1;
+                        bind_pattern(constant(i).clone(), item, &mut frame.env)?;
+                    }
+                    None => {
+                        self.stack.truncate(len - 2);
+                        self.stack.push(Expr::None);
+                        frame.pc = target;
+                    }
+                }
+            }
+            Op::Match(i, target) => {
+                let mut bindings = vec![];
+                if match_pattern(constant(i), self.stack.last().unwrap(), &mut bindings) {
+                    self.stack.pop();
+                    frame.env.extend(bindings);
+                } else {
+                    frame.pc = target;
+                }
+            }
+            Op::NoMatch(i) => {
+                let patterns = constant(i).clone();
+                let val = self.stack.pop().unwrap();
+                return err("PatternMismatch", dict(&[(symbol("patterns"), patterns), (symbol("value"), val)])).map(drop);
+            }
+
+            Op::Raise => return Err(self.pop()),
+            Op::Try(target) => frame.handlers.push(Handler {
+                target,
+                stack_len: self.stack.len(),
+                env: frame.env.clone(),
+                scopes: frame.scopes.len(),
+            }),
+            Op::EndTry => {
+                frame.handlers.pop();
+            }
+
+            Op::Prepare { args, name, skip } => match self.stack.last() {
+                Some(Expr::Fn(..) | Expr::Proc(..) | Expr::Macro(..)) => {}
+                Some(Expr::Builtin(_)) => {
+                    let Some(Expr::Builtin(builtin)) = self.stack.pop() else {
+                        unreachable!()
+                    };
+                    let (Expr::List(args), Expr::String(name)) = (constant(args).clone(), constant(name)) else {
+                        unreachable!("calls have a list of arguments and a name")
+                    };
+                    builtin.check_arity(name, args.len())?;
+                    frame.pc = skip;
+                    let result = builtin.call(args, editor, &mut frame.env)?;
+                    self.stack.push(result);
+                }
+                _ => return err("InvalidFn", self.pop()).map(drop),
+            },
+            Op::Call { argc, name } => {
+                let Expr::String(name) = constant(name).clone() else {
+                    unreachable!("functions are named by strings")
+                };
+                let args = self.stack.split_off(self.stack.len() - argc);
+                let f = self.pop();
+                self.call(f, args, &name, editor)?;
+            }
+            Op::Catch { name } => {
+                let Expr::String(name) = constant(name).clone() else {
+                    unreachable!("functions are named by strings")
+                };
+                let f = self.stack.pop().unwrap();
+                let error = self.stack.pop().unwrap();
+                match f {
+                    Expr::Builtin(builtin) => {
+                        builtin.check_arity(&name, 1)?;
+                        let result = builtin.call(vec![Expr::Quote(Box::new(error))], editor, &mut frame.env)?;
+                        self.stack.push(result);
+                    }
+                    f @ (Expr::Fn(..) | Expr::Proc(..) | Expr::Macro(..)) => self.call(f, vec![error], &name, editor)?,
+                    f => return err("InvalidFn", f).map(drop),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Bind the arguments to a function's parameters, and start running its body.
+    fn call(&mut self, f: Expr, args: Vec<Expr>, name: &str, editor: &mut Editor) -> Result<(), Expr> {
+        let (params, body, mut scope) = match f {
+            Expr::Fn(params, body, captured) => (params, body, captured.child()),
+            Expr::Proc(params, body) => (params, body, Env::new()),
+            // Macros bind their arguments in the scope they're called from
+            Expr::Macro(params, body) => (params, body, self.frames.last().unwrap().env.clone()),
+            f => return err("InvalidFn", f).map(drop),
+        };
+        bind_params(name, params, args, editor, &mut scope)?;
+        let chunk = self.compiled(body);
+        // A call that's the last thing a function does takes over its frame,
+        // so loops written as recursion don't get any deeper
+        let nested = self.frames.len() > 1;
+        let caller = self.frames.last_mut().unwrap();
+        let call_site = caller.location().cloned();
+        if nested && caller.handlers.is_empty() && returns(&caller.chunk, caller.pc) {
+            *caller = Frame::new(chunk, scope, name.to_string(), call_site);
+        } else {
+            editor.enter_call()?;
+            self.frames.push(Frame::new(chunk, scope, name.to_string(), call_site));
+        }
+        Ok(())
+    }
+
+    fn compiled(&mut self, body: Rc<Expr>) -> Rc<Chunk> {
+        let (_, chunk) = self
+            .bodies
+            .entry(Rc::as_ptr(&body))
+            .or_insert_with(|| (body.clone(), Rc::new(compile(&body))));
+        chunk.clone()
+    }
+}
+
+/// Whether the chunk finishes from `pc` without doing anything but jump or
+/// leave scopes.
+fn returns(chunk: &Chunk, mut pc: usize) -> bool {
+    loop {
+        match chunk.code.get(pc) {
+            None => return true,
+            Some(Op::Jump(target)) => pc = *target,
+            // The scopes of a frame go with it when a call takes it over
+            Some(Op::ExitScope) => pc += 1,
+            Some(_) => return false,
+        }
+    }
+}
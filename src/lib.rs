@@ -2,14 +2,38 @@ mod buffer;
 pub use buffer::*;
 mod change;
 pub use change::*;
+mod dir;
+pub use dir::*;
 mod editor;
 pub use editor::*;
+mod event;
+pub use event::*;
 mod frontend;
 pub use frontend::*;
+mod fuzzy;
+pub use fuzzy::*;
+mod keymap;
+pub use keymap::*;
 mod lang;
 pub use lang::*;
+mod palette;
+pub use palette::*;
+mod project;
+pub use project::*;
+mod repl;
+pub use repl::*;
+mod screen;
+pub use screen::*;
+mod syntax;
+pub use syntax::*;
 mod terminal;
 pub use terminal::*;
+mod theme;
+pub use theme::*;
+mod vi;
+pub use vi::*;
+mod view;
+pub use view::*;
 
 extern crate pest;
 #[macro_use]
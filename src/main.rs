@@ -1,145 +1,346 @@
 use lite::{
-    delete, eval, get_selected, get_selected_lines, get_selection_end, get_selection_len,
-    get_selection_start, get_undo_stack_len, goto_cursor, insert, move_cursor, redo, select,
-    undo, unselect, parse, Buffer, Builtin, Direction, Editor, Expr, Frontend, Input, Terminal
+    center_view, delete, eval, get_selected, get_selected_lines, get_selection_end,
+    get_selection_len, get_selection_start, get_undo_stack_len, get_view, goto_cursor, insert,
+    move_cursor, redo, scroll_view, select, undo, unselect, parse, parse_file, dict, err, preview_file,
+    project_files, replace_in_files, replace_preview, search_project, string, symbol, format_keys, parse_keys, palette_entries, pretty_error, string_builtins, with_script_stack,
+    Buffer, Builtin, Command, CountingAllocator, Direction, Editor, Event, Expr, Frontend, Input, KeyPress, PaletteEntry, SearchMatch, Terminal,
+    Theme, Vi, ACTIONS, DEFAULT_MODE,
 };
-use dirs::home_dir;
+use regex::Regex;
+use dirs::{config_dir, home_dir};
+use std::{path::PathBuf, time::Duration};
 
-fn main() -> Result<(), Expr> {
+// Counts the memory in use, so scripts can be stopped when they use too much
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    // Scripts run on the editor's thread, and evaluating them recurses
+    with_script_stack(edit);
+}
+
+/// Run the editor until it's quit.
+fn edit() {
     let mut editor = Editor::new();
+    add_builtins(&mut editor);
+
+    // Syntax definitions in the config directory extend the built-in ones
+    let syntax_dir = config_dir().map(|dir| dir.join("lite").join("syntaxes"));
+    let (_, syntax_errors) = match &syntax_dir {
+        Some(dir) => editor.syntaxes_mut().load_dir(dir),
+        None => (0, vec![]),
+    };
+    let theme_dir = config_dir().map(|dir| dir.join("lite").join("themes"));
+    let (_, theme_errors) = match &theme_dir {
+        Some(dir) => editor.themes_mut().load_dir(dir),
+        None => (0, vec![]),
+    };
+    
+    // Check if config file exists in home directory
+    let home = home_dir().unwrap();
+    let config_file = home.join("config.lite");
+    let (config, config_name) = if config_file.exists() {
+        (std::fs::read_to_string(&config_file).unwrap(), config_file.display().to_string())
+    } else {
+        (include_str!("../config.lite").to_string(), String::from("config.lite"))
+    };
+    // A broken config is reported once the editor is up, rather than stopping it
+    let config_error = match parse_file(&config, &config_name).and_then(|config| editor.eval(config)) {
+        Ok(exports) => {
+            if let Expr::Dict(dict) = exports.clone() {
+                for (key, val) in dict {
+                    editor.env.set(key, val);
+                }
+            }
+            editor.env.set(
+                Expr::Symbol(String::from("config")),
+                exports,
+            );
+            None
+        }
+        Err(e) => Some(editor.report_error(&e)),
+    };
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 {
+        editor.open_file(args[1].clone());
+    }
+
+
+    let mut frontend = Terminal::new(editor.take_event_queue().unwrap_or_default());
+    let mut state = State::default();
+    show_buffer(&editor, &mut frontend);
+    if let Some(e) = syntax_errors.first() {
+        frontend.set_status(&format!("Failed to load syntax {}", e)).unwrap();
+    }
+    if let Some(e) = theme_errors.first() {
+        frontend.set_status(&format!("Failed to load theme {}", e)).unwrap();
+    }
+    if let Some(e) = config_error {
+        editor.show_errors();
+        show_buffer(&editor, &mut frontend);
+        frontend.set_status(&format!("Error in config: {}", e)).unwrap();
+    }
+
+    // Buffers opened, closed, saved or renamed after this watch their files themselves
+    editor.watch_open_files();
+    'editor: loop {
+        editor.resize_view(frontend.width(), frontend.height());
+        frontend.render(&editor, false).unwrap();
+
+        // Sleep until something happens, then handle everything that
+        // happened before drawing again
+        let events = frontend.wait_for_events(editor.next_deadline()).unwrap();
+        if !events.is_empty() {
+            editor.mark_active();
+        }
+        for event in events {
+            let input = match event {
+                Event::Input(input) => input,
+                Event::FileChanged(file) => {
+                    if let Some(message) = editor.file_changed(&file) {
+                        frontend.set_status(&message).unwrap();
+                    }
+                    continue;
+                }
+                Event::JobOutput(id, line) => {
+                    editor.job_output(id, &line);
+                    continue;
+                }
+                Event::JobDone(id, code) => {
+                    editor.job_output(id, &format!("[exited with {}]", code.map_or("a signal".to_string(), |code| format!("status {code}"))));
+                    frontend.set_status(&format!("Job {} finished", id)).unwrap();
+                    continue;
+                }
+            };
+            if handle_input(input, &mut editor, &mut frontend, &mut state) {
+                break 'editor;
+            }
+        }
 
-    editor.env.scope.insert(
+        for e in editor.run_timers() {
+            let message = editor.report_error(&e);
+            frontend.set_status(&format!("Error in timer: {}", message)).unwrap();
+        }
+        for e in editor.take_hook_errors() {
+            let message = editor.report_error(&e);
+            frontend.set_status(&format!("Error in hook: {}", message)).unwrap();
+        }
+    }
+
+    editor.run_hooks("exit", &[]);
+    frontend.exit();
+    // There's no status line left to show these on
+    for e in editor.take_hook_errors() {
+        eprintln!("Error in exit hook:");
+        for line in pretty_error(&e, 80) {
+            eprintln!("{}", line);
+        }
+    }
+
+    // let mut buf = Buffer::default();
+
+    // Change::Insert("Hello world!\n".to_string()).apply(&mut buf);
+    // Change::move_cur(Direction::Left, &buf).apply(&mut buf);
+    // Change::Insert(" testing".to_string()).apply(&mut buf);
+    // Change::move_cur(Direction::Down, &buf).apply(&mut buf);
+
+    // Change::Undo.apply(&mut buf);
+    // Change::Undo.apply(&mut buf);
+    // Change::Undo.apply(&mut buf);
+    // Change::Undo.apply(&mut buf);
+    // // Change::Redo.apply(&mut buf);
+    // // Change::Undo.apply(&mut buf);
+    // // Change::Redo.apply(&mut buf);
+    // // Change::Redo.apply(&mut buf);
+    // // Change::Redo.apply(&mut buf);
+
+    // println!("{:#?}", buf);
+    // println!("selected: {:?}", buf.selected());
+
+    // println!("{:?}", f64::from(Float::from(1232.1221394873219847334213)) + f64::from(Float::from(1234.231234321)));
+    // println!("{:?}", 1232.1221394873219847334213 + 1234.231234321)
+}
+
+/// Define the builtins scripts and the config use to drive the editor.
+fn add_builtins(editor: &mut Editor) {
+    editor.env.set(
         Expr::Symbol(String::from("insert")),
         Expr::Builtin(Builtin::new(
             "insert",
-            "insert some text",
-            "insert some text into the buffer",
+            &["text..."],
+            "Insert text at the cursor",
+            "Inserts each argument at the cursor in turn, converting anything that isn't a string to text",
             insert,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("delete")),
         Expr::Builtin(Builtin::new(
             "delete",
-            "delete some text",
-            "delete some text form the buffer",
+            &["count"],
+            "Delete characters before the cursor",
+            "Deletes the given number of characters to the left of the cursor, like pressing backspace that many times",
             delete,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("move")),
         Expr::Builtin(Builtin::new(
             "move",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            &["direction..."],
+            "Move the cursor",
+            "Moves the cursor by each argument in turn: \"left\", \"right\", \"up\" or \"down\" moves it one step, and a number moves it that many characters right, or left if it's negative",
             move_cursor,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("goto")),
         Expr::Builtin(Builtin::new(
             "goto",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            &["row", "[col]"],
+            "Move the cursor to a position",
+            "Moves the cursor to a row and column, counting from 0, given either as two numbers or as a list like the ones get-select-start returns",
             goto_cursor,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
+        Expr::Symbol(String::from("scroll")),
+        Expr::Builtin(Builtin::new(
+            "scroll",
+            &["direction", "[count]"],
+            "Scroll the view",
+            "Scrolls the view without moving the cursor, by a direction and an optional count of lines, or by a signed number of lines",
+            scroll_view,
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("center")),
+        Expr::Builtin(Builtin::new(
+            "center",
+            &[],
+            "Center the view",
+            "Scrolls the view so the cursor is in the middle of the screen",
+            center_view,
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("get-view")),
+        Expr::Builtin(Builtin::new(
+            "get-view",
+            &[],
+            "Get the visible region",
+            "Returns a dict with the id of the buffer in the view, its first visible row and column, and the number of rows and columns it shows",
+            get_view,
+        )),
+    );
+    editor.env.set(
         Expr::Symbol(String::from("get-select-start")),
         Expr::Builtin(Builtin::new(
-            "select-start",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            "get-select-start",
+            &[],
+            "Get where the selection starts",
+            "Returns the row and column where the selection starts as a list, or None if nothing is selected",
             get_selection_start,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("get-select-end")),
         Expr::Builtin(Builtin::new(
-            "select-end",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            "get-select-end",
+            &[],
+            "Get where the selection ends",
+            "Returns the row and column where the selection ends as a list, or None if nothing is selected",
             get_selection_end,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("get-select")),
         Expr::Builtin(Builtin::new(
             "get-select",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            &[],
+            "Get the selected text",
+            "Returns the selected text as a string, or None if nothing is selected",
             get_selected,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("get-select-lines")),
         Expr::Builtin(Builtin::new(
             "get-select-lines",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            &[],
+            "Get the selected lines",
+            "Returns the selected text as a list of lines, or None if nothing is selected",
             get_selected_lines,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("get-select-len")),
         Expr::Builtin(Builtin::new(
             "get-select-len",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            &[],
+            "Get the length of the selection",
+            "Returns the number of characters selected, or None if nothing is selected",
             get_selection_len,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("get-undo-stack-len")),
         Expr::Builtin(Builtin::new(
             "get-undo-stack-len",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            &[],
+            "Count the changes to undo",
+            "Returns how many changes to the current buffer can be undone",
             get_undo_stack_len,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("select")),
         Expr::Builtin(Builtin::new(
             "select",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            &[],
+            "Start selecting",
+            "Starts a selection at the cursor, which grows as the cursor moves until unselect",
             select,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("unselect")),
         Expr::Builtin(Builtin::new(
             "unselect",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            &[],
+            "Stop selecting",
+            "Clears the selection, leaving the text as it is",
             unselect,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("undo")),
         Expr::Builtin(Builtin::new(
             "undo",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            &["count"],
+            "Undo changes",
+            "Undoes the given number of changes to the current buffer",
             undo,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("redo")),
         Expr::Builtin(Builtin::new(
             "redo",
-            "move the cursor",
-            "move the cursor in the current buffer",
+            &["count"],
+            "Redo undone changes",
+            "Redoes the given number of changes that were undone in the current buffer",
             redo,
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("add")),
         Expr::Builtin(Builtin::new(
             "add",
-            "adds two things",
-            "adds two things together",
+            &["a", "b"],
+            "Add two values",
+            "Adds two numbers, or joins two strings or lists",
             |args, editor, env| {
                 eval(
                     Expr::Add(Box::new(args[0].clone()), Box::new(args[1].clone())),
@@ -149,12 +350,13 @@ fn main() -> Result<(), Expr> {
             },
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("sub")),
         Expr::Builtin(Builtin::new(
             "sub",
-            "subtracts two things",
-            "subtracts two things together",
+            &["a", "b"],
+            "Subtract two numbers",
+            "Subtracts the second number from the first",
             |args, editor, env| {
                 eval(
                     Expr::Sub(Box::new(args[0].clone()), Box::new(args[1].clone())),
@@ -164,12 +366,13 @@ fn main() -> Result<(), Expr> {
             },
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("mul")),
         Expr::Builtin(Builtin::new(
             "mul",
-            "multiplies two things",
-            "multiplies two things together",
+            &["a", "b"],
+            "Multiply two values",
+            "Multiplies two numbers, or repeats a string or list a number of times",
             |args, editor, env| {
                 eval(
                     Expr::Mul(Box::new(args[0].clone()), Box::new(args[1].clone())),
@@ -179,12 +382,13 @@ fn main() -> Result<(), Expr> {
             },
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("div")),
         Expr::Builtin(Builtin::new(
             "div",
-            "divides two things",
-            "divides two things together",
+            &["a", "b"],
+            "Divide two numbers",
+            "Divides the first number by the second",
             |args, editor, env| {
                 eval(
                     Expr::Div(Box::new(args[0].clone()), Box::new(args[1].clone())),
@@ -194,12 +398,13 @@ fn main() -> Result<(), Expr> {
             },
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("rem")),
         Expr::Builtin(Builtin::new(
             "rem",
-            "remainders two things",
-            "remainders two things together",
+            &["a", "b"],
+            "Get the remainder of a division",
+            "Returns the remainder of dividing the first number by the second",
             |args, editor, env| {
                 eval(
                     Expr::Rem(Box::new(args[0].clone()), Box::new(args[1].clone())),
@@ -209,12 +414,13 @@ fn main() -> Result<(), Expr> {
             },
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
         Expr::Symbol(String::from("new-buf")),
         Expr::Builtin(Builtin::new(
             "new-buf",
-            "Create a new buffer in the editor",
-            "Creates a new buffer in the editor",
+            &[],
+            "Create a new buffer",
+            "Creates a new, empty buffer and returns its id, without switching to it",
             |_args, editor, _env| {
                 let buf = Buffer::default();
                 editor.add_buf(buf);
@@ -222,449 +428,1104 @@ fn main() -> Result<(), Expr> {
             },
         )),
     );
-    editor.env.scope.insert(
+    editor.env.set(
+        Expr::Symbol(String::from("list-bufs")),
+        Expr::Builtin(Builtin::new(
+            "list-bufs",
+            &[],
+            "List the open buffers",
+            "Lists every open buffer as a dict with its id, file name and whether it has been edited",
+            |_args, editor, _env| {
+                Ok(Expr::List(
+                    editor
+                        .buffers()
+                        .iter()
+                        .enumerate()
+                        .map(|(id, buf)| {
+                            dict(&[
+                                (symbol("id"), Expr::Int(id as i64)),
+                                (symbol("name"), buf.get_file_name().map(string).unwrap_or(Expr::None)),
+                                (symbol("edited"), Expr::Bool(buf.is_edited())),
+                            ])
+                        })
+                        .collect(),
+                ))
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("tab-bar")),
+        Expr::Builtin(Builtin::new(
+            "tab-bar",
+            &["show"],
+            "Show or hide the tab bar",
+            "Shows the open buffers along the top of the screen if given True, and hides them if given False",
+            |args, editor, env| {
+                if let Expr::Bool(show) = eval(args[0].clone(), editor, env)? {
+                    editor.set_tab_bar(show);
+                    Ok(Expr::None)
+                } else {
+                    err("TypeMismatch", Expr::String("Expected a boolean".to_string()))
+                }
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("bind")),
+        Expr::Builtin(Builtin::new(
+            "bind",
+            &["keys", "command", "[mode]"],
+            "Bind keys to an action or function",
+            "Binds a key, or a chord of keys like \"Ctrl-x Ctrl-s\", to one of the editor's actions given by name, or to a function to call with no arguments. Binding None removes the binding. The binding is for the default mode, unless a mode like \"normal\" or \"insert\" is given after the command",
+            |args, editor, env| {
+                if args.len() != 2 && args.len() != 3 {
+                    return err("ArityMismatch", Expr::String("Expected keys, a command and maybe a mode".to_string()));
+                }
+                let Expr::String(keys) = eval(args[0].clone(), editor, env)? else {
+                    return err("TypeMismatch", Expr::String("Expected the keys as a string".to_string()));
+                };
+                let keys = parse_keys(&keys).map_err(|e| error("InvalidKeys", e))?;
+                let command = eval(args[1].clone(), editor, env)?;
+                let mode = match args.get(2).map(|mode| eval(mode.clone(), editor, env)).transpose()? {
+                    Some(Expr::String(mode)) => mode,
+                    Some(_) => return err("TypeMismatch", Expr::String("Expected the mode as a string".to_string())),
+                    None => DEFAULT_MODE.to_string(),
+                };
+                match command {
+                    Expr::None => {
+                        editor.keymap_mut().unbind(&mode, &keys);
+                    }
+                    Expr::String(action) => {
+                        if !ACTIONS.iter().any(|(name, _)| *name == action) {
+                            return err("UnknownAction", Expr::String(action));
+                        }
+                        editor.keymap_mut().bind(&mode, keys, Command::Action(action));
+                    }
+                    function => editor.keymap_mut().bind(&mode, keys, Command::Script(function)),
+                }
+                Ok(Expr::None)
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("vi-mode")),
+        Expr::Builtin(Builtin::new(
+            "vi-mode",
+            &["enabled"],
+            "Turn vi-style modal editing on or off",
+            "Starts editing in vi's normal mode if given True, and goes back to the default bindings if given False",
+            |args, editor, env| {
+                if let Expr::Bool(enabled) = eval(args[0].clone(), editor, env)? {
+                    editor.unselect();
+                    editor.keymap_mut().set_mode(if enabled { "normal" } else { DEFAULT_MODE }).map_err(|e| error("UnknownMode", e))?;
+                    Ok(Expr::None)
+                } else {
+                    err("TypeMismatch", Expr::String("Expected a boolean".to_string()))
+                }
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("mouse")),
+        Expr::Builtin(Builtin::new(
+            "mouse",
+            &["enabled"],
+            "Turn mouse support on or off",
+            "Lets the mouse place the cursor, select and scroll if given True, and leaves the mouse to the terminal if given False",
+            |args, editor, env| {
+                if let Expr::Bool(enabled) = eval(args[0].clone(), editor, env)? {
+                    editor.set_mouse(enabled);
+                    Ok(Expr::None)
+                } else {
+                    err("TypeMismatch", Expr::String("Expected a boolean".to_string()))
+                }
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("limits")),
+        Expr::Builtin(Builtin::new(
+            "limits",
+            &["[limits]"],
+            "Limit how much a script can do",
+            "Sets any of the limits in a dict like {steps: 1000000, depth: 500, memory: 100000000}: the steps a script can run for, how deep its function calls can go, and the bytes of memory it can use. A script that goes over one is stopped with an error, as is one interrupted with Ctrl-C. Returns the limits as they are after any changes",
+            |args, editor, env| {
+                let mut limits = editor.limits();
+                if let Some(arg) = args.first() {
+                    let Expr::Dict(changes) = eval(arg.clone(), editor, env)? else {
+                        return err("TypeMismatch", Expr::String("Expected a dict of limits".to_string()));
+                    };
+                    for (key, val) in changes {
+                        match (key, val) {
+                            (Expr::Symbol(key), Expr::Int(n)) if n > 0 && key == "steps" => limits.steps = n as u64,
+                            (Expr::Symbol(key), Expr::Int(n)) if n > 0 && key == "depth" => limits.depth = n as usize,
+                            (Expr::Symbol(key), Expr::Int(n)) if n > 0 && key == "memory" => limits.memory = n as usize,
+                            (key, _) => return err("TypeMismatch", Expr::String(format!("Expected steps, depth or memory as a positive number, not {key:?}"))),
+                        }
+                    }
+                    editor.set_limits(limits);
+                }
+                Ok(dict(&[
+                    (symbol("steps"), Expr::Int(limits.steps as i64)),
+                    (symbol("depth"), Expr::Int(limits.depth as i64)),
+                    (symbol("memory"), Expr::Int(limits.memory as i64)),
+                ]))
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("open")),
+        Expr::Builtin(Builtin::new(
+            "open",
+            &["file"],
+            "Open a file",
+            "Switches to the buffer editing a file, opening it in a new buffer if needed. Returns the buffer's id",
+            |args, editor, env| {
+                if let Expr::String(file) = eval(args[0].clone(), editor, env)? {
+                    editor.open_file(file);
+                    Ok(Expr::Int(editor.cur_buf_id() as i64))
+                } else {
+                    err("TypeMismatch", Expr::String("Expected a string".to_string()))
+                }
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("search-project")),
+        Expr::Builtin(Builtin::new(
+            "search-project",
+            &["pattern", "[globs]"],
+            "Search every project file",
+            "Searches the files under the working directory for a regex, optionally limited by a list of globs (prefix a glob with ! to exclude), and returns the matches as dicts with a path, line, col and text",
+            |args, editor, env| {
+                let Expr::String(pattern) = eval(args[0].clone(), editor, env)? else {
+                    return err("TypeMismatch", Expr::String("Expected a string".to_string()));
+                };
+                let globs = match args.get(1).map(|arg| eval(arg.clone(), editor, env)).transpose()? {
+                    Some(Expr::List(globs)) => globs.iter().map(ToString::to_string).collect(),
+                    _ => vec![],
+                };
+                let regex = Regex::new(&pattern).map_err(|e| error("InvalidRegex", e.to_string()))?;
+                let matches = search_project(".", &regex, &globs).map_err(|e| error("SearchFailed", e))?;
+                Ok(Expr::List(
+                    matches
+                        .into_iter()
+                        .map(|found| {
+                            dict(&[
+                                (symbol("path"), string(found.path)),
+                                (symbol("line"), Expr::Int(found.line as i64)),
+                                (symbol("col"), Expr::Int(found.col as i64)),
+                                (symbol("text"), string(found.text)),
+                            ])
+                        })
+                        .collect(),
+                ))
+            },
+        )),
+    );
+    editor.env.set(
         Expr::Symbol(String::from("set-buf")),
         Expr::Builtin(Builtin::new(
             "set-buf",
-            "Set the current buffer",
-            "Sets the current buffer",
+            &["id"],
+            "Switch to a buffer",
+            "Switches to the buffer with the given id, as listed by list-bufs, and returns the id of the current buffer",
             |args, editor, env| {
                 if let Expr::Int(id) = eval(args[0].clone(), editor, env)? {
                     editor.set_buf(id as usize);
                     Ok(Expr::Int(editor.cur_buf_id() as i64))
                 } else {
-                    Err(Expr::String("Expected an integer".to_string()))
+                    err("TypeMismatch", Expr::String("Expected an integer".to_string()))
                 }
             },
         )),
     );
-    
-    // Check if config file exists in home directory
-    let home = home_dir().unwrap();
-    let config = home.join("config.lite");
-    let config = if config.exists() {
-        std::fs::read_to_string(config).unwrap()
-    } else {
-        include_str!("../config.lite").to_string()  
-    };
-    let config = match parse(config.trim()) {
-        Ok(expr) => expr,
-        Err(e) => {
-            eprintln!("Failed to parse config: {}", e);
-            std::process::exit(1);
+    editor.env.set(
+        Expr::Symbol(String::from("repl-target")),
+        Expr::Builtin(Builtin::new(
+            "repl-target",
+            &["id"],
+            "Choose the buffer the REPL edits",
+            "Makes code typed into the REPL run with the buffer of the given id as the current one, so that insert, goto and the like change it",
+            |args, editor, env| {
+                if let Expr::Int(id) = eval(args[0].clone(), editor, env)? {
+                    editor.set_repl_target(id as usize).map_err(|e| error("InvalidBuffer", e))?;
+                    Ok(Expr::None)
+                } else {
+                    err("TypeMismatch", Expr::String("Expected a buffer id".to_string()))
+                }
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("load-syntax")),
+        Expr::Builtin(Builtin::new(
+            "load-syntax",
+            &["file"],
+            "Load a .sublime-syntax file",
+            "Loads a syntax definition from a .sublime-syntax file, to highlight files with its extensions. Returns the syntax's name.",
+            |args, editor, env| {
+                if let Expr::String(file) = eval(args[0].clone(), editor, env)? {
+                    match editor.syntaxes_mut().load(&file) {
+                        Ok(syntax) => Ok(string(syntax.name.clone())),
+                        Err(e) => Err(error("LoadFailed", e)),
+                    }
+                } else {
+                    err("TypeMismatch", Expr::String("Expected a file name".to_string()))
+                }
+            },
+        )),
+    );
+
+    editor.env.set(
+        Expr::Symbol(String::from("set-theme")),
+        Expr::Builtin(Builtin::new(
+            "set-theme",
+            &["name"],
+            "Switch to a color theme",
+            "Switches to the color theme with the given name, such as \"dark\" or \"light\"",
+            |args, editor, env| {
+                if let Expr::String(name) = eval(args[0].clone(), editor, env)? {
+                    editor.themes_mut().set(&name).map_err(|e| error("UnknownTheme", e))?;
+                    Ok(Expr::None)
+                } else {
+                    err("TypeMismatch", Expr::String("Expected a theme name".to_string()))
+                }
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("list-themes")),
+        Expr::Builtin(Builtin::new(
+            "list-themes",
+            &[],
+            "List the color themes",
+            "Lists the names of every color theme that can be switched to with set-theme",
+            |_args, editor, _env| {
+                Ok(Expr::List(editor.themes().names().into_iter().map(string).collect()))
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("define-theme")),
+        Expr::Builtin(Builtin::new(
+            "define-theme",
+            &["name", "styles"],
+            "Define a color theme",
+            "Defines a color theme from a name and a dict of styles, like {keyword: \"magenta bold\", normal: \"white on black\"}. A style is a foreground color, then `on` and a background color, then any of bold, italic and underline. Colors are names like dark_grey, 256-color numbers, or #rrggbb. Elements left out are taken from the theme named by `inherit`, or from the dark theme",
+            |args, editor, env| {
+                let name = match eval(args[0].clone(), editor, env)? {
+                    Expr::String(name) => name,
+                    _ => return err("TypeMismatch", Expr::String("Expected a theme name".to_string())),
+                };
+                let Expr::Dict(styles) = eval(args[1].clone(), editor, env)? else {
+                    return err("TypeMismatch", Expr::String("Expected a dict of styles".to_string()));
+                };
+                let mut specs = vec![];
+                for (element, style) in styles {
+                    match (element, style) {
+                        (Expr::Symbol(element) | Expr::String(element), Expr::String(style)) => specs.push((element, style)),
+                        (element, _) => return err("TypeMismatch", Expr::String(format!("Expected a style string for {element}"))),
+                    }
+                }
+                let theme = Theme::from_specs(&name, &specs, editor.themes()).map_err(|e| error("InvalidTheme", e))?;
+                editor.themes_mut().add(theme);
+                Ok(string(name))
+            },
+        )),
+    );
+
+    editor.env.set(
+        Expr::Symbol(String::from("after")),
+        Expr::Builtin(Builtin::new(
+            "after",
+            &["ms", "callback"],
+            "Run a function after a delay",
+            "Calls a function with no arguments once, after the given number of milliseconds. Returns a timer id for cancel-timer",
+            |args, editor, env| {
+                let Expr::Int(ms) = eval(args[0].clone(), editor, env)? else {
+                    return err("TypeMismatch", Expr::String("Expected a number of milliseconds".to_string()));
+                };
+                let callback = eval(args[1].clone(), editor, env)?;
+                Ok(Expr::Int(editor.add_timer(Duration::from_millis(ms.max(0) as u64), false, callback) as i64))
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("every")),
+        Expr::Builtin(Builtin::new(
+            "every",
+            &["ms", "callback"],
+            "Run a function repeatedly",
+            "Calls a function with no arguments every time the given number of milliseconds passes. Returns a timer id for cancel-timer",
+            |args, editor, env| {
+                let Expr::Int(ms) = eval(args[0].clone(), editor, env)? else {
+                    return err("TypeMismatch", Expr::String("Expected a number of milliseconds".to_string()));
+                };
+                let callback = eval(args[1].clone(), editor, env)?;
+                Ok(Expr::Int(editor.add_timer(Duration::from_millis(ms.max(1) as u64), true, callback) as i64))
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("on-idle")),
+        Expr::Builtin(Builtin::new(
+            "on-idle",
+            &["callback"],
+            "Run a function whenever the editor is idle",
+            "Calls a function with no arguments each time the editor has had no input or other events for half a second. Returns a timer id for cancel-timer",
+            |args, editor, env| {
+                let callback = eval(args[0].clone(), editor, env)?;
+                Ok(Expr::Int(editor.add_idle_callback(callback) as i64))
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("cancel-timer")),
+        Expr::Builtin(Builtin::new(
+            "cancel-timer",
+            &["id"],
+            "Stop a timer",
+            "Stops a timer or idle callback started by after, every or on-idle. Returns whether there was one with the id",
+            |args, editor, env| {
+                if let Expr::Int(id) = eval(args[0].clone(), editor, env)? {
+                    Ok(Expr::Bool(editor.cancel_timer(id as usize)))
+                } else {
+                    err("TypeMismatch", Expr::String("Expected a timer id".to_string()))
+                }
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("get")),
+        Expr::Builtin(Builtin::new(
+            "get",
+            &["collection", "key"],
+            "Get an item of a dict, list or string",
+            "Looks up a key in a dict, like `get details file`, or the item at an index of a list or string. Returns None if there is no such item",
+            |args, editor, env| {
+                if args.len() != 2 {
+                    return err("ArityMismatch", Expr::String("Expected a collection and a key".to_string()));
+                }
+                eval(Expr::Get(Box::new(args[0].clone()), Box::new(args[1].clone())), editor, env)
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("add-hook")),
+        Expr::Builtin(Builtin::new(
+            "add-hook",
+            &["event", "callback"],
+            "Run a function on an editor event",
+            "Calls a function with a dict of details each time an event happens: open, before-save, after-save, switch, insert, delete, move, idle or exit. Returns a hook id for remove-hook",
+            |args, editor, env| {
+                let Expr::String(event) = eval(args[0].clone(), editor, env)? else {
+                    return err("TypeMismatch", Expr::String("Expected the name of an event".to_string()));
+                };
+                let callback = eval(args[1].clone(), editor, env)?;
+                editor.add_hook(&event, callback).map(|id| Expr::Int(id as i64)).map_err(|e| error("UnknownEvent", e))
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("remove-hook")),
+        Expr::Builtin(Builtin::new(
+            "remove-hook",
+            &["id"],
+            "Stop running a hook",
+            "Removes a hook added by add-hook. Returns whether there was one with the id",
+            |args, editor, env| {
+                if let Expr::Int(id) = eval(args[0].clone(), editor, env)? {
+                    Ok(Expr::Bool(editor.remove_hook(id as usize)))
+                } else {
+                    err("TypeMismatch", Expr::String("Expected a hook id".to_string()))
+                }
+            },
+        )),
+    );
+    editor.env.set(
+        Expr::Symbol(String::from("spawn")),
+        Expr::Builtin(Builtin::new(
+            "spawn",
+            &["command"],
+            "Run a shell command in the background",
+            "Runs a shell command without waiting for it, adding its output to a new read-only buffer as it arrives. Returns the job's id",
+            |args, editor, env| {
+                if let Expr::String(command) = eval(args[0].clone(), editor, env)? {
+                    Ok(Expr::Int(editor.spawn_job(&command).map_err(|e| error("SpawnFailed", e))? as i64))
+                } else {
+                    err("TypeMismatch", Expr::String("Expected a command".to_string()))
+                }
+            },
+        )),
+    );
+    for builtin in string_builtins() {
+        editor.env.set(Expr::Symbol(builtin.name.clone()), Expr::Builtin(builtin));
+    }
+}
+
+/// An error of a kind, raised by a builtin with a message about it.
+fn error(kind: &str, message: String) -> Expr {
+    err(kind, Expr::String(message)).unwrap_err()
+}
+
+/// What the main loop remembers from one key to the next.
+#[derive(Default)]
+struct State {
+    /// Whether the selection was started by moving with shift held.
+    selected: bool,
+    vi: Vi,
+    copied: String,
+    last_search: String,
+    last_eval: String,
+    last_project_search: String,
+    last_project_globs: String,
+}
+
+/// Handle a key or mouse input, looking it up in the keymap. Returns
+/// whether the editor should quit.
+fn handle_input(input: Input, editor: &mut Editor, frontend: &mut impl Frontend, state: &mut State) -> bool {
+    // Shifted characters arrive in upper case already
+    let input = input.unshifted();
+    let is_key = !matches!(input, Input::Resize | Input::Click { .. } | Input::Drag { .. } | Input::ScrollUp | Input::ScrollDown);
+    if is_key && Vi::is_active(editor) {
+        state.vi.record(&input);
+    }
+
+    let press = editor.keymap_mut().press(input);
+    run_key_press(press, editor, frontend, state)
+}
+
+/// Do what pressing keys came to. Returns true if the editor should quit.
+fn run_key_press(press: KeyPress, editor: &mut Editor, frontend: &mut impl Frontend, state: &mut State) -> bool {
+    match press {
+        KeyPress::Run(Command::Action(action)) if action == "vi-repeat" => {
+            let (keys, count) = state.vi.start_repeat();
+            for key in std::iter::repeat_n(keys, count).flatten() {
+                if handle_input(key, editor, frontend, state) {
+                    return true;
+                }
+            }
+            state.vi.end_repeat();
         }
-    };
-    match editor.eval(config) {
-        Ok(exports) => {
-            if let Expr::Dict(dict) = exports.clone() {
-                for (key, val) in dict {
-                    editor.env.scope.insert(key, val);
+        KeyPress::Run(Command::Action(action)) if action.starts_with("vi-") => state.vi.run(&action, editor),
+        KeyPress::Run(Command::Action(action)) => {
+            if run_action(&action, editor, frontend, state) {
+                return true;
+            }
+            state.vi.finish(editor);
+        }
+        KeyPress::Run(Command::Script(function)) => {
+            if let Err(e) = editor.eval(Expr::Apply(Box::new(function), vec![])) {
+                let message = editor.report_error(&e);
+                frontend.set_status(&format!("Error: {}", message)).unwrap();
+            }
+            state.vi.finish(editor);
+        }
+        KeyPress::Pending(keys) => frontend.set_status(&format!("{}-", format_keys(&keys))).unwrap(),
+        KeyPress::Unbound(mut keys) if keys.len() > 1 => {
+            // No binding starts with all of these keys, so the first does
+            // what it does on its own, typing itself if it isn't bound, and
+            // the rest are pressed again, since they may start a chord
+            let first = editor.keymap().press_alone(keys[0].clone());
+            if matches!(first, KeyPress::Unbound(_)) && !matches!(keys[0], Input::Char(_)) {
+                frontend.set_status(&format!("{} is not bound", format_keys(&keys))).unwrap();
+            }
+            keys.remove(0);
+            if run_key_press(first, editor, frontend, state) {
+                return true;
+            }
+            for key in keys {
+                let press = editor.keymap_mut().press(key);
+                if run_key_press(press, editor, frontend, state) {
+                    return true;
                 }
             }
-            editor.env.scope.insert(
-                Expr::Symbol(String::from("config")),
-                exports,
-            );
         }
+        KeyPress::Unbound(mut keys) => match keys.remove(0) {
+            Input::Resize => editor.resize_view(frontend.width(), frontend.height()),
+            Input::Click { row, col, clicks } => {
+                editor.click((row, col), clicks);
+                // Double and triple clicks select, like a drag
+                state.selected = clicks > 1;
+            }
+            Input::Drag { row, col } => {
+                editor.drag((row, col));
+                state.selected = true;
+            }
+            Input::ScrollUp => editor.scroll_view(Direction::Up, 3),
+            Input::ScrollDown => editor.scroll_view(Direction::Down, 3),
+
+            Input::Char(ch) if editor.cur_buf().unwrap().is_dir() => {
+                dir_command(ch, editor, frontend);
+            }
+            // Outside of insert mode, vi's modes don't take Enter
+            Input::Enter if editor.cur_buf().is_some_and(|buf| buf.is_dir() || buf.is_results() || buf.is_repl()) => {
+                run_action("enter", editor, frontend, state);
+            }
+            // Keys that vi's normal and visual modes don't bind do nothing
+            _ if Vi::is_command_mode(editor) => state.vi.cancel(editor),
+
+            Input::Char(ch) => {
+                state.selected = false;
+                editor.unselect();
+                editor.insert(ch);
+            }
+
+            // Pasted text goes in as it is, as one change to undo
+            Input::Paste(text) => {
+                state.selected = false;
+                editor.unselect();
+                editor.insert(text);
+            }
+
+            _ => {}
+        },
+    }
+    false
+}
+
+/// Show the result of running a script, or the error it raised.
+fn show_result(result: Result<Expr, Expr>, editor: &mut Editor, frontend: &mut impl Frontend) {
+    match result {
+        Ok(result) => frontend.set_status(&format!("Result: {result}")).unwrap(),
         Err(e) => {
-            eprintln!("{}", e);
-            std::process::exit(1);
+            let message = editor.report_error(&e);
+            frontend.set_status(&format!("Error: {}", message)).unwrap()
         }
     }
+}
 
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() > 1 {
-        let file = args[1].clone();
-        let buf = Buffer::from_file_name(file);
-        editor.add_buf(buf);
-        editor.set_buf(editor.max_buf_id());
+/// Ask for each argument of a palette entry in turn, and for arguments
+/// that can be given any number of times until one is left empty. Leaving
+/// an optional argument empty stops asking. Returns None if a prompt is
+/// cancelled.
+fn prompt_args(entry: &PaletteEntry, frontend: &mut impl Frontend) -> Result<Option<Vec<Expr>>, Expr> {
+    let mut args = vec![];
+    for param in &entry.params {
+        let many = param.ends_with("...");
+        loop {
+            let Ok(input) = frontend.prompt(&format!("{} {}: ", entry.name, param), None) else {
+                return Ok(None);
+            };
+            if input.trim().is_empty() && (many || param.starts_with('[')) {
+                return Ok(Some(args));
+            }
+            args.push(parse(&input)?);
+            if !many {
+                break;
+            }
+        }
     }
+    Ok(Some(args))
+}
 
+/// Show which buffer is being edited on the status line.
+fn show_buffer(editor: &Editor, frontend: &mut impl Frontend) {
+    frontend.set_status(&format!("Editing in buffer #{}: {}", editor.cur_buf_id(), editor.cur_buf().map_or("unnamed", |buf| buf.get_file_name().unwrap_or(buf.short_name())))).unwrap();
+}
 
-    let mut last_search = String::new();
-    let mut last_eval = String::new();
+/// Start selecting, unless moving with shift held already started a selection.
+fn extend_selection(editor: &mut Editor, state: &mut State) {
+    if !state.selected || !editor.is_selected() {
+        editor.select();
+        state.selected = true;
+    }
+}
 
-    let mut frontend = Terminal::default();
-    let mut selected = false;
-    let mut copied = String::new();
-    frontend.set_status(&format!("Editing in buffer #{}: {}", editor.cur_buf_id(), editor.cur_buf().unwrap().get_file_name().unwrap_or("unnamed"))).unwrap();
+/// Run one of the editor's built-in actions, as bound in the keymap.
+/// Returns whether the editor should quit.
+fn run_action(action: &str, editor: &mut Editor, frontend: &mut impl Frontend, state: &mut State) -> bool {
+    match action {
+        "quit" => return true,
 
-    loop {
-        frontend.render(&editor, false).unwrap();
+        "select-left" | "select-right" | "select-up" | "select-down" => {
+            extend_selection(editor, state);
+            match action {
+                "select-left" => editor.move_cur(Direction::Left),
+                "select-right" => editor.move_cur(Direction::Right),
+                "select-up" => editor.move_cur(Direction::Up),
+                _ => editor.move_cur(Direction::Down),
+            }
+        }
+        "select-home" => {
+            extend_selection(editor, state);
+            while editor.cur_buf().unwrap().cur_pos().1 > 0 {
+                editor.move_cur(Direction::Left);
+            }
+        }
+        "select-end" => {
+            extend_selection(editor, state);
+            while editor.cur_buf().unwrap().cur_pos().1 < editor.cur_buf().unwrap().cur_line().len() {
+                editor.move_cur(Direction::Right);
+            }
+        }
+        "select-page-down" => {
+            extend_selection(editor, state);
+            editor.page(Direction::Down);
+        }
+        "select-page-up" => {
+            extend_selection(editor, state);
+            editor.page(Direction::Up);
+        }
 
-        let input = frontend.wait_for_input(&editor);
-        // println!("{:?}", input);
-        // std::thread::sleep(std::time::Duration::from_millis(1000));
-        if Ok(Input::Alt(Box::new(Input::Char('q')))) == input {
-            break;
-        } else {
-            match input {
-                Ok(Input::Shift(shift)) => {
-                        match *shift {
-                        Input::Char(ch) => {
-                            selected = false;
-                            editor.unselect();
-                            editor.insert(ch.to_uppercase())
-                        }
-                        Input::Left | Input::Right | Input::Up | Input::Down => {
-                            if !selected || !editor.is_selected() {
-                                editor.select();
-                                selected = true;
-                            }
-                            match *shift {
-                                Input::Left => editor.move_cur(Direction::Left),
-                                Input::Right => editor.move_cur(Direction::Right),
-                                Input::Up => editor.move_cur(Direction::Up),
-                                Input::Down => editor.move_cur(Direction::Down),
-                                _ => {}
-                            }
-                        }
-                        Input::Home => {
-                            if !selected || !editor.is_selected() {
-                                editor.select();
-                                selected = true;
-                            }
-                            while editor.cur_buf().unwrap().cur_pos().1 > 0 {
-                                editor.move_cur(Direction::Left);
-                            }
-                        }
-                        Input::End => {
-                            if !selected || !editor.is_selected() {
-                                editor.select();
-                                selected = true;
-                            }
-                            while editor.cur_buf().unwrap().cur_pos().1 < editor.cur_buf().unwrap().cur_line().len() {
-                                editor.move_cur(Direction::Right);
-                            }
-                        }
-                        Input::PageDown => {
-                            if !selected || !editor.is_selected() {
-                                editor.select();
-                                selected = true;
-                            }
-                            for _ in 0..frontend.height() {
-                                editor.move_cur(Direction::Down);
-                            }
-                        }
-                        Input::PageUp => {
-                            if !selected || !editor.is_selected() {
-                                editor.select();
-                                selected = true;
-                            }
-                            for _ in 0..frontend.height() {
-                                editor.move_cur(Direction::Up);
-                            }
-                        }
-                        // other => panic!("{:?}", other),
-                        _ => {}
-                    }
-                    frontend.render(&editor, true).expect("Failed to render");
-                },
+        "page-up" => editor.page(Direction::Up),
+        "page-down" => editor.page(Direction::Down),
+        "home" => {
+            while editor.cur_buf().unwrap().cur_pos().1 > 0 {
+                editor.move_cur(Direction::Left);
+            }
+        }
+        "end" => {
+            while editor.cur_buf().unwrap().cur_pos().1 < editor.cur_buf().unwrap().cur_line().len() {
+                editor.move_cur(Direction::Right);
+            }
+        }
 
-                Ok(Input::PageUp) => {
-                    for _ in 0..frontend.height() {
-                        editor.move_cur(Direction::Up);
-                    }
+        "buffer-0" | "buffer-1" | "buffer-2" | "buffer-3" | "buffer-4" | "buffer-5" | "buffer-6" | "buffer-7"
+        | "buffer-8" | "buffer-9" => {
+            editor.set_buf(action["buffer-".len()..].parse().unwrap());
+            show_buffer(editor, frontend);
+        }
+        "shell-command" => {
+            // Get a shell command
+            if let Ok(cmd) = frontend.prompt("Enter shell command: ", None) {
+                let words = cmd.split_whitespace().collect::<Vec<&str>>();
+                // Join together the stdout and stderr
+                if let Ok(output) = std::process::Command::new(words[0])
+                    .args(&words[1..])
+                    .output() {
+
+                    let output = format!(
+                        "STDOUT:\n{}\nSTDERR:\n{}",
+                        String::from_utf8_lossy(&output.stdout),
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                    let buf = Buffer::from_text(&output);
+                    editor.add_buf(buf);
+                    editor.set_buf(editor.max_buf_id());
+                    frontend.set_status(&format!("Viewing output of: {}", cmd)).unwrap();
+                } else {
+                    frontend.set_status("Failed to run command").unwrap();
                 }
-                Ok(Input::PageDown) => {
-                    for _ in 0..frontend.height() {
-                        editor.move_cur(Direction::Down);
+            }
+        }
+        "eval" => {
+            // Get an input command from the prompt
+            if let Ok(cmd) = frontend.prompt("Enter command: ", Some(state.last_eval.clone())) {
+                state.last_eval = cmd.clone();
+                let result = parse_file(&cmd, "eval").and_then(|expr| editor.eval(expr));
+                show_result(result, editor, frontend);
+            }
+        }
+        "repl" => {
+            editor.open_repl();
+            show_buffer(editor, frontend);
+        }
+        "repl-older" => editor.recall_repl_input(true),
+        "repl-newer" => editor.recall_repl_input(false),
+        "command-palette" => {
+            let entries = palette_entries(&editor.env);
+            let width = entries.iter().map(|entry| entry.usage().chars().count()).max().unwrap_or(0);
+            let options: Vec<String> = entries.iter().map(|entry| format!("{:width$}  {}", entry.usage(), entry.help)).collect();
+            let preview_cols = frontend.width();
+            if let Ok(index) = frontend.pick_with_preview("Run command: ", &options, &|index| entries[index].describe(preview_cols)) {
+                let entry = &entries[index];
+                match prompt_args(entry, frontend) {
+                    Ok(Some(args)) => {
+                        let result = editor.eval(Expr::Apply(Box::new(symbol(&entry.name)), args));
+                        show_result(result, editor, frontend);
                     }
+                    Ok(None) => {}
+                    Err(e) => show_result(Err(e), editor, frontend),
                 }
-                Ok(Input::Home) => {
-                    while editor.cur_buf().unwrap().cur_pos().1 > 0 {
-                        editor.move_cur(Direction::Left);
-                    }
+            }
+        }
+        "next-buffer" => {
+            editor.next_buf();
+            show_buffer(editor, frontend);
+        }
+        "prev-buffer" => {
+            editor.prev_buf();
+            show_buffer(editor, frontend);
+        }
+        "pick-buffer" => {
+            let options: Vec<String> = editor
+                .buffers()
+                .iter()
+                .enumerate()
+                .map(|(id, buf)| format!(
+                    "#{} {}{}",
+                    id,
+                    buf.get_file_name().unwrap_or("unnamed"),
+                    if buf.is_edited() { " [modified]" } else { "" }
+                ))
+                .collect();
+            if let Ok(id) = frontend.pick("Switch to buffer: ", &options) {
+                editor.set_buf(id);
+            }
+            show_buffer(editor, frontend);
+        }
+        "toggle-tab-bar" => {
+            editor.set_tab_bar(!editor.tab_bar());
+            show_buffer(editor, frontend);
+        }
+        "open-path" => {
+            if let Ok(file) = frontend.prompt("Enter file name: ", None) {
+                editor.open_file(file);
+            }
+            show_buffer(editor, frontend);
+        }
+        "search-project" => {
+            let Ok(pattern) = frontend.prompt("Search project: ", Some(state.last_project_search.clone())) else {
+                return false;
+            };
+            let Ok(globs) = frontend.prompt("In files (globs, !glob to exclude): ", Some(state.last_project_globs.clone())) else {
+                return false;
+            };
+            state.last_project_search = pattern.clone();
+            state.last_project_globs = globs.clone();
+            let globs: Vec<String> = globs.split_whitespace().map(String::from).collect();
+            match Regex::new(&pattern).map_err(|e| e.to_string()).and_then(|regex| search_project(".", &regex, &globs)) {
+                Ok(matches) => {
+                    let mut results = vec![format!("{} matches for {}", matches.len(), pattern)];
+                    results.extend(matches.iter().map(ToString::to_string));
+                    editor.add_buf(Buffer::from_search_results(&results.join("\n")));
+                    editor.set_buf(editor.max_buf_id());
+                    frontend.set_status(&format!("Found {} matches for {}", matches.len(), pattern)).unwrap();
                 }
-                Ok(Input::End) => {
-                    while editor.cur_buf().unwrap().cur_pos().1 < editor.cur_buf().unwrap().cur_line().len() {
-                        editor.move_cur(Direction::Right);
-                    }
+                Err(e) => frontend.set_status(&format!("Error: {}", e)).unwrap(),
+            }
+        }
+        "replace-project" => {
+            if state.last_project_search.is_empty() {
+                frontend.set_status("Search the project with Alt-f before replacing").unwrap();
+                return false;
+            }
+            let Ok(replacement) = frontend.prompt(&format!("Replace {} with: ", state.last_project_search), None) else {
+                return false;
+            };
+            let globs: Vec<String> = state.last_project_globs.split_whitespace().map(String::from).collect();
+            let (regex, matches) = match Regex::new(&state.last_project_search).map_err(|e| e.to_string()).and_then(|regex| {
+                search_project(".", &regex, &globs).map(|matches| (regex, matches))
+            }) {
+                Ok(found) => found,
+                Err(e) => {
+                    frontend.set_status(&format!("Error: {}", e)).unwrap();
+                    return false;
                 }
+            };
 
-                Ok(Input::Char(ch)) => {
-                    selected = false;
-                    editor.unselect();
-                    editor.insert(ch);
-                }
-
-                Ok(Input::Alt(alt)) => {
-                    match *alt {
-                        Input::Char('1') => editor.set_buf(1),
-                        Input::Char('2') => editor.set_buf(2),
-                        Input::Char('3') => editor.set_buf(3),
-                        Input::Char('4') => editor.set_buf(4),
-                        Input::Char('5') => editor.set_buf(5),
-                        Input::Char('6') => editor.set_buf(6),
-                        Input::Char('7') => editor.set_buf(7),
-                        Input::Char('8') => editor.set_buf(8),
-                        Input::Char('9') => editor.set_buf(9),
-                        Input::Char('0') => editor.set_buf(0),
-                        Input::Char('!') => {
-                            // Get a shell command
-                            if let Ok(cmd) = frontend.prompt("Enter shell command: ", None) {
-                                let words = cmd.split_whitespace().collect::<Vec<&str>>();
-                                // Join together the stdout and stderr
-                                if let Ok(output) = std::process::Command::new(words[0])
-                                    .args(&words[1..])
-                                    .output() {
-
-                                    let output = format!(
-                                        "STDOUT:\n{}\nSTDERR:\n{}",
-                                        String::from_utf8_lossy(&output.stdout),
-                                        String::from_utf8_lossy(&output.stderr)
-                                    );
-                                    let buf = Buffer::from_text(&output);
-                                    editor.add_buf(buf);
-                                    editor.set_buf(editor.max_buf_id());
-                                    frontend.set_status(&format!("Viewing output of: {}", cmd)).unwrap();
-                                } else {
-                                    frontend.set_status("Failed to run command").unwrap();
-                                }
-                            }
-                            continue;
-                        },
-                        Input::Char('e') => {
-                            // Get an input command from the prompt
-                            if let Ok(cmd) = frontend.prompt("Enter command: ", Some(last_eval.clone())) {
-                                last_eval = cmd.clone();
-                                match parse(&cmd) {
-                                    Ok(expr) => match editor.eval(expr) {
-                                        Ok(result) => {
-                                            frontend.set_status(&format!("Result: {result}")).unwrap();
-                                        }
-                                        Err(e) => {
-                                            frontend.set_status(&format!("Error: {}", e)).unwrap();
-                                        }
-                                    },
-                                    Err(e) => {
-                                        frontend.set_status(&format!("Syntax Error: {}", e)).unwrap();
-                                    }
-                                }
-                            }
-                            continue;
-                        }
-                        Input::Char('n') => {
-                            editor.next_buf();
-                        },
-                        Input::Char('p') => {
-                            editor.prev_buf();
-                        },
-                        _ => {}
-                    }
-                    frontend.set_status(&format!("Editing in buffer #{}: {}", editor.cur_buf_id(), editor.cur_buf().unwrap().get_file_name().unwrap_or("unnamed"))).unwrap();
-                }
-
-                Ok(Input::Control(ctrl)) => {
-                    match *ctrl {
-                        Input::Char('s') => {
-                            let buf = editor.cur_buf_mut().unwrap();
-                            match buf.get_file_name().map(String::from) {
-                                Some(file_name) => {
-                                    buf.save(&file_name).unwrap();
-                                },
-                                None => {
-                                    if let Ok(file_name) = frontend.prompt("Enter file name: ", None) {
-                                        buf.save(&file_name).unwrap();
-                                    }
-                                }
-                            }
-                        },
-                        Input::Char('q') => {
-                            if !editor.cur_buf().unwrap().is_edited() {
-                                editor.quit_buf(false);
-                                frontend.set_status(&format!("Editing in buffer #{}: {}", editor.cur_buf_id(), editor.cur_buf().unwrap().get_file_name().unwrap_or("unnamed"))).unwrap();
-                                continue;
-                            }
-
-                            let should_save = frontend.ask("Do you want to save the buffer?", "y", "n").unwrap();
-                            if should_save && editor.cur_buf().unwrap().get_file_name().is_none() {
-                                if let Ok(filename) = frontend.prompt("Enter file name: ", None) {
-                                    editor.cur_buf_mut().unwrap().set_file_name(filename);
-                                    editor.quit_buf(should_save);
-                                }
-                            } else {
-                                editor.quit_buf(should_save);
-                            }
-                            frontend.set_status(&format!("Editing in buffer #{}: {}", editor.cur_buf_id(), editor.cur_buf().unwrap().get_file_name().unwrap_or("unnamed"))).unwrap();
-                        },
-                        Input::Char('o') => {
-                            if let Ok(file) = frontend.prompt("Enter file name: ", None) {
-                                editor.add_buf(Buffer::from_file_name(file));
-                                editor.set_buf(editor.max_buf_id());
-                                frontend.set_status(&format!("Editing in buffer #{}: {}", editor.cur_buf_id(), editor.cur_buf().unwrap().get_file_name().unwrap_or("unnamed"))).unwrap();
-                            }
-                        },
-
-                        Input::Char('a') => {
-                            editor.goto_cur((0, 0));
-                            editor.select();
-                            let lines = editor.cur_buf().unwrap().content();
-                            let row = lines.len() - 1;
-                            let col = lines[row].len();
-                            editor.goto_cur((row, col));
-                        },
-
-                        Input::Char('f') => {
-                            // Prompt the user for a search string
-                            if let Ok(search) = frontend.prompt("Search: ", Some(last_search.clone())) {
-                                last_search = search.clone();
-                                let buf = editor.cur_buf().unwrap();
-                                match buf.find(&search) {
-                                    Some((row, col)) => {
-                                        editor.goto_cur((row, col));
-                                    },
-                                    None => {
-                                        frontend.set_status("Not found").unwrap();
-                                    }
-                                }
-                            }
-
-                        },
-                        Input::Char('n') => {
-                            editor.new_buf();
-                            editor.set_buf(editor.max_buf_id());
-                        },
-                        Input::Char('z') => {
-                            editor.undo();
-                            frontend.render(&editor, true).expect("Failed to render");
-                        },
-                        Input::Char('y') => {
-                            editor.redo();
-                            frontend.render(&editor, true).expect("Failed to render");
-                        },
-                        Input::Char('c') => {
-                            if let Some(selected) = editor.get_selected() {
-                                copied = selected.clone();
-                            }
-                        }
-                        Input::Char('d') => {
-                            if let Some(selected_text) = editor.get_selected() {
-                                let size = selected_text.len();
-                                editor.goto_cur(editor.selection_end().unwrap());
-                                editor.delete(size);
-                                editor.unselect();
-                                selected = false;
-                                frontend.render(&editor, true).expect("Failed to render");
-                            } else {
-                                editor.delete(1);
-                            }
-                        }
-                        Input::Char('x') => {
-                            if let Some(selected_text) = editor.get_selected() {
-                                copied = selected_text.clone();
-                                let size = selected_text.len();
-                                editor.goto_cur(editor.selection_end().unwrap());
-                                editor.delete(size);
-                                editor.unselect();
-                                selected = false;
-                                frontend.render(&editor, true).expect("Failed to render");
-                            }
+            // Files with unsaved changes in a buffer are left alone
+            let mut files: Vec<String> = matches.iter().map(|found| found.path.clone()).collect();
+            files.dedup();
+            let (files, skipped): (Vec<String>, Vec<String>) = files.into_iter().partition(|file| {
+                editor.find_buf(file).is_none_or(|id| !editor.buffers()[id].is_edited())
+            });
+            let matches: Vec<SearchMatch> = matches.into_iter().filter(|found| files.contains(&found.path)).collect();
+
+            editor.add_buf(Buffer::from_search_results(&replace_preview(&matches, &regex, &replacement).join("\n")));
+            editor.set_buf(editor.max_buf_id());
+            editor.resize_view(frontend.width(), frontend.height());
+            frontend.render(editor, false).expect("Failed to render");
+            let question = format!("Replace {} matches in {} files?", matches.len(), files.len());
+            if let Ok(true) = frontend.ask(&question, "y", "n") {
+                match replace_in_files(".", &files, &regex, &replacement) {
+                    Ok(changed) => {
+                        for file in &files {
+                            editor.reload_file(file);
                         }
-                        Input::Char('v') if !copied.is_empty() => {
-                            editor.insert(&copied);
-                            selected = false;
-                            editor.unselect();
-                        },
-                        _ => {}
+                        frontend.set_status(&if skipped.is_empty() {
+                            format!("Changed {} files", changed)
+                        } else {
+                            format!("Changed {} files, skipped unsaved: {}", changed, skipped.join(", "))
+                        }).unwrap();
                     }
+                    Err(e) => frontend.set_status(&format!("Error: {}", e)).unwrap(),
                 }
+            } else {
+                frontend.set_status("Replace cancelled").unwrap();
+            }
+        }
 
-                Ok(Input::Enter) => {
-                    selected = false;
-                    editor.unselect();
-                    editor.insert('\n');
+        "save" => {
+            match editor.cur_buf().unwrap().get_file_name().map(String::from) {
+                Some(file_name) => {
+                    editor.save(&file_name).unwrap();
+                },
+                None => {
+                    if let Ok(file_name) = frontend.prompt("Enter file name: ", None) {
+                        editor.save(&file_name).unwrap();
+                    }
                 }
+            }
+        }
+        "close-buffer" => {
+            if !editor.cur_buf().unwrap().is_edited() {
+                editor.quit_buf(false);
+                show_buffer(editor, frontend);
+                return false;
+            }
 
-                Ok(Input::Tab) => {
-                    selected = false;
-                    editor.unselect();
-                    editor.insert("    ");
+            let should_save = frontend.ask("Do you want to save the buffer?", "y", "n").unwrap();
+            if should_save && editor.cur_buf().unwrap().get_file_name().is_none() {
+                if let Ok(filename) = frontend.prompt("Enter file name: ", None) {
+                    editor.cur_buf_mut().unwrap().set_file_name(filename);
+                    editor.quit_buf(should_save);
                 }
-
-                Ok(Input::Backspace) => {
-                    // If we are at the beginning of the document, do nothing.
-                    if editor.cur_buf().unwrap().cur_pos() == (0, 0) {
-                        continue;
-                    }
-
-                    if let Some(end) = editor.selection_end() {
-                        let size = editor.get_selected().unwrap().len();
-                        editor.goto_cur(end);
-                        editor.delete(size);
-                    } else {
-                        editor.delete(1);
+            } else {
+                editor.quit_buf(should_save);
+            }
+            show_buffer(editor, frontend);
+        }
+        "open-file" => {
+            let files = project_files(".");
+            let preview_rows = frontend.height();
+            if let Ok(index) = frontend.pick_with_preview("Open file: ", &files, &|index| preview_file(&files[index], preview_rows)) {
+                editor.open_file(files[index].clone());
+                show_buffer(editor, frontend);
+            }
+        }
+        "select-all" => {
+            editor.goto_cur((0, 0));
+            editor.select();
+            let lines = editor.cur_buf().unwrap().content();
+            let row = lines.len() - 1;
+            let col = lines[row].len();
+            editor.goto_cur((row, col));
+        }
+        "find" => {
+            // Prompt the user for a search string
+            if let Ok(search) = frontend.prompt("Search: ", Some(state.last_search.clone())) {
+                state.last_search = search.clone();
+                let buf = editor.cur_buf().unwrap();
+                match buf.find(&search) {
+                    Some((row, col)) => {
+                        editor.goto_cur((row, col));
+                    },
+                    None => {
+                        frontend.set_status("Not found").unwrap();
                     }
-                    selected = false;
-                    editor.unselect();
                 }
+            }
+        }
+        "new-buffer" => {
+            editor.new_buf();
+            editor.set_buf(editor.max_buf_id());
+        }
+        "center" => editor.center_view(),
+        "undo" => editor.undo(),
+        "redo" => editor.redo(),
+        "copy" => {
+            if let Some(selected) = editor.get_selected() {
+                state.copied = selected.clone();
+            }
+        }
+        "delete-selection" => {
+            if let Some(selected_text) = editor.get_selected() {
+                let size = selected_text.len();
+                editor.goto_cur(editor.selection_end().unwrap());
+                editor.delete(size);
+                editor.unselect();
+                state.selected = false;
+            } else {
+                editor.delete(1);
+            }
+        }
+        "cut" => {
+            if let Some(selected_text) = editor.get_selected() {
+                state.copied = selected_text.clone();
+                let size = selected_text.len();
+                editor.goto_cur(editor.selection_end().unwrap());
+                editor.delete(size);
+                editor.unselect();
+                state.selected = false;
+            }
+        }
+        "paste" => {
+            if !state.copied.is_empty() {
+                editor.insert(&state.copied);
+                state.selected = false;
+                editor.unselect();
+            }
+        }
 
-                Ok(Input::Delete) => {
-                    if let Some(end) = editor.selection_end() {
-                        let size = editor.get_selected().unwrap().len();
-                        editor.goto_cur(end);
-                        editor.delete(size);
-                    } else {
-                        editor.move_cur(Direction::Right);
-                        editor.delete(1);
-                    }
-                    selected = false;
-                    editor.unselect();
-                }
+        "enter" if editor.cur_buf().unwrap().is_repl() => {
+            state.selected = false;
+            editor.unselect();
+            // Code that isn't finished yet goes on over more lines
+            if !editor.run_repl_input() {
+                editor.insert('\n');
+            }
+        }
+        "enter" if editor.cur_buf().unwrap().is_results() => {
+            editor.open_result();
+            show_buffer(editor, frontend);
+        }
+        "enter" if editor.cur_buf().unwrap().is_dir() => {
+            editor.open_dir_entry();
+            show_buffer(editor, frontend);
+        }
+        "enter" => {
+            state.selected = false;
+            editor.unselect();
+            editor.insert('\n');
+        }
+        "tab" => {
+            state.selected = false;
+            editor.unselect();
+            editor.insert("    ");
+        }
+        "backspace" => {
+            // If we are at the beginning of the document, do nothing.
+            if editor.cur_buf().unwrap().cur_pos() == (0, 0) {
+                return false;
+            }
 
-                Ok(Input::Left) => {
-                    selected = false;
-                    editor.unselect();
-                    editor.move_cur(Direction::Left);
-                }
+            if let Some(end) = editor.selection_end() {
+                let size = editor.get_selected().unwrap().len();
+                editor.goto_cur(end);
+                editor.delete(size);
+            } else {
+                editor.delete(1);
+            }
+            state.selected = false;
+            editor.unselect();
+        }
+        "delete" => {
+            if let Some(end) = editor.selection_end() {
+                let size = editor.get_selected().unwrap().len();
+                editor.goto_cur(end);
+                editor.delete(size);
+            } else {
+                editor.move_cur(Direction::Right);
+                editor.delete(1);
+            }
+            state.selected = false;
+            editor.unselect();
+        }
+        "left" | "right" | "up" | "down" => {
+            state.selected = false;
+            editor.unselect();
+            editor.move_cur(match action {
+                "left" => Direction::Left,
+                "right" => Direction::Right,
+                "up" => Direction::Up,
+                _ => Direction::Down,
+            });
+        }
+        _ => frontend.set_status(&format!("Unknown action {}", action)).unwrap(),
+    }
+    false
+}
 
-                Ok(Input::Right) => {
-                    selected = false;
-                    editor.unselect();
-                    editor.move_cur(Direction::Right);
-                }
+/// Handle a key pressed in a directory buffer: `n` creates a file (or a
+/// directory, if the name ends in `/`), `r` renames the entry under the
+/// cursor, `d` deletes it and `g` lists the directory again.
+fn dir_command(ch: char, editor: &mut Editor, frontend: &mut impl Frontend) {
+    let buf = editor.cur_buf().unwrap();
+    let entry = buf.dir_entry().cloned();
+    let root = buf.dir_tree().unwrap().root().to_path_buf();
 
-                Ok(Input::Up) => {
-                    selected = false;
-                    editor.unselect();
-                    editor.move_cur(Direction::Up);
+    let result = match (ch, entry) {
+        ('n', entry) => {
+            let parent = match entry {
+                Some(entry) if entry.is_dir => entry.path,
+                Some(entry) => entry.path.parent().map(PathBuf::from).unwrap_or(root),
+                None => root,
+            };
+            match frontend.prompt(&format!("New file in {}/: ", parent.display()), None) {
+                Ok(name) if !name.is_empty() => {
+                    let path = parent.join(&name);
+                    // Cancelling the question, or input closing, counts as no
+                    let Ok(true) = frontend.ask(&format!("Create {}?", path.display()), "y", "n") else {
+                        return;
+                    };
+                    if name.ends_with('/') {
+                        std::fs::create_dir_all(&path)
+                    } else {
+                        std::fs::File::create_new(&path).map(|_| ())
+                    }
+                    .map(|_| format!("Created {}", path.display()))
                 }
-
-                Ok(Input::Down) => {
-                    selected = false;
-                    editor.unselect();
-                    editor.move_cur(Direction::Down);
+                _ => return,
+            }
+        }
+        ('r', Some(entry)) => {
+            let name = entry.path.file_name().unwrap().to_string_lossy().into_owned();
+            match frontend.prompt("Rename to: ", Some(name)) {
+                Ok(name) if !name.is_empty() => {
+                    let path = entry.path.with_file_name(&name);
+                    let Ok(true) = frontend.ask(&format!("Rename {} to {}?", entry.path.display(), path.display()), "y", "n") else {
+                        return;
+                    };
+                    let open = editor.find_buf(&entry.path.to_string_lossy());
+                    std::fs::rename(&entry.path, &path).map(|_| {
+                        // Keep any buffer editing the file pointed at it
+                        if let Some(id) = open {
+                            editor.rename_buf(id, path.to_string_lossy().into_owned());
+                        }
+                        format!("Renamed {} to {}", entry.path.display(), path.display())
+                    })
                 }
-
-                _ => {}
+                _ => return,
             }
         }
-    }
-
-    frontend.exit();
-
-    Ok(())
-
-    // let mut buf = Buffer::default();
+        ('d', Some(entry)) => {
+            let Ok(true) = frontend.ask(&format!("Delete {}?", entry.path.display()), "y", "n") else {
+                return;
+            };
+            if entry.is_dir {
+                std::fs::remove_dir_all(&entry.path)
+            } else {
+                std::fs::remove_file(&entry.path)
+            }
+            .map(|_| format!("Deleted {}", entry.path.display()))
+        }
+        ('g', _) => Ok(format!("Refreshed {}", root.display())),
+        _ => return,
+    };
 
-    // Change::Insert("Hello world!\n".to_string()).apply(&mut buf);
-    // Change::move_cur(Direction::Left, &buf).apply(&mut buf);
-    // Change::Insert(" testing".to_string()).apply(&mut buf);
-    // Change::move_cur(Direction::Down, &buf).apply(&mut buf);
+    editor.refresh_dirs();
+    match result {
+        Ok(status) => frontend.set_status(&status).unwrap(),
+        Err(e) => frontend.set_status(&format!("Error: {}", e)).unwrap(),
+    }
+}
 
-    // Change::Undo.apply(&mut buf);
-    // Change::Undo.apply(&mut buf);
-    // Change::Undo.apply(&mut buf);
-    // Change::Undo.apply(&mut buf);
-    // // Change::Redo.apply(&mut buf);
-    // // Change::Undo.apply(&mut buf);
-    // // Change::Redo.apply(&mut buf);
-    // // Change::Redo.apply(&mut buf);
-    // // Change::Redo.apply(&mut buf);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lite::{help_problems, run};
 
-    // println!("{:#?}", buf);
-    // println!("selected: {:?}", buf.selected());
+    // The command palette shows the help of every builtin as its documentation
+    #[test]
+    fn builtins_have_help() {
+        let mut editor = Editor::new();
+        add_builtins(&mut editor);
+        assert_eq!(help_problems(&editor.env), Vec::<String>::new());
+    }
 
-    // println!("{:?}", f64::from(Float::from(1232.1221394873219847334213)) + f64::from(Float::from(1234.231234321)));
-    // println!("{:?}", 1232.1221394873219847334213 + 1234.231234321)
-}
\ No newline at end of file
+    // Errors from the editor's builtins say where they were raised, like
+    // any other, on both paths
+    #[test]
+    fn builtin_errors_have_locations() {
+        let cases = [
+            ("bind 1 2", "TypeMismatch", "input:1:1"),
+            ("1;\nafter \"x\" (() -> 2)", "TypeMismatch", "input:2:1"),
+            ("bind \"Ctrl-q\" \"nope\"", "UnknownAction", "input:1:1"),
+        ];
+        for (code, kind, at) in cases {
+            for evaluate in [eval, run] {
+                let mut editor = Editor::new();
+                add_builtins(&mut editor);
+                let mut env = editor.env.clone();
+                let Err(Expr::Dict(error)) = evaluate(parse(code).unwrap(), &mut editor, &mut env) else {
+                    panic!("{code} didn't raise an error");
+                };
+                assert_eq!(error.get(&symbol("kind")), Some(&string(kind)));
+                assert_eq!(error.get(&symbol("at")), Some(&string(at)));
+            }
+        }
+    }
+}
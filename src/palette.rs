@@ -0,0 +1,112 @@
+use super::{Env, Expr};
+use std::collections::BTreeMap;
+
+/// A command the palette can run: a builtin, or a function defined by a script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaletteEntry {
+    pub name: String,
+    /// The arguments to ask for, named like a builtin's `params`.
+    pub params: Vec<String>,
+    pub help: String,
+    pub help_long: String,
+}
+
+impl PaletteEntry {
+    /// How to call it, like `goto row col`.
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.clone();
+        for param in &self.params {
+            usage.push(' ');
+            usage.push_str(param);
+        }
+        usage
+    }
+
+    /// The usage and the long help, wrapped to a width, for previewing the entry.
+    pub fn describe(&self, width: usize) -> Vec<String> {
+        let mut lines = vec![self.usage(), String::new()];
+        lines.extend(wrap(&self.help_long, width));
+        lines
+    }
+}
+
+/// Every builtin and script function in scope, in order of their names.
+pub fn palette_entries(env: &Env) -> Vec<PaletteEntry> {
+    let mut entries = vec![];
+    for (key, value) in &env.bindings() {
+        let Expr::Symbol(name) = key else {
+            continue;
+        };
+        let entry = match value {
+            Expr::Builtin(builtin) => PaletteEntry {
+                name: name.clone(),
+                params: builtin.params.clone(),
+                help: builtin.help.clone(),
+                help_long: builtin.help_long.clone(),
+            },
+            Expr::Fn(params, _, _) | Expr::Proc(params, _) | Expr::Macro(params, _) => PaletteEntry {
+                name: name.clone(),
+                // Scripts name a parameter `_` when they don't use it
+                params: params
+                    .iter()
+                    .filter_map(|param| match param {
+                        Expr::Symbol(param) if param != "_" => Some(param.clone()),
+                        Expr::Assign(param, _) => Some(format!("[{param:?}]")),
+                        Expr::Rest(param) => Some(format!("{param:?}...")),
+                        _ => None,
+                    })
+                    .collect(),
+                help: String::from("Defined in a script"),
+                help_long: format!("{value:?}"),
+            },
+            _ => continue,
+        };
+        entries.push(entry);
+    }
+    entries
+}
+
+/// Problems with the help of the builtins in scope, so that the palette
+/// documents them properly: builtins that go by another name than their
+/// own, and ones whose help is empty or the same as another's.
+pub fn help_problems(env: &Env) -> Vec<String> {
+    let mut problems = vec![];
+    let bindings = env.bindings();
+    let mut helps: BTreeMap<&str, &str> = BTreeMap::new();
+    for (key, value) in &bindings {
+        let (Expr::Symbol(name), Expr::Builtin(builtin)) = (key, value) else {
+            continue;
+        };
+        if builtin.name != *name {
+            problems.push(format!("{} is named {}", name, builtin.name));
+        }
+        if builtin.help.is_empty() || builtin.help_long.is_empty() {
+            problems.push(format!("{} has no help", name));
+        }
+        for help in [&builtin.help, &builtin.help_long] {
+            if let Some(other) = helps.insert(help, name) {
+                problems.push(format!("{} has the same help as {}", name, other));
+            }
+        }
+    }
+    problems
+}
+
+/// Break text into lines of at most `width` characters, between words.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
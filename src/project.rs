@@ -0,0 +1,212 @@
+use ignore::{overrides::OverrideBuilder, Walk, WalkBuilder};
+use regex::Regex;
+use std::{
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+/// Walk the files under `root`, skipping hidden files and anything excluded
+/// by `.gitignore`. If any globs are given, only files matching them are
+/// walked; globs starting with `!` exclude files instead.
+fn walk(root: &Path, globs: &[String]) -> Result<Walk, String> {
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in globs {
+        overrides.add(glob).map_err(|e| e.to_string())?;
+    }
+    let overrides = overrides.build().map_err(|e| e.to_string())?;
+    Ok(WalkBuilder::new(root).overrides(overrides).build())
+}
+
+/// List every file under `root`, skipping hidden files and anything
+/// excluded by `.gitignore`. Paths are relative to `root` and sorted.
+pub fn project_files(root: impl AsRef<Path>) -> Vec<String> {
+    let root = root.as_ref();
+    let mut files: Vec<String> = WalkBuilder::new(root)
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .filter_map(|entry| {
+            entry
+                .path()
+                .strip_prefix(root)
+                .ok()
+                .map(|path| path.to_string_lossy().into_owned())
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// The most of a file read for a preview, so a huge file with long lines
+/// can't stall the picker.
+const PREVIEW_BYTES: u64 = 64 * 1024;
+
+/// Read the first `count` lines of a file, for previewing it. Only the start
+/// of the file is read, and files that aren't text aren't shown.
+pub fn preview_file(file: impl AsRef<Path>, count: usize) -> Vec<String> {
+    let file = match File::open(file) {
+        Ok(file) => file,
+        Err(e) => return vec![e.to_string()],
+    };
+    let mut reader = BufReader::new(file.take(PREVIEW_BYTES));
+    let mut lines = vec![];
+    let mut line = vec![];
+    while lines.len() < count {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => return vec![e.to_string()],
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+        let text = match std::str::from_utf8(&line) {
+            Ok(text) if !text.contains('\0') => text,
+            // A character cut off where the preview stops reading
+            Err(e) if e.error_len().is_none() => std::str::from_utf8(&line[..e.valid_up_to()]).unwrap(),
+            _ => return vec![String::from("(not a text file)")],
+        };
+        lines.push(text.to_string());
+    }
+    lines
+}
+
+/// A line of a project file that matched a search.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub path: String,
+    pub line: usize,
+    pub col: usize,
+    pub text: String,
+}
+
+impl fmt::Display for SearchMatch {
+    /// Matches are shown as `path:line:col: text`, counting from 1.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.path, self.line + 1, self.col + 1, self.text)
+    }
+}
+
+/// Search every line of every project file under `root` for `pattern`.
+/// See `walk` for how files are chosen. Files that aren't valid UTF-8
+/// are skipped.
+pub fn search_project(
+    root: impl AsRef<Path>,
+    pattern: &Regex,
+    globs: &[String],
+) -> Result<Vec<SearchMatch>, String> {
+    let root = root.as_ref();
+    let mut files: Vec<_> = walk(root, globs)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .map(|entry| entry.into_path())
+        .collect();
+    files.sort();
+
+    let mut matches = vec![];
+    for file in files {
+        let Ok(text) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        let path = file.strip_prefix(root).unwrap_or(&file).to_string_lossy().into_owned();
+        for (line, text) in text.lines().enumerate() {
+            if let Some(found) = pattern.find(text) {
+                matches.push(SearchMatch {
+                    path: path.clone(),
+                    line,
+                    col: found.start(),
+                    text: text.to_string(),
+                });
+            }
+        }
+    }
+    Ok(matches)
+}
+
+/// Parse a `path:line:col: text` search result back into a path and
+/// a zero-based position.
+pub fn parse_location(line: &str) -> Option<(String, (usize, usize))> {
+    let mut parts = line.splitn(4, ':');
+    let path = parts.next()?;
+    let row = parts.next()?.parse::<usize>().ok()?;
+    let col = parts.next()?.parse::<usize>().ok()?;
+    Some((
+        path.to_string(),
+        (row.saturating_sub(1), col.saturating_sub(1)),
+    ))
+}
+
+/// Show what replacing `pattern` with `replacement` would do to each
+/// matched line, as removed and added lines.
+pub fn replace_preview(matches: &[SearchMatch], pattern: &Regex, replacement: &str) -> Vec<String> {
+    let mut lines = vec![];
+    for found in matches {
+        lines.push(format!("{}:{}:{}:", found.path, found.line + 1, found.col + 1));
+        lines.push(format!("- {}", found.text));
+        lines.push(format!("+ {}", pattern.replace_all(&found.text, replacement)));
+    }
+    lines
+}
+
+/// Replace `pattern` with `replacement` on every line of the given files,
+/// relative to `root`. Returns the number of files that changed.
+pub fn replace_in_files(
+    root: impl AsRef<Path>,
+    files: &[String],
+    pattern: &Regex,
+    replacement: &str,
+) -> Result<usize, String> {
+    let mut changed = 0;
+    for file in files {
+        let path = root.as_ref().join(file);
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", file, e))?;
+        let replaced: String = text
+            .split_inclusive('\n')
+            .map(|line| {
+                let (body, newline) = match line.strip_suffix('\n') {
+                    Some(body) => (body, "\n"),
+                    None => (line, ""),
+                };
+                format!("{}{}", pattern.replace_all(body, replacement), newline)
+            })
+            .collect();
+        if replaced != text {
+            std::fs::write(&path, replaced).map_err(|e| format!("{}: {}", file, e))?;
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previews_the_start_of_text_files() {
+        let dir = std::env::temp_dir().join(format!("lite-preview-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let text = dir.join("text.txt");
+        std::fs::write(&text, "héllo\r\nworld\nmore\n").unwrap();
+        assert_eq!(preview_file(&text, 2), vec!["héllo", "world"]);
+        assert_eq!(preview_file(&text, 10), vec!["héllo", "world", "more"]);
+
+        let binary = dir.join("binary.bin");
+        std::fs::write(&binary, [0x7f, b'E', b'L', b'F', 0, 0xff, b'\n']).unwrap();
+        assert_eq!(preview_file(&binary, 2), vec!["(not a text file)"]);
+
+        // Only the start of a long line is read
+        let long = dir.join("long.txt");
+        std::fs::write(&long, "é".repeat(PREVIEW_BYTES as usize)).unwrap();
+        assert_eq!(preview_file(&long, 2), vec!["é".repeat(PREVIEW_BYTES as usize / 2)]);
+
+        assert_eq!(preview_file(dir.join("missing"), 2).len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use super::Expr;
+
+/// The prompt the input of a REPL buffer is typed after.
+pub const REPL_PROMPT: &str = "> ";
+
+/// What a REPL buffer keeps besides its text: where the input being typed
+/// starts, the inputs run before, and the buffer that code runs against.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Repl {
+    /// The row of the prompt that the input being typed follows.
+    pub input_row: usize,
+    /// The buffer that is current while code runs, so that `insert` and
+    /// the other editing builtins change it rather than the REPL.
+    pub target: Option<usize>,
+    history: Vec<String>,
+    /// Which input of the history was brought back last, while going through it.
+    recalled: Option<usize>,
+}
+
+impl Repl {
+    pub fn new(target: usize) -> Self {
+        Self {
+            target: Some(target),
+            ..Self::default()
+        }
+    }
+
+    /// Where the input starts, just after the prompt.
+    pub fn input_start(&self) -> (usize, usize) {
+        (self.input_row, REPL_PROMPT.len())
+    }
+
+    /// Remember an input that ran, and go back to the end of the history.
+    pub fn remember(&mut self, input: &str) {
+        if self.history.last().map(String::as_str) != Some(input) {
+            self.history.push(input.to_string());
+        }
+        self.recalled = None;
+    }
+
+    /// The input before the one brought back last, or the one after it.
+    /// Going past the newest input gives an empty one.
+    pub fn recall(&mut self, older: bool) -> Option<String> {
+        let index = match (self.recalled, older) {
+            (None, true) => self.history.len().checked_sub(1)?,
+            (Some(index), true) => index.saturating_sub(1),
+            (None, false) => return None,
+            (Some(index), false) if index + 1 < self.history.len() => index + 1,
+            (Some(_), false) => {
+                self.recalled = None;
+                return Some(String::new());
+            }
+        };
+        self.recalled = Some(index);
+        Some(self.history[index].clone())
+    }
+}
+
+/// Whether code closes every bracket and string it opens, so that Enter
+/// runs it rather than starting another line of it.
+pub fn is_complete(code: &str) -> bool {
+    let mut depth = 0;
+    let mut chars = code.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '#' => {
+                // Comments run to the end of the line
+                chars.by_ref().find(|&ch| ch == '\n');
+            }
+            '"' => loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => {
+                        chars.next();
+                    }
+                    Some(_) => {}
+                    None => return false,
+                }
+            },
+            _ => {}
+        }
+    }
+    // Too many closing brackets is a syntax error to show, not a reason to wait
+    depth <= 0
+}
+
+/// Lines showing a value, with the items of lists and dicts that don't
+/// fit in `width` on lines of their own.
+pub fn pretty(expr: &Expr, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    pretty_into(expr, width, "", &mut lines);
+    lines
+}
+
+fn pretty_into(expr: &Expr, width: usize, indent: &str, lines: &mut Vec<String>) {
+    let flat = format!("{indent}{expr:?}");
+    let (open, close, items): (_, _, Vec<(Option<&Expr>, &Expr)>) = match expr {
+        Expr::List(items) => ("[", "]", items.iter().map(|item| (None, item)).collect()),
+        Expr::Dict(dict) => ("{", "}", dict.iter().map(|(key, value)| (Some(key), value)).collect()),
+        _ => ("", "", vec![]),
+    };
+    if flat.chars().count() <= width || items.is_empty() {
+        lines.push(flat);
+        return;
+    }
+    lines.push(format!("{indent}{open}"));
+    let inner = format!("{indent}  ");
+    for (key, value) in items {
+        let start = lines.len();
+        pretty_into(value, width.saturating_sub(1), &inner, lines);
+        if let Some(key) = key {
+            lines[start].replace_range(..inner.len(), &format!("{inner}{key:?}: "));
+        }
+        lines.last_mut().unwrap().push(',');
+    }
+    lines.push(format!("{indent}{close}"));
+}
+
+/// Lines showing an error. Errors raised by `err` are shown by their
+/// kind, followed by the expression they're about, the line of code they
+/// were raised at and the calls they were raised in.
+pub fn pretty_error(error: &Expr, width: usize) -> Vec<String> {
+    if let Expr::Dict(dict) = error {
+        let get = |key: &str| dict.get(&Expr::Symbol(String::from(key)));
+        if let (Some(Expr::String(kind)), Some(expr)) = (get("kind"), get("expr")) {
+            let mut lines = vec![format!("{kind}:")];
+            match expr {
+                // Messages like parse errors are shown as they are
+                Expr::String(message) => lines.extend(message.lines().map(|line| format!("  {line}"))),
+                expr => lines.extend(pretty(expr, width.saturating_sub(2)).into_iter().map(|line| format!("  {line}"))),
+            }
+            if let Some(Expr::String(at)) = get("at") {
+                lines.push(format!("at {at}"));
+                if let Some(Expr::String(source)) = get("source") {
+                    let col = at.rsplit(':').next().and_then(|col| col.parse::<usize>().ok()).unwrap_or(1);
+                    lines.push(format!("  {source}"));
+                    lines.push(format!("  {}^", " ".repeat(col - 1)));
+                }
+            }
+            if let Some(Expr::List(stack)) = get("stack") {
+                lines.extend(stack.iter().map(|call| format!("in {call}")));
+            }
+            return lines;
+        }
+    }
+    match error {
+        Expr::String(message) => message.lines().map(String::from).collect(),
+        error => pretty(error, width),
+    }
+}
+
+/// A line summing up an error, for the status line.
+pub fn error_summary(error: &Expr) -> String {
+    if let Expr::Dict(dict) = error {
+        let get = |key: &str| dict.get(&Expr::Symbol(String::from(key)));
+        if let (Some(Expr::String(kind)), Some(expr)) = (get("kind"), get("expr")) {
+            let expr = match expr {
+                Expr::String(message) => message.lines().next().unwrap_or_default().to_string(),
+                expr => format!("{expr:?}"),
+            };
+            return match get("at") {
+                Some(Expr::String(at)) => format!("{kind}: {expr} at {at}"),
+                _ => format!("{kind}: {expr}"),
+            };
+        }
+    }
+    error.to_string()
+}
@@ -0,0 +1,338 @@
+use super::{ColorDepth, Style};
+use crossterm::{
+    cursor::{Hide, MoveTo, SetCursorStyle, Show},
+    queue,
+    style::{Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor},
+    terminal::{Clear, ClearType},
+    Command,
+};
+use std::{fmt, io};
+use unicode_width::UnicodeWidthChar;
+
+/// How many cells a character takes on screen: two for wide characters
+/// like CJK and most emoji, none for combining marks. Control characters
+/// are drawn as a blank, so they take one.
+pub fn char_width(ch: char) -> usize {
+    if ch.is_control() {
+        1
+    } else {
+        ch.width().unwrap_or(0)
+    }
+}
+
+/// How many cells text takes on screen.
+pub fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// The cell that the character at byte `index` of `text` is drawn at.
+/// An index inside a character counts as the start of it.
+pub fn display_col(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    display_width(&text[..index])
+}
+
+/// The byte index in `text` of the character drawn at cell `col`, or the
+/// length of the text if it doesn't reach that far.
+pub fn byte_col(text: &str, col: usize) -> usize {
+    let mut used = 0;
+    for (i, ch) in text.char_indices() {
+        used += char_width(ch);
+        if used > col {
+            return i;
+        }
+    }
+    text.len()
+}
+
+/// A command that switches to drawing in a theme's style, with its
+/// colors fitted to what the terminal can show.
+pub struct SetStyle(pub Style, pub ColorDepth);
+
+impl Command for SetStyle {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        let SetStyle(style, depth) = self;
+        SetAttribute(Attribute::Reset).write_ansi(f)?;
+        SetForegroundColor(style.fg.map_or(Color::Reset, |c| depth.fit(c))).write_ansi(f)?;
+        SetBackgroundColor(style.bg.map_or(Color::Reset, |c| depth.fit(c))).write_ansi(f)?;
+        if style.bold {
+            SetAttribute(Attribute::Bold).write_ansi(f)?;
+        }
+        if style.italic {
+            SetAttribute(Attribute::Italic).write_ansi(f)?;
+        }
+        if style.underline {
+            SetAttribute(Attribute::Underlined).write_ansi(f)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn execute_winapi(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// One character on the screen and how it's drawn. A wide character fills
+/// its cell and the one after it, which holds `WIDE_CONTINUATION`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+}
+
+/// The character in the second cell of a wide character. It's never printed.
+pub const WIDE_CONTINUATION: char = '\0';
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self { ch: ' ', style: Style::default() }
+    }
+}
+
+/// How the cursor is drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CursorShape {
+    /// Whatever the terminal draws by default.
+    #[default]
+    Default,
+    Block,
+    Bar,
+}
+
+/// A frame of the terminal: a grid of cells and where the cursor is.
+/// A frontend draws each frame into a `Screen`, then writes only the
+/// cells that differ from the frame before it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Screen {
+    cols: usize,
+    rows: usize,
+    cells: Vec<Cell>,
+    cursor: Option<(usize, usize)>,
+    shape: CursorShape,
+}
+
+impl Screen {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            cols,
+            rows,
+            cells: vec![Cell::default(); cols * rows],
+            cursor: None,
+            shape: CursorShape::Default,
+        }
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Fill the screen with blanks in a style.
+    pub fn clear(&mut self, style: Style) {
+        self.cells.fill(Cell { ch: ' ', style });
+    }
+
+    /// Fill a row with blanks in a style.
+    pub fn clear_row(&mut self, row: usize, style: Style) {
+        if row < self.rows {
+            self.cells[row * self.cols..(row + 1) * self.cols].fill(Cell { ch: ' ', style });
+        }
+    }
+
+    /// Print text starting at a cell, cut off at the edge of the screen.
+    /// Returns the column after the last character printed.
+    pub fn print(&mut self, row: usize, col: usize, text: &str, style: Style) -> usize {
+        let mut col = col;
+        if row >= self.rows {
+            return col;
+        }
+        for ch in text.chars() {
+            if col >= self.cols {
+                break;
+            }
+            // Control characters like tabs would move the terminal's cursor
+            let ch = if ch.is_control() { ' ' } else { ch };
+            match char_width(ch) {
+                // Combining marks would change the character before them,
+                // which a cell can't hold
+                0 => {}
+                1 => {
+                    self.put(row, col, Cell { ch, style });
+                    col += 1;
+                }
+                _ if col + 1 >= self.cols => {
+                    // Half a wide character can't be drawn at the edge
+                    self.put(row, col, Cell { ch: ' ', style });
+                    col += 1;
+                }
+                _ => {
+                    self.put(row, col, Cell { ch, style });
+                    self.put(row, col + 1, Cell { ch: WIDE_CONTINUATION, style });
+                    col += 2;
+                }
+            }
+        }
+        col
+    }
+
+    /// Set a cell, blanking what's left of any wide character it overwrites half of.
+    fn put(&mut self, row: usize, col: usize, cell: Cell) {
+        let i = row * self.cols + col;
+        let old = self.cells[i];
+        if old.ch == WIDE_CONTINUATION && cell.ch != WIDE_CONTINUATION && col > 0 {
+            self.cells[i - 1].ch = ' ';
+        }
+        if old.ch != WIDE_CONTINUATION && char_width(old.ch) > 1 && col + 1 < self.cols {
+            self.cells[i + 1].ch = ' ';
+        }
+        self.cells[i] = cell;
+    }
+
+    /// Change the style of the cells in `from..to` of a row, keeping their text.
+    pub fn restyle(&mut self, row: usize, from: usize, to: usize, style: Style) {
+        if row < self.rows {
+            for cell in &mut self.cells[row * self.cols + from.min(self.cols)..row * self.cols + to.min(self.cols)] {
+                cell.style = style;
+            }
+        }
+    }
+
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        self.cursor
+    }
+
+    /// Show the cursor at `(row, col)`, or hide it with `None`.
+    pub fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+        self.cursor = cursor;
+    }
+
+    pub fn set_cursor_shape(&mut self, shape: CursorShape) {
+        self.shape = shape;
+    }
+
+    /// Queue the commands that turn the terminal showing `old` into this
+    /// frame. Nothing is queued if the frames are the same.
+    pub fn diff(&self, old: &Screen, out: &mut impl io::Write, depth: ColorDepth) -> io::Result<()> {
+        let redraw = (self.cols, self.rows) != (old.cols, old.rows);
+        if redraw {
+            queue!(out, SetAttribute(Attribute::Reset), Clear(ClearType::All))?;
+        }
+
+        // Where the terminal's cursor is after the last print, and what style it's in
+        let mut at = None;
+        let mut style = None;
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                let i = row * self.cols + col;
+                let cell = self.cells[i];
+                // The terminal draws both halves of a wide character at once
+                let width = if cell.ch == WIDE_CONTINUATION { 0 } else { char_width(cell.ch).max(1) };
+                let changed = (i..i + width).any(|i| old.cells.get(i) != self.cells.get(i));
+                if width == 0 || (!redraw && !changed) {
+                    continue;
+                }
+                if at != Some((row, col)) {
+                    queue!(out, MoveTo(col as u16, row as u16))?;
+                }
+                if style != Some(cell.style) {
+                    queue!(out, SetStyle(cell.style, depth))?;
+                    style = Some(cell.style);
+                }
+                queue!(out, Print(cell.ch))?;
+                at = Some((row, col + width));
+            }
+        }
+
+        if self.shape != old.shape {
+            queue!(
+                out,
+                match self.shape {
+                    CursorShape::Default => SetCursorStyle::DefaultUserShape,
+                    CursorShape::Block => SetCursorStyle::SteadyBlock,
+                    CursorShape::Bar => SetCursorStyle::SteadyBar,
+                }
+            )?;
+        }
+
+        // Printing moves the cursor, so it has to be put back
+        if at.is_some() || redraw || self.cursor != old.cursor {
+            match self.cursor {
+                Some((row, col)) => queue!(out, MoveTo(col as u16, row as u16), Show)?,
+                None => queue!(out, Hide)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(screen: &Screen, row: usize) -> String {
+        let cells = &screen.cells[row * screen.cols..(row + 1) * screen.cols];
+        cells.iter().map(|cell| cell.ch).filter(|&ch| ch != WIDE_CONTINUATION).collect()
+    }
+
+    #[test]
+    fn measures_text() {
+        assert_eq!(display_width("a漢😀\t"), 6);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_col("a漢b", 4), 3);
+        // Inside a character counts as its start
+        assert_eq!(display_col("a漢b", 2), 1);
+        assert_eq!(byte_col("a漢b", 0), 0);
+        assert_eq!(byte_col("a漢b", 1), 1);
+        assert_eq!(byte_col("a漢b", 2), 1);
+        assert_eq!(byte_col("a漢b", 3), 4);
+        assert_eq!(byte_col("a漢b", 9), 5);
+    }
+
+    #[test]
+    fn prints_wide_characters_in_two_cells() {
+        let mut screen = Screen::new(6, 1);
+        assert_eq!(screen.print(0, 0, "a漢b", Style::default()), 4);
+        assert_eq!(screen.cells[2].ch, WIDE_CONTINUATION);
+        assert_eq!(text(&screen, 0), "a漢b  ");
+
+        // Overwriting half of a wide character blanks the other half
+        screen.print(0, 2, "x", Style::default());
+        assert_eq!(text(&screen, 0), "a xb  ");
+        screen.print(0, 0, "漢", Style::default());
+        screen.print(0, 0, "y", Style::default());
+        assert_eq!(text(&screen, 0), "y xb  ");
+
+        // A wide character that would cross the edge isn't drawn
+        assert_eq!(screen.print(0, 5, "漢", Style::default()), 6);
+        assert_eq!(text(&screen, 0), "y xb  ");
+    }
+
+    #[test]
+    fn diffs_wide_characters() {
+        let old = Screen::new(6, 1);
+        let mut new = old.clone();
+        new.print(0, 0, "a漢b", Style::default());
+        new.set_cursor(Some((0, 4)));
+        let mut out = vec![];
+        new.diff(&old, &mut out, ColorDepth::TrueColor).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // The characters print one after the other, with the cursor after them
+        assert!(out.contains("a漢b"), "{out:?}");
+        assert!(!out.contains('\0'));
+        assert!(out.ends_with("\x1b[1;5H\x1b[?25h"), "{out:?}");
+
+        // Changing only the second half of a wide character redraws it
+        let mut styled = new.clone();
+        styled.restyle(0, 2, 3, Style { bold: true, ..Style::default() });
+        let mut out = vec![];
+        styled.diff(&new, &mut out, ColorDepth::TrueColor).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains('漢'));
+    }
+}
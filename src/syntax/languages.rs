@@ -0,0 +1,211 @@
+use super::{Language, StringRule};
+use std::collections::BTreeSet;
+
+fn words(text: &str) -> BTreeSet<String> {
+    text.split_whitespace().map(String::from).collect()
+}
+
+fn list(text: &str) -> Vec<String> {
+    text.split_whitespace().map(String::from).collect()
+}
+
+/// The operators shared by most C-like languages.
+const C_OPERATORS: &str = "+ - * / % = ! < > & | ^ ~ ? : . , ; ( ) [ ] { } \
+    == != <= >= && || ++ -- += -= *= /= %= &= |= ^= << >> -> <<= >>=";
+
+/// The languages highlighted out of the box.
+pub fn builtin() -> Vec<Language> {
+    vec![rust(), c(), python(), javascript(), go(), shell(), toml(), lite()]
+}
+
+fn rust() -> Language {
+    Language {
+        name: "Rust".to_string(),
+        extensions: list("rs"),
+        keywords: words(
+            "as async await break const continue crate dyn else enum extern false fn for if impl in \
+             let loop match mod move mut pub ref return self Self static struct super trait true type \
+             unsafe use where while",
+        ),
+        types: words(
+            "bool char str u8 u16 u32 u64 u128 usize i8 i16 i32 i64 i128 isize f32 f64 \
+             String Vec Option Result Box Rc Arc HashMap BTreeMap",
+        ),
+        builtins: words(
+            "println print eprintln eprint format write writeln vec panic assert assert_eq \
+             assert_ne unreachable unimplemented todo matches Some None Ok Err",
+        ),
+        operators: list(&format!("{C_OPERATORS} => :: .. ..= # @")),
+        line_comments: list("//"),
+        block_comment: Some(("/*".to_string(), "*/".to_string())),
+        strings: vec![StringRule::new("\"", "\"", Some('\\'), true)],
+        ..Language::default()
+    }
+}
+
+fn c() -> Language {
+    Language {
+        name: "C".to_string(),
+        extensions: list("c h cc cpp cxx hpp hh"),
+        keywords: words(
+            "auto break case catch class const constexpr continue default delete do else enum \
+             extern false for goto if inline namespace new nullptr operator private protected \
+             public return sizeof static struct switch template this throw true try typedef \
+             typename union using virtual volatile while NULL",
+        ),
+        types: words(
+            "int float double char bool void short long signed unsigned size_t ssize_t \
+             int8_t int16_t int32_t int64_t uint8_t uint16_t uint32_t uint64_t FILE",
+        ),
+        builtins: words(
+            "printf scanf malloc free calloc realloc exit abort memcpy memset memmove memcmp \
+             strcmp strcpy strcat strlen strchr strstr strtok",
+        ),
+        operators: list(&format!(
+            "{C_OPERATORS} :: #include #define #undef #if #ifdef #ifndef #elif #else #endif #pragma"
+        )),
+        line_comments: list("//"),
+        block_comment: Some(("/*".to_string(), "*/".to_string())),
+        strings: vec![
+            StringRule::new("\"", "\"", Some('\\'), false),
+            StringRule::new("'", "'", Some('\\'), false),
+        ],
+        ..Language::default()
+    }
+}
+
+fn python() -> Language {
+    Language {
+        name: "Python".to_string(),
+        extensions: list("py pyw"),
+        shebangs: list("python"),
+        keywords: words(
+            "and as assert async await break class continue def del elif else except False \
+             finally for from global if import in is lambda None nonlocal not or pass raise \
+             return True try while with yield",
+        ),
+        types: words("int float str bool list dict set tuple bytes object type"),
+        builtins: words(
+            "print len range enumerate zip map filter open input isinstance super self \
+             sorted reversed min max sum abs any all",
+        ),
+        operators: list("+ - * / // % ** = == != < > <= >= += -= *= /= ( ) [ ] { } : , . @ -> :="),
+        line_comments: list("#"),
+        strings: vec![
+            StringRule::new("\"\"\"", "\"\"\"", Some('\\'), true),
+            StringRule::new("'''", "'''", Some('\\'), true),
+            StringRule::new("\"", "\"", Some('\\'), false),
+            StringRule::new("'", "'", Some('\\'), false),
+        ],
+        ..Language::default()
+    }
+}
+
+fn javascript() -> Language {
+    Language {
+        name: "JavaScript".to_string(),
+        extensions: list("js jsx mjs cjs ts tsx"),
+        shebangs: list("node deno"),
+        keywords: words(
+            "async await break case catch class const continue default delete do else export \
+             extends false finally for from function if import in instanceof interface let new \
+             null of return static super switch this throw true try type typeof undefined var \
+             void while yield",
+        ),
+        types: words("number string boolean any unknown never object Array Map Set Promise"),
+        builtins: words("console window document JSON Math Object require module"),
+        operators: list(&format!("{C_OPERATORS} === !== => ?? ?. ...")),
+        line_comments: list("//"),
+        block_comment: Some(("/*".to_string(), "*/".to_string())),
+        strings: vec![
+            StringRule::new("\"", "\"", Some('\\'), false),
+            StringRule::new("'", "'", Some('\\'), false),
+            StringRule::new("`", "`", Some('\\'), true),
+        ],
+        ident_chars: "$".to_string(),
+    }
+}
+
+fn go() -> Language {
+    Language {
+        name: "Go".to_string(),
+        extensions: list("go"),
+        keywords: words(
+            "break case chan const continue default defer else fallthrough for func go goto if \
+             import interface map package range return select struct switch type var true false nil",
+        ),
+        types: words(
+            "bool byte complex64 complex128 error float32 float64 int int8 int16 int32 int64 \
+             rune string uint uint8 uint16 uint32 uint64 uintptr any",
+        ),
+        builtins: words("append cap close copy delete len make new panic print println recover"),
+        operators: list(&format!("{C_OPERATORS} := <- ...")),
+        line_comments: list("//"),
+        block_comment: Some(("/*".to_string(), "*/".to_string())),
+        strings: vec![
+            StringRule::new("\"", "\"", Some('\\'), false),
+            StringRule::new("'", "'", Some('\\'), false),
+            StringRule::new("`", "`", None, true),
+        ],
+        ..Language::default()
+    }
+}
+
+fn shell() -> Language {
+    Language {
+        name: "Shell".to_string(),
+        extensions: list("sh bash zsh"),
+        shebangs: list("sh bash zsh dash"),
+        keywords: words(
+            "if then else elif fi case esac for while until do done in function return \
+             local export readonly",
+        ),
+        builtins: words("echo printf cd exit source set unset shift test read eval exec trap"),
+        operators: list("| || & && ; ;; > >> < << = == != $ ( ) [ ] [[ ]] { }"),
+        line_comments: list("#"),
+        strings: vec![
+            StringRule::new("\"", "\"", Some('\\'), true),
+            StringRule::new("'", "'", None, true),
+        ],
+        ..Language::default()
+    }
+}
+
+fn toml() -> Language {
+    Language {
+        name: "TOML".to_string(),
+        extensions: list("toml"),
+        keywords: words("true false"),
+        operators: list("= [ ] [[ ]] { } , ."),
+        line_comments: list("#"),
+        strings: vec![
+            StringRule::new("\"\"\"", "\"\"\"", Some('\\'), true),
+            StringRule::new("'''", "'''", None, true),
+            StringRule::new("\"", "\"", Some('\\'), false),
+            StringRule::new("'", "'", None, false),
+        ],
+        ident_chars: "-".to_string(),
+        ..Language::default()
+    }
+}
+
+/// The editor's own scripting language.
+fn lite() -> Language {
+    Language {
+        name: "Lite".to_string(),
+        extensions: list("lite"),
+        keywords: words(
+            "do if in fn let try for then else proc true false none raise catch to while match with True False None",
+        ),
+        builtins: words(
+            "insert delete move goto select unselect undo redo add sub mul div rem \
+             get-select get-select-start get-select-end get-select-len get-select-lines \
+             new-buf set-buf",
+        ),
+        operators: list("-> => ~> ... == != <= >= < > = + - * / % ^ & | ! ; : , ( ) [ ] { } ' @"),
+        line_comments: list("#"),
+        strings: vec![StringRule::new("\"", "\"", Some('\\'), true)],
+        ident_chars: "-?!".to_string(),
+        ..Language::default()
+    }
+}
@@ -0,0 +1,525 @@
+use std::{collections::BTreeSet, path::Path};
+
+mod languages;
+mod sublime;
+pub use sublime::*;
+
+/// What a piece of highlighted text is, independent of how it's drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Scope {
+    Normal,
+    Keyword,
+    Type,
+    Builtin,
+    Operator,
+    String,
+    Comment,
+    Number,
+}
+
+impl Scope {
+    /// The name of the scope's element in a theme.
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Normal => "normal",
+            Scope::Keyword => "keyword",
+            Scope::Type => "type",
+            Scope::Builtin => "builtin",
+            Scope::Operator => "operator",
+            Scope::String => "string",
+            Scope::Comment => "comment",
+            Scope::Number => "number",
+        }
+    }
+
+    /// Map a TextMate scope name such as `string.quoted.double` to a scope.
+    /// Of several space-separated names, the first one that maps wins.
+    pub fn from_name(name: &str) -> Option<Self> {
+        name.split_whitespace().find_map(|name| {
+            let is = |prefix: &str| name == prefix || name.starts_with(&format!("{prefix}."));
+            Some(if is("comment") || is("punctuation.definition.comment") {
+                Scope::Comment
+            } else if is("string") || is("punctuation.definition.string") || is("constant.character.escape") {
+                Scope::String
+            } else if is("constant.numeric") {
+                Scope::Number
+            } else if is("keyword.operator") {
+                Scope::Operator
+            } else if is("storage.type") || is("entity.name.type") || is("entity.name.class") || is("support.type") || is("support.class") {
+                Scope::Type
+            } else if is("keyword") || is("storage") || is("constant.language") {
+                Scope::Keyword
+            } else if is("support") || is("entity.name.function") || is("variable.function") {
+                Scope::Builtin
+            } else {
+                return None;
+            })
+        })
+    }
+}
+
+/// A highlighted byte range of a line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub scope: Scope,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Where the tokenizer is at the end of a line, so that block comments
+/// and multi-line strings continue on the next line.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LineState {
+    #[default]
+    Normal,
+    Comment,
+    /// Inside a string, opened by the rule with this index.
+    String(usize),
+    /// The stack of contexts of a loaded syntax.
+    Contexts(Vec<usize>),
+}
+
+/// A kind of string literal, such as `"..."` or Python's `'''...'''`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StringRule {
+    pub open: String,
+    pub close: String,
+    pub escape: Option<char>,
+    pub multiline: bool,
+}
+
+impl StringRule {
+    pub fn new(open: &str, close: &str, escape: Option<char>, multiline: bool) -> Self {
+        Self {
+            open: open.to_string(),
+            close: close.to_string(),
+            escape,
+            multiline,
+        }
+    }
+
+    /// Find the end of the closing delimiter in the text after the opening one.
+    fn find_close(&self, text: &str) -> Option<usize> {
+        let mut chars = text.char_indices();
+        while let Some((i, ch)) = chars.next() {
+            if Some(ch) == self.escape {
+                chars.next();
+            } else if text[i..].starts_with(&self.close) {
+                return Some(i + self.close.len());
+            }
+        }
+        None
+    }
+}
+
+/// The definition of how to highlight one language.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Language {
+    pub name: String,
+    /// File extensions, without the dot.
+    pub extensions: Vec<String>,
+    /// Interpreter names that select this language from a `#!` line.
+    pub shebangs: Vec<String>,
+    pub keywords: BTreeSet<String>,
+    pub types: BTreeSet<String>,
+    pub builtins: BTreeSet<String>,
+    pub operators: Vec<String>,
+    pub line_comments: Vec<String>,
+    pub block_comment: Option<(String, String)>,
+    pub strings: Vec<StringRule>,
+    /// Characters besides letters, digits and `_` that can appear in identifiers.
+    pub ident_chars: String,
+}
+
+impl Language {
+    fn is_ident_char(&self, ch: char) -> bool {
+        ch.is_alphanumeric() || ch == '_' || self.ident_chars.contains(ch)
+    }
+
+    fn classify(&self, word: &str) -> Scope {
+        if self.keywords.contains(word) {
+            Scope::Keyword
+        } else if self.types.contains(word) {
+            Scope::Type
+        } else if self.builtins.contains(word) {
+            Scope::Builtin
+        } else {
+            Scope::Normal
+        }
+    }
+
+    /// The longest operator at the start of the text.
+    fn operator_len(&self, text: &str) -> Option<usize> {
+        self.operators
+            .iter()
+            .filter(|op| text.starts_with(op.as_str()))
+            .map(String::len)
+            .max()
+    }
+
+    /// The string rule with the longest opening delimiter at the start of the text.
+    fn string_rule(&self, text: &str) -> Option<usize> {
+        (0..self.strings.len())
+            .filter(|&i| text.starts_with(&self.strings[i].open))
+            .max_by_key(|&i| self.strings[i].open.len())
+    }
+
+    /// Split a line into highlighted spans, starting in `state`.
+    /// Returns the spans and the state the next line starts in.
+    pub fn highlight_line(&self, line: &str, state: &LineState) -> (Vec<Span>, LineState) {
+        let mut state = match state {
+            LineState::Contexts(_) => LineState::Normal,
+            state => state.clone(),
+        };
+        let mut spans = vec![];
+        let mut push = |scope, start, end| {
+            if start < end {
+                spans.push(Span { scope, start, end })
+            }
+        };
+
+        let mut i = 0;
+        while i < line.len() {
+            let rest = &line[i..];
+            match state {
+                LineState::Comment => {
+                    let close = self.block_comment.as_ref().map(|(_, close)| close.as_str());
+                    match close.and_then(|close| rest.find(close).map(|j| j + close.len())) {
+                        Some(end) => {
+                            push(Scope::Comment, i, i + end);
+                            i += end;
+                            state = LineState::Normal;
+                        }
+                        None => {
+                            push(Scope::Comment, i, line.len());
+                            i = line.len();
+                        }
+                    }
+                }
+                LineState::String(rule) => match self.strings.get(rule).and_then(|r| r.find_close(rest)) {
+                    Some(end) => {
+                        push(Scope::String, i, i + end);
+                        i += end;
+                        state = LineState::Normal;
+                    }
+                    None => {
+                        push(Scope::String, i, line.len());
+                        i = line.len();
+                    }
+                },
+                LineState::Normal | LineState::Contexts(_) => {
+                    let ch = rest.chars().next().unwrap();
+                    if self.line_comments.iter().any(|c| rest.starts_with(c.as_str())) {
+                        push(Scope::Comment, i, line.len());
+                        i = line.len();
+                    } else if let Some((open, _)) = self.block_comment.as_ref().filter(|(open, _)| rest.starts_with(open.as_str())) {
+                        push(Scope::Comment, i, i + open.len());
+                        i += open.len();
+                        state = LineState::Comment;
+                    } else if let Some(rule) = self.string_rule(rest) {
+                        let open = self.strings[rule].open.len();
+                        push(Scope::String, i, i + open);
+                        i += open;
+                        state = LineState::String(rule);
+                    } else if ch.is_ascii_digit() {
+                        // Take in suffixes, hex digits, exponents and decimal points
+                        let end = rest
+                            .char_indices()
+                            .find(|&(j, c)| {
+                                !(c.is_alphanumeric()
+                                    || c == '_'
+                                    || (c == '.' && rest[j + 1..].starts_with(|d: char| d.is_ascii_digit())))
+                            })
+                            .map_or(rest.len(), |(j, _)| j);
+                        push(Scope::Number, i, i + end);
+                        i += end;
+                    } else if self.is_ident_char(ch) {
+                        let end = rest
+                            .char_indices()
+                            .find(|&(_, c)| !self.is_ident_char(c))
+                            .map_or(rest.len(), |(j, _)| j);
+                        push(self.classify(&rest[..end]), i, i + end);
+                        i += end;
+                    } else if let Some(len) = self.operator_len(rest) {
+                        push(Scope::Operator, i, i + len);
+                        i += len;
+                    } else {
+                        push(Scope::Normal, i, i + ch.len_utf8());
+                        i += ch.len_utf8();
+                    }
+                }
+            }
+        }
+
+        // Only some strings may continue onto the next line
+        if let LineState::String(rule) = state {
+            if !self.strings.get(rule).is_some_and(|r| r.multiline) {
+                state = LineState::Normal;
+            }
+        }
+        (spans, state)
+    }
+}
+
+/// A way of highlighting one language: either one of the built-in
+/// definitions or a syntax loaded from a file.
+#[derive(Clone, Debug)]
+pub enum Syntax {
+    Builtin(Language),
+    Sublime(SublimeSyntax),
+}
+
+impl Syntax {
+    pub fn name(&self) -> &str {
+        match self {
+            Syntax::Builtin(language) => &language.name,
+            Syntax::Sublime(syntax) => &syntax.name,
+        }
+    }
+
+    fn has_extension(&self, extension: &str) -> bool {
+        let extensions = match self {
+            Syntax::Builtin(language) => &language.extensions,
+            Syntax::Sublime(syntax) => &syntax.extensions,
+        };
+        extensions.iter().any(|e| e == extension)
+    }
+
+    fn matches_first_line(&self, line: &str) -> bool {
+        match self {
+            Syntax::Builtin(language) => shebang_interpreter(line)
+                .is_some_and(|interpreter| language.shebangs.iter().any(|s| interpreter.starts_with(s.as_str()))),
+            Syntax::Sublime(syntax) => syntax.matches_first_line(line),
+        }
+    }
+
+    /// Split a line into highlighted spans, starting in `state`.
+    /// Returns the spans and the state the next line starts in.
+    pub fn highlight_line(&self, line: &str, state: &LineState) -> (Vec<Span>, LineState) {
+        match self {
+            Syntax::Builtin(language) => language.highlight_line(line, state),
+            Syntax::Sublime(syntax) => {
+                let stack = match state {
+                    LineState::Contexts(stack) => stack.as_slice(),
+                    _ => &[],
+                };
+                let (spans, stack) = syntax.highlight_line(line, stack);
+                (spans, LineState::Contexts(stack))
+            }
+        }
+    }
+}
+
+/// The languages the editor knows how to highlight.
+#[derive(Clone, Debug)]
+pub struct Syntaxes {
+    syntaxes: Vec<Syntax>,
+}
+
+impl Default for Syntaxes {
+    fn default() -> Self {
+        Self {
+            syntaxes: languages::builtin().into_iter().map(Syntax::Builtin).collect(),
+        }
+    }
+}
+
+impl Syntaxes {
+    pub fn syntaxes(&self) -> &[Syntax] {
+        &self.syntaxes
+    }
+
+    /// Load a `.sublime-syntax` file. Loaded syntaxes take priority over
+    /// the built-in ones, and replace any loaded earlier with the same name.
+    pub fn load(&mut self, file: impl AsRef<Path>) -> Result<&SublimeSyntax, String> {
+        let file = file.as_ref();
+        let text = std::fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+        let syntax = SublimeSyntax::parse(&text).map_err(|e| format!("{}: {}", file.display(), e))?;
+        self.syntaxes
+            .retain(|s| !matches!(s, Syntax::Sublime(old) if old.name == syntax.name));
+        self.syntaxes.insert(0, Syntax::Sublime(syntax));
+        match &self.syntaxes[0] {
+            Syntax::Sublime(syntax) => Ok(syntax),
+            Syntax::Builtin(_) => unreachable!(),
+        }
+    }
+
+    /// Load every `.sublime-syntax` file in a directory. Returns the
+    /// number loaded, and an error message for each file that failed.
+    pub fn load_dir(&mut self, dir: impl AsRef<Path>) -> (usize, Vec<String>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return (0, vec![]);
+        };
+        let mut files: Vec<_> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "sublime-syntax"))
+            .collect();
+        files.sort();
+
+        let mut loaded = 0;
+        let mut errors = vec![];
+        for file in files {
+            match self.load(&file) {
+                Ok(_) => loaded += 1,
+                Err(e) => errors.push(e),
+            }
+        }
+        (loaded, errors)
+    }
+
+    /// Pick the syntax for a file from its extension, or failing that,
+    /// from its first line.
+    pub fn detect(&self, file: Option<&str>, first_line: &str) -> Option<&Syntax> {
+        let extension = file
+            .and_then(|file| Path::new(file).extension())
+            .and_then(|ext| ext.to_str());
+        if let Some(extension) = extension {
+            if let Some(syntax) = self.syntaxes.iter().find(|s| s.has_extension(extension)) {
+                return Some(syntax);
+            }
+        }
+        self.syntaxes.iter().find(|s| s.matches_first_line(first_line))
+    }
+}
+
+/// The highlighted lines of a buffer, kept until the lines change.
+#[derive(Clone, Debug, Default)]
+pub struct HighlightCache {
+    /// The syntax the lines were highlighted with.
+    syntax: String,
+    /// The spans of each line, and the state the next line starts in.
+    lines: Vec<(Vec<Span>, LineState)>,
+}
+
+impl HighlightCache {
+    /// Forget the highlighting of a line and every line after it.
+    pub fn invalidate(&mut self, row: usize) {
+        self.lines.truncate(row);
+    }
+
+    /// The spans of the lines in `start..end`, highlighting any lines
+    /// up to `end` that aren't cached yet.
+    pub fn highlight(&mut self, syntax: &Syntax, lines: &[String], start: usize, end: usize) -> Vec<Vec<Span>> {
+        if self.syntax != syntax.name() {
+            self.syntax = syntax.name().to_string();
+            self.lines.clear();
+        }
+
+        let end = end.min(lines.len());
+        while self.lines.len() < end {
+            let state = self.lines.last().map(|(_, state)| state.clone()).unwrap_or_default();
+            let line = syntax.highlight_line(&lines[self.lines.len()], &state);
+            self.lines.push(line);
+        }
+        self.lines[start.min(end)..end].iter().map(|(spans, _)| spans.clone()).collect()
+    }
+}
+/// The program named by a `#!` line, looking through `/usr/bin/env`.
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?;
+    let program = program.rsplit('/').next().unwrap_or(program);
+    if program == "env" {
+        words.find(|word| !word.starts_with('-'))
+    } else {
+        Some(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn language(name: &str) -> Syntax {
+        Syntaxes::default().syntaxes().iter().find(|s| s.name() == name).unwrap().clone()
+    }
+
+    /// The text of each span that isn't plain, with its scope.
+    fn scopes<'a>(syntax: &Syntax, line: &'a str, state: &LineState) -> (Vec<(Scope, &'a str)>, LineState) {
+        let (spans, state) = syntax.highlight_line(line, state);
+        let tokens = spans
+            .into_iter()
+            .filter(|span| span.scope != Scope::Normal)
+            .map(|span| (span.scope, &line[span.start..span.end]))
+            .collect();
+        (tokens, state)
+    }
+
+    #[test]
+    fn tokenizes_a_line() {
+        let rust = language("Rust");
+        let (found, state) = scopes(&rust, "let x: u8 = 0x1f + 2.5; // done", &LineState::Normal);
+        assert_eq!(
+            found,
+            vec![
+                (Scope::Keyword, "let"),
+                (Scope::Operator, ":"),
+                (Scope::Type, "u8"),
+                (Scope::Operator, "="),
+                (Scope::Number, "0x1f"),
+                (Scope::Operator, "+"),
+                (Scope::Number, "2.5"),
+                (Scope::Operator, ";"),
+                (Scope::Comment, "// done"),
+            ]
+        );
+        assert_eq!(state, LineState::Normal);
+
+        // The longest operator wins, and spans are byte ranges of any text
+        let (found, _) = scopes(&rust, "a..=b \"é\\\"\" é", &LineState::Normal);
+        assert_eq!(found, vec![(Scope::Operator, "..="), (Scope::String, "\""), (Scope::String, "é\\\"\"")]);
+    }
+
+    #[test]
+    fn carries_state_across_lines() {
+        let rust = language("Rust");
+        let (tokens1, state) = scopes(&rust, "x /* open", &LineState::Normal);
+        assert_eq!(tokens1, vec![(Scope::Comment, "/*"), (Scope::Comment, " open")]);
+        assert_eq!(state, LineState::Comment);
+        let (tokens2, state) = scopes(&rust, "still */ fn", &state);
+        assert_eq!(tokens2, vec![(Scope::Comment, "still */"), (Scope::Keyword, "fn")]);
+        assert_eq!(state, LineState::Normal);
+
+        let python = language("Python");
+        let (_, state) = scopes(&python, "s = \"\"\"doc", &LineState::Normal);
+        assert_eq!(state, LineState::String(0));
+        let (tokens3, state) = scopes(&python, "if \"\"\" if", &state);
+        assert_eq!(tokens3, vec![(Scope::String, "if \"\"\""), (Scope::Keyword, "if")]);
+        assert_eq!(state, LineState::Normal);
+
+        // Strings that can't span lines end with the line
+        let (_, state) = scopes(&python, "s = 'open", &LineState::Normal);
+        assert_eq!(state, LineState::Normal);
+    }
+
+    #[test]
+    fn detects_languages() {
+        let syntaxes = Syntaxes::default();
+        let name = |file, line| syntaxes.detect(file, line).map(Syntax::name);
+        assert_eq!(name(Some("src/main.rs"), ""), Some("Rust"));
+        assert_eq!(name(Some("script"), "#!/usr/bin/env python3"), Some("Python"));
+        assert_eq!(name(Some("script"), "#!/usr/bin/python3 -u"), Some("Python"));
+        assert_eq!(name(None, "plain text"), None);
+    }
+
+    #[test]
+    fn caches_until_invalidated() {
+        let rust = language("Rust");
+        let mut cache = HighlightCache::default();
+        let mut lines = vec!["a".to_string(), "b */ c".to_string()];
+        let scope = |spans: &[Vec<Span>]| spans[0].first().map(|span| span.scope);
+        assert_eq!(scope(&cache.highlight(&rust, &lines, 1, 2)), Some(Scope::Normal));
+
+        // Edits aren't seen until the lines after them are invalidated
+        lines[0] = "/* a".to_string();
+        assert_eq!(scope(&cache.highlight(&rust, &lines, 1, 2)), Some(Scope::Normal));
+        cache.invalidate(0);
+        assert_eq!(scope(&cache.highlight(&rust, &lines, 1, 2)), Some(Scope::Comment));
+
+        // Switching syntax starts over
+        let python = language("Python");
+        assert_eq!(scope(&cache.highlight(&python, &lines, 0, 1)), Some(Scope::Operator));
+        assert_eq!(cache.highlight(&python, &lines, 1, 5).len(), 1);
+    }
+}
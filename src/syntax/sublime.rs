@@ -0,0 +1,430 @@
+use super::{Scope, Span};
+use regex::{Regex, RegexBuilder};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+
+/// What matching a pattern does to the stack of contexts.
+#[derive(Clone, Debug)]
+enum Action {
+    None,
+    Push(Vec<usize>),
+    Pop,
+    Set(Vec<usize>),
+}
+
+#[derive(Clone, Debug)]
+struct Pattern {
+    regex: Regex,
+    scope: Option<Scope>,
+    captures: Vec<(usize, Scope)>,
+    action: Action,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Context {
+    /// The scope of text matched inside the context that no pattern gives a scope.
+    content_scope: Option<Scope>,
+    patterns: Vec<Pattern>,
+}
+
+/// A syntax definition loaded from a `.sublime-syntax` file.
+///
+/// Only the commonly used part of the format is supported: `match` patterns
+/// with `scope`, `captures`, `push`, `set` and `pop`, `include`, variables,
+/// `meta_scope`, `meta_content_scope` and the `prototype` context. Regexes
+/// that the `regex` crate can't compile, such as ones with lookbehind, are
+/// skipped and listed in `warnings`.
+#[derive(Clone, Debug)]
+pub struct SublimeSyntax {
+    pub name: String,
+    pub extensions: Vec<String>,
+    first_line: Option<Regex>,
+    /// The contexts, with `main` first.
+    contexts: Vec<Context>,
+    pub warnings: Vec<String>,
+}
+
+impl SublimeSyntax {
+    /// Parse the text of a `.sublime-syntax` file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let doc: Value = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+        let name = doc["name"].as_str().ok_or("syntax has no name")?.to_string();
+        let extensions = doc["file_extensions"]
+            .as_sequence()
+            .map(|exts| exts.iter().filter_map(Value::as_str).map(String::from).collect())
+            .unwrap_or_default();
+        let first_line = match doc["first_line_match"].as_str() {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let raw = doc["contexts"].as_mapping().ok_or("syntax has no contexts")?;
+        if raw.get("main").is_none() {
+            return Err("syntax has no main context".to_string());
+        }
+
+        let mut variables = BTreeMap::new();
+        if let Some(vars) = doc["variables"].as_mapping() {
+            for (key, value) in vars {
+                if let (Some(key), Some(value)) = (key.as_str(), value.as_str()) {
+                    variables.insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+
+        let mut loader = Loader {
+            raw,
+            variables,
+            index: BTreeMap::new(),
+            contexts: vec![],
+            warnings: vec![],
+        };
+        loader.load();
+
+        Ok(Self {
+            name,
+            extensions,
+            first_line,
+            contexts: loader.contexts,
+            warnings: loader.warnings,
+        })
+    }
+
+    /// Whether the `first_line_match` of the syntax matches the line.
+    pub fn matches_first_line(&self, line: &str) -> bool {
+        self.first_line.as_ref().is_some_and(|re| re.is_match(line))
+    }
+
+    /// Split a line into highlighted spans, starting with the given stack
+    /// of contexts. Returns the spans and the stack the next line starts with.
+    pub fn highlight_line(&self, line: &str, stack: &[usize]) -> (Vec<Span>, Vec<usize>) {
+        // Patterns are written to match the line's newline too
+        let text = format!("{line}\n");
+        let mut stack = stack.to_vec();
+        if stack.is_empty() {
+            stack.push(0);
+        }
+
+        let mut spans = vec![];
+        let mut push = |scope: Option<Scope>, start: usize, end: usize| {
+            let end = end.min(line.len());
+            if start < end {
+                spans.push(Span {
+                    scope: scope.unwrap_or(Scope::Normal),
+                    start,
+                    end,
+                })
+            }
+        };
+
+        let mut pos = 0;
+        // Patterns that match nothing and only change the stack could loop forever
+        let mut steps = 0;
+        while pos < text.len() && steps < 4 * text.len() + 64 {
+            steps += 1;
+            let context = &self.contexts[*stack.last().unwrap()];
+            let found = context
+                .patterns
+                .iter()
+                .filter_map(|p| p.regex.find_at(&text, pos).map(|m| (m.start(), m.end(), p)))
+                .min_by_key(|&(start, _, _)| start);
+            let Some((start, end, pattern)) = found else {
+                push(context.content_scope, pos, text.len());
+                break;
+            };
+
+            push(context.content_scope, pos, start);
+            push(pattern.scope.or(context.content_scope), start, end);
+            if !pattern.captures.is_empty() {
+                if let Some(caps) = pattern.regex.captures_at(&text, pos) {
+                    for &(group, scope) in &pattern.captures {
+                        if let Some(m) = caps.get(group) {
+                            push(Some(scope), m.start(), m.end());
+                        }
+                    }
+                }
+            }
+
+            match &pattern.action {
+                Action::None if end == pos => {
+                    // Step over a character so an empty match can't repeat
+                    let len = text[pos..].chars().next().map_or(1, char::len_utf8);
+                    push(context.content_scope, pos, pos + len);
+                    pos += len;
+                    continue;
+                }
+                Action::None => {}
+                Action::Push(contexts) => stack.extend(contexts),
+                Action::Pop => {
+                    stack.pop();
+                }
+                Action::Set(contexts) => {
+                    stack.pop();
+                    stack.extend(contexts);
+                }
+            }
+            if stack.is_empty() {
+                stack.push(0);
+            }
+            pos = end;
+        }
+        (spans, stack)
+    }
+}
+
+/// Compiles the raw YAML contexts of a syntax into `Context`s.
+struct Loader<'a> {
+    raw: &'a Mapping,
+    variables: BTreeMap<String, String>,
+    /// The index of each named context.
+    index: BTreeMap<String, usize>,
+    contexts: Vec<Context>,
+    warnings: Vec<String>,
+}
+
+impl Loader<'_> {
+    fn load(&mut self) {
+        // Give every named context its index first, so pushes can refer
+        // to contexts defined later in the file
+        let mut names = vec!["main".to_string()];
+        names.extend(
+            self.raw
+                .keys()
+                .filter_map(Value::as_str)
+                .filter(|name| *name != "main")
+                .map(String::from),
+        );
+        for name in &names {
+            self.index.insert(name.clone(), self.contexts.len());
+            self.contexts.push(Context::default());
+        }
+
+        for name in &names {
+            let items = self.raw.get(name.as_str()).cloned().unwrap_or(Value::Null);
+            let context = self.context(&items, name != "prototype");
+            self.contexts[self.index[name]] = context;
+        }
+    }
+
+    fn context(&mut self, items: &Value, with_prototype: bool) -> Context {
+        let mut context = Context::default();
+        let mut with_prototype = with_prototype;
+        let items = items.as_sequence().cloned().unwrap_or_default();
+        for item in &items {
+            if let Some(scope) = item["meta_scope"].as_str() {
+                context.content_scope = Scope::from_name(scope);
+            }
+            if let Some(scope) = item["meta_content_scope"].as_str() {
+                context.content_scope = Scope::from_name(scope);
+            }
+            if item["meta_include_prototype"].as_bool() == Some(false) {
+                with_prototype = false;
+            }
+        }
+        if with_prototype && self.raw.get("prototype").is_some() {
+            context.patterns = self.included("prototype", 0);
+        }
+        context.patterns.extend(self.patterns(&items, 0));
+        context
+    }
+
+    /// The patterns of a named context, for including in another one.
+    fn included(&mut self, name: &str, depth: usize) -> Vec<Pattern> {
+        if depth > 16 {
+            self.warnings.push(format!("include of {name} nests too deeply"));
+            return vec![];
+        }
+        match self.raw.get(name).and_then(Value::as_sequence).cloned() {
+            Some(items) => self.patterns(&items, depth + 1),
+            None => {
+                self.warnings.push(format!("unknown context {name}"));
+                vec![]
+            }
+        }
+    }
+
+    fn patterns(&mut self, items: &[Value], depth: usize) -> Vec<Pattern> {
+        let mut patterns = vec![];
+        for item in items {
+            if let Some(name) = item["include"].as_str() {
+                patterns.extend(self.included(name, depth));
+                continue;
+            }
+            let Some(source) = item["match"].as_str() else {
+                continue;
+            };
+            let source = self.expand(source);
+            // As in Sublime Text, `^` and `$` match at the start and end of the line
+            let regex = match RegexBuilder::new(&source).multi_line(true).build() {
+                Ok(regex) => regex,
+                Err(_) => {
+                    self.warnings.push(format!("unsupported regex {source}"));
+                    continue;
+                }
+            };
+
+            let mut captures = vec![];
+            if let Some(caps) = item["captures"].as_mapping() {
+                for (group, scope) in caps {
+                    let group = group
+                        .as_u64()
+                        .map(|g| g as usize)
+                        .or_else(|| group.as_str().and_then(|g| g.parse().ok()));
+                    let scope = scope.as_str().and_then(Scope::from_name);
+                    if let (Some(group), Some(scope)) = (group, scope) {
+                        captures.push((group, scope));
+                    }
+                }
+            }
+
+            let action = if item["pop"].as_bool() == Some(true) || item["pop"].as_u64().is_some() {
+                Action::Pop
+            } else if !item["push"].is_null() {
+                Action::Push(self.targets(&item["push"]))
+            } else if !item["set"].is_null() {
+                Action::Set(self.targets(&item["set"]))
+            } else {
+                Action::None
+            };
+
+            patterns.push(Pattern {
+                regex,
+                scope: item["scope"].as_str().and_then(Scope::from_name),
+                captures,
+                action,
+            });
+        }
+        patterns
+    }
+
+    /// The contexts a `push` or `set` refers to: a name, a list of names,
+    /// or an anonymous context written inline.
+    fn targets(&mut self, value: &Value) -> Vec<usize> {
+        match value {
+            Value::String(name) => self.target(name).into_iter().collect(),
+            Value::Sequence(items) if items.iter().all(Value::is_string) => items
+                .iter()
+                .filter_map(Value::as_str)
+                .filter_map(|name| self.target(name))
+                .collect(),
+            Value::Sequence(_) => {
+                let context = self.context(value, true);
+                self.contexts.push(context);
+                vec![self.contexts.len() - 1]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn target(&mut self, name: &str) -> Option<usize> {
+        let index = self.index.get(name).copied();
+        if index.is_none() {
+            self.warnings.push(format!("unknown context {name}"));
+        }
+        index
+    }
+
+    /// Substitute `{{variable}}` references, which may themselves
+    /// refer to other variables.
+    fn expand(&self, source: &str) -> String {
+        let mut text = source.to_string();
+        for _ in 0..8 {
+            if !text.contains("{{") {
+                break;
+            }
+            for (name, value) in &self.variables {
+                text = text.replace(&format!("{{{{{name}}}}}"), value);
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYNTAX: &str = r#"
+name: Toy
+file_extensions: [toy]
+first_line_match: ^#!.*toy
+variables:
+  ident: '[a-z]+'
+contexts:
+  prototype:
+    - match: '#.*$'
+      scope: comment.line
+  main:
+    - match: '\b(let)\s+({{ident}})'
+      captures:
+        1: keyword.other
+        2: entity.name.function
+    - match: '"'
+      scope: punctuation.definition.string.begin
+      push: string
+    - match: '(?<=x)y'
+      scope: keyword
+    - include: numbers
+  numbers:
+    - match: '\d+'
+      scope: constant.numeric
+  string:
+    - meta_scope: string.quoted
+    - meta_include_prototype: false
+    - match: '"'
+      pop: true
+"#;
+
+    /// The text of each span that isn't plain, with its scope.
+    fn scopes<'a>(syntax: &SublimeSyntax, line: &'a str, stack: &[usize]) -> (Vec<(Scope, &'a str)>, Vec<usize>) {
+        let (spans, stack) = syntax.highlight_line(line, stack);
+        let found = spans
+            .into_iter()
+            .filter(|span| span.scope != Scope::Normal)
+            .map(|span| (span.scope, &line[span.start..span.end]))
+            .collect();
+        (found, stack)
+    }
+
+    #[test]
+    fn loads_a_syntax() {
+        let syntax = SublimeSyntax::parse(SYNTAX).unwrap();
+        assert_eq!(syntax.name, "Toy");
+        assert_eq!(syntax.extensions, vec!["toy"]);
+        assert!(syntax.matches_first_line("#!/usr/bin/toy"));
+        assert!(!syntax.matches_first_line("#!/bin/sh"));
+        // Lookbehind isn't supported by the regex crate
+        assert_eq!(syntax.warnings, vec!["unsupported regex (?<=x)y"]);
+
+        assert!(SublimeSyntax::parse("contexts: {main: []}").unwrap_err().contains("no name"));
+        assert!(SublimeSyntax::parse("name: X\ncontexts: {other: []}").unwrap_err().contains("no main"));
+        assert!(SublimeSyntax::parse("name: [").is_err());
+        let unknown = SublimeSyntax::parse("name: X\ncontexts:\n  main:\n    - match: a\n      push: nowhere").unwrap();
+        assert_eq!(unknown.warnings, vec!["unknown context nowhere"]);
+    }
+
+    #[test]
+    fn highlights_with_a_stack_of_contexts() {
+        let syntax = SublimeSyntax::parse(SYNTAX).unwrap();
+        let (found, stack) = scopes(&syntax, "let abc = 12 # note", &[]);
+        assert_eq!(
+            found,
+            vec![
+                (Scope::Keyword, "let"),
+                (Scope::Builtin, "abc"),
+                (Scope::Number, "12"),
+                (Scope::Comment, "# note"),
+            ]
+        );
+        assert_eq!(stack, vec![0]);
+
+        // A string left open carries on with the next line, where the
+        // prototype's comments don't apply
+        let (found, stack) = scopes(&syntax, "1 \"open", &stack);
+        assert_eq!(found, vec![(Scope::Number, "1"), (Scope::String, "\""), (Scope::String, "open")]);
+        assert_eq!(stack.len(), 2);
+        let (found, stack) = scopes(&syntax, "# not \" 2", &stack);
+        assert_eq!(found, vec![(Scope::String, "# not "), (Scope::String, "\""), (Scope::Number, "2")]);
+        assert_eq!(stack, vec![0]);
+    }
+}
@@ -1,207 +1,300 @@
 use crate::*;
 
 use crossterm::{
-    cursor::MoveTo,
-    event::{poll, read, Event, KeyCode, KeyModifiers},
+    cursor::{MoveTo, SetCursorStyle},
+    event::{
+        read, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event as TerminalEvent, KeyCode, KeyModifiers, MouseButton,
+        MouseEvent, MouseEventKind,
+    },
     execute,
-    style::{Color, Attribute, Print, ResetColor, SetBackgroundColor, SetForegroundColor, SetAttribute},
+    style::ResetColor,
     terminal::{disable_raw_mode, enable_raw_mode, size, SetTitle, Clear, ClearType},
 };
 
-use std::io::stdout;
+use std::{
+    io::{stdout, Write},
+    time::{Duration, Instant},
+};
 
 pub struct Terminal {
-    screen_start_row: usize, // the starting row to be displayed
-    screen_cols: usize,      // the number of columns to be displayed
-    screen_rows: usize,      // the number of rows to be displayed
+    screen_cols: usize, // the number of columns to be displayed
+    screen_rows: usize, // the number of rows to be displayed
     status: String,
-    last_get_lines: Option<(usize, usize)>,
-    last_buffer_id: usize,
+    tab_bar: bool,      // whether the top row is taken by the tab bar
+    mouse: bool,        // whether the terminal reports the mouse to the editor
+    theme: Theme,       // the editor's theme as of the last render
+    depth: ColorDepth,
+    front: Screen,      // what the terminal is showing
+    back: Screen,       // the frame being drawn
+    events: EventQueue,
+}
+
+/// Translate a terminal event into the editor's input, if it's one the editor uses.
+fn to_input(event: TerminalEvent) -> Option<Input> {
+    match event {
+        TerminalEvent::Key(key_event) => {
+            let mut result = match key_event.code {
+                KeyCode::Backspace => Input::Backspace,
+                KeyCode::Delete => Input::Delete,
+                KeyCode::Left => Input::Left,
+                KeyCode::Right => Input::Right,
+                KeyCode::Up => Input::Up,
+                KeyCode::Down => Input::Down,
+                KeyCode::Enter => Input::Enter,
+                KeyCode::Tab => Input::Tab,
+                KeyCode::Esc => Input::Esc,
+                KeyCode::Home => Input::Home,
+                KeyCode::End => Input::End,
+                KeyCode::PageUp => Input::PageUp,
+                KeyCode::PageDown => Input::PageDown,
+                KeyCode::Char(ch) => Input::Char(ch),
+                _ => return None,
+            };
+            if key_event.modifiers.contains(KeyModifiers::SHIFT) {
+                result = Input::Shift(Box::new(result));
+            }
+            if key_event.modifiers.contains(KeyModifiers::CONTROL) {
+                result = Input::Control(Box::new(result));
+            }
+            if key_event.modifiers.contains(KeyModifiers::ALT) {
+                result = Input::Alt(Box::new(result));
+            }
+            Some(result)
+        }
+        TerminalEvent::Resize(_, _) => Some(Input::Resize),
+        TerminalEvent::Paste(text) => Some(Input::Paste(text.replace("\r\n", "\n").replace('\r', "\n"))),
+        TerminalEvent::Mouse(MouseEvent { kind, column, row, .. }) => match kind {
+            MouseEventKind::Down(MouseButton::Left) => Some(Input::Click {
+                row: row as usize,
+                col: column as usize,
+                clicks: 1,
+            }),
+            MouseEventKind::Drag(MouseButton::Left) => Some(Input::Drag {
+                row: row as usize,
+                col: column as usize,
+            }),
+            MouseEventKind::ScrollUp => Some(Input::ScrollUp),
+            MouseEventKind::ScrollDown => Some(Input::ScrollDown),
+            _ => None,
+        },
+        _ => None,
+    }
 }
 
 impl Terminal {
+    /// A terminal frontend whose input is sent to `events`, along with
+    /// whatever else is sent there.
+    pub fn new(events: EventQueue) -> Self {
+        enable_raw_mode().expect("Failed to enable raw mode");
+        execute!(stdout(), SetTitle("lite📝")).expect("Could not set terminal title");
+        // Without this, pasted text arrives as if it were typed
+        let _ = execute!(stdout(), EnableBracketedPaste);
 
-    const BACKGROUND_COLOR: Color = Color::Black;
-    const STATUS_COLOR: Color = Color::DarkMagenta;
-
-    const KEYWORDS: &'static [&'static str] = &[
-        "use",
-        "struct",
-        "enum",
-        "union",
-        "class",
-        "const",
-        "static",
-        "interface",
-        "trait",
-        "impl",
-        "fn",
-        "let",
-        "if",
-        "else",
-        "while",
-        "for",
-        "in",
-        "return",
-        "break",
-        "continue",
-        "true",
-        "false",
-        "null",
-        "NULL",
-        "nil",
-        "NIL",
-        "and",
-        "or",
-        "not",
-        "xor",
-        "mod",
-        "div",
-        "as",
-        "is",
-        "new",
-        "delete",
-        "sizeof",
-        "typeof",
-    ];
-
-    const BUILTINS: &'static [&'static str] = &[
-        "printf",
-        "scanf",
-        "malloc",
-        "free",
-        "calloc",
-        "realloc",
-        "exit",
-        "abort",
-        "memcpy",
-        "memset",
-        "memmove",
-        "memcmp",
-        "strcmp",
-        "strcpy",
-        "strcat",
-        "strlen",
-        "strchr",
-        "strstr",
-        "strtok",
-    ];
-
-    const TYPES: &'static [&'static str] = &[
-        "int",
-        "float",
-        "double",
-        "char",
-        "bool",
-        "string",
-        "void",
-    ];
-
-    const OPERATORS: &'static [&'static str] = &[
-        "#define",
-        "#pragma",
-        "#ifdef",
-        "#ifndef",
-        "#else",
-        "#endif",
-        "#include",
-        "~",
-        "$",
-        "!",
-        "+",
-        "-",
-        "*",
-        "/",
-        "%",
-        "(",
-        ")",
-        "{",
-        "}",
-        "()",
-        "++",
-        "--",
-        "+=",
-        "-=",
-        "*=",
-        "/=",
-        "%=",
-        "<<",
-        ">>",
-        "<",
-        ">",
-        "<=",
-        ">=",
-        "==",
-        "!=",
-        ",",
-        "&",
-    ];
-
-    fn print_line_with_highlighting(&mut self, row: u16, line: &str, start: usize, _end: usize) {
-        // Split the input into characters
-        let words: Vec<_> = line.split_inclusive(char::is_whitespace).collect();
-
-        execute!(stdout(), MoveTo(start as u16, row)).unwrap();
-        for word in words {
-            let trimmed = word.trim().to_lowercase();
-            let mut color = Color::White;
-            if Self::KEYWORDS.contains(&trimmed.as_str()) {
-                // execute!(stdout(), SetForegroundColor(Color::Magenta)).unwrap();
-                color = Color::Magenta;
-            } else if Self::TYPES.contains(&trimmed.as_str()) {
-                // execute!(stdout(), SetForegroundColor(Color::Blue)).unwrap();
-                color = Color::Blue;
-            } else if Self::OPERATORS.contains(&trimmed.as_str()) {
-                // execute!(stdout(), SetForegroundColor(Color::Yellow)).unwrap();
-                color = Color::Yellow;
-            } else if Self::BUILTINS.contains(&trimmed.as_str()) {
-                // execute!(stdout(), SetForegroundColor(Color::Green)).unwrap();
-                color = Color::Green;
-            } else {
-                // execute!(stdout(), ResetColor).unwrap();
+        // Reading blocks, so it has its own thread
+        let sender = events.sender();
+        let interrupt = events.interrupt();
+        std::thread::spawn(move || {
+            // When and where the last click was, and how many came in a row
+            let mut last_click: Option<(Instant, (usize, usize), usize)> = None;
+            while let Ok(event) = read() {
+                let mut input = to_input(event);
+                if let Some(Input::Click { row, col, clicks }) = &mut input {
+                    *clicks = match last_click {
+                        Some((at, pos, count)) if pos == (*row, *col) && at.elapsed() < Self::DOUBLE_CLICK => count % 3 + 1,
+                        _ => 1,
+                    };
+                    last_click = Some((Instant::now(), (*row, *col), *clicks));
+                }
+                // The editor can't see keys while a script runs, so Ctrl-C stops it from here
+                if input == Some(Input::Control(Box::new(Input::Char('c')))) && interrupt.request() {
+                    continue;
+                }
+                if let Some(input) = input {
+                    if sender.send(Event::Input(input)).is_err() {
+                        break;
+                    }
+                }
             }
+        });
 
-            if !word.chars().all(char::is_alphanumeric) {
-                for ch in word.chars() {
-                    if Self::OPERATORS.contains(&ch.to_string().as_str()) {
-                        execute!(stdout(), SetBackgroundColor(Self::BACKGROUND_COLOR), SetForegroundColor(Color::Yellow), Print(ch)).unwrap();
-                    } else {
-                        execute!(stdout(), SetBackgroundColor(Self::BACKGROUND_COLOR), SetForegroundColor(color), Print(ch)).unwrap();
-                    }
+        Self {
+            screen_cols: 80,
+            screen_rows: 23,
+            status: String::new(),
+            tab_bar: false,
+            mouse: false,
+            theme: Theme::default(),
+            depth: ColorDepth::detect(),
+            front: Screen::default(),
+            back: Screen::default(),
+            events,
+        }
+    }
+
+    const PICKER_ROWS: usize = 10;
+    /// How soon another click has to come to count as a double click.
+    const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+    /// The theme's style for an element.
+    fn style(&self, element: &str) -> Style {
+        self.theme.style(element)
+    }
+
+    /// Write the cells of the new frame that changed since the last one.
+    fn present(&mut self) -> Result<(), String> {
+        let mut out = vec![];
+        self.back.diff(&self.front, &mut out, self.depth).map_err(|e| e.to_string())?;
+        if !out.is_empty() {
+            let mut stdout = stdout();
+            stdout.write_all(&out).map_err(|e| e.to_string())?;
+            stdout.flush().map_err(|e| e.to_string())?;
+        }
+        self.front.clone_from(&self.back);
+        Ok(())
+    }
+
+    /// Turn the terminal's mouse reporting on or off.
+    fn capture_mouse(&mut self, enabled: bool) {
+        if self.mouse != enabled {
+            self.mouse = enabled;
+            let _ = if enabled {
+                execute!(stdout(), EnableMouseCapture)
+            } else {
+                execute!(stdout(), DisableMouseCapture)
+            };
+        }
+    }
+
+    /// Make the position of a mouse event relative to the view, like the
+    /// editor expects. Clicks outside of the view are dropped.
+    fn to_view(&self, event: Event) -> Option<Event> {
+        let top = self.tab_bar as usize;
+        let last = self.screen_rows.saturating_sub(1);
+        match event {
+            Event::Input(Input::Click { row, col, clicks }) => {
+                if row < top || row - top > last {
+                    return None;
                 }
-                execute!(stdout(), ResetColor).unwrap();
+                Some(Event::Input(Input::Click { row: row - top, col, clicks }))
+            }
+            // A drag past the edge of the view stops at the edge
+            Event::Input(Input::Drag { row, col }) => Some(Event::Input(Input::Drag {
+                row: row.saturating_sub(top).min(last),
+                col,
+            })),
+            event => Some(event),
+        }
+    }
+
+    /// Wait for the next key for a prompt. Other events wait until the
+    /// editor's next batch.
+    fn next_key(&mut self) -> Result<Input, String> {
+        self.events
+            .next_input()
+            .map(Input::unshifted)
+            .ok_or_else(|| "The terminal's input closed".to_string())
+    }
+
+    /// Show text on the status line, with the cursor after it.
+    fn print_status_line(&mut self, text: &str) -> Result<(), String> {
+        let row = self.screen_rows;
+        self.back.clear_row(row, self.style("status-bar"));
+        let col = self.back.print(row, 0, text, self.style("status-bar"));
+        self.back.set_cursor(Some((row, col.min(self.screen_cols.saturating_sub(1)))));
+        self.present()
+    }
+
+    /// Print the part of a line that fits in the view at `(row, col)`, colored
+    /// by its spans and drawn over `base`. The rest of the row is filled with `base`.
+    fn print_line_with_highlighting(&mut self, (row, col): (usize, usize), line: &str, spans: &[Span], start_col: usize, text_cols: usize, base: Style) {
+        let mut scopes = vec![Scope::Normal; line.len()];
+        for span in spans {
+            scopes[span.start..span.end].fill(span.scope);
+        }
+
+        let end = (col + text_cols).min(self.back.cols());
+        let mut col = col;
+        // The cells of the line before each character, counting from its start
+        let mut used = 0;
+        for (i, ch) in line.char_indices() {
+            let width = char_width(ch);
+            used += width;
+            if used <= start_col {
+                continue;
+            }
+            let style = match scopes[i] {
+                Scope::Normal => base,
+                scope => self.theme.get(scope.name()).over(base),
+            };
+            if used - width < start_col {
+                // Only part of a wide character is scrolled into view
+                col = self.back.print(row, col, &" ".repeat(used - start_col), style);
+            } else if col + width > end {
+                break;
             } else {
-                execute!(stdout(), SetBackgroundColor(Self::BACKGROUND_COLOR), SetForegroundColor(color), Print(word), ResetColor).unwrap();
+                col = self.back.print(row, col, ch.encode_utf8(&mut [0; 4]), style);
             }
+        }
+        self.back.print(row, col, &" ".repeat(end.saturating_sub(col)), base);
+    }
 
+    /// List the open buffers along the top row, highlighting the current one.
+    fn print_tab_bar(&mut self, editor: &Editor) {
+        self.back.clear_row(0, self.style("tab-bar"));
+        let mut used = 0;
+        for (id, buf) in editor.buffers().iter().enumerate() {
+            let tab = format!(" {}:{}{} ", id, buf.short_name(), if buf.is_edited() { "*" } else { "" });
+            let style = if id == editor.cur_buf_id() { self.style("status-bar") } else { self.style("tab-bar") };
+            used = self.back.print(0, used, &tab, style);
         }
     }
+
+    /// Draw the filtered options of a picker above the status line.
+    /// If there is a preview, it takes over the rest of the screen.
+    fn print_picker(&mut self, prompt: &str, filter: &str, options: &[String], matches: &[usize], selected: usize, preview: &[String]) -> Result<(), String> {
+        let rows = Self::PICKER_ROWS.min(self.screen_rows.saturating_sub(1)).max(1);
+        let first = (selected + 1).saturating_sub(rows);
+        if !preview.is_empty() {
+            for row in 0..self.screen_rows - rows {
+                let line = preview.get(row).map(String::as_str).unwrap_or("");
+                self.back.clear_row(row, self.style("normal"));
+                self.back.print(row, 0, line, self.style("normal"));
+            }
+        }
+        for i in 0..rows {
+            let row = self.screen_rows - rows + i;
+            self.back.clear_row(row, self.style("tab-bar"));
+            if let Some(&index) = matches.get(first + i) {
+                let style = if first + i == selected { self.style("selection") } else { self.style("tab-bar") };
+                self.back.print(row, 0, &options[index], style);
+            }
+        }
+        let row = self.screen_rows;
+        self.back.clear_row(row, self.style("status-bar"));
+        self.back.print(row, 0, &format!("{}{}  ({}/{})", prompt, filter, matches.len(), options.len()), self.style("status-bar"));
+        let col = prompt.chars().count() + filter.chars().count();
+        self.back.set_cursor(Some((row, col.min(self.screen_cols.saturating_sub(1)))));
+        self.present()
+    }
 }
 
 impl Default for Terminal {
     fn default() -> Self {
-        enable_raw_mode().expect("Failed to enable raw mode");
-        execute!(stdout(), SetTitle("lite📝")).expect("Could not set terminal title");
-        Self {
-            screen_start_row: 0,
-            screen_cols: 80,
-            screen_rows: 23,
-            status: String::new(),
-            last_get_lines: None,
-            last_buffer_id: 0,
-        }
+        Self::new(EventQueue::default())
     }
 }
 
 impl Drop for Terminal {
     fn drop(&mut self) {
+        self.capture_mouse(false);
+        let _ = execute!(stdout(), DisableBracketedPaste);
         disable_raw_mode().expect("Failed to disable raw mode");
     }
 }
 
 impl Frontend for Terminal {
     fn height(&self) -> usize {
-        self.screen_rows
+        self.screen_rows - self.tab_bar as usize
     }
 
     fn width(&self) -> usize {
@@ -209,338 +302,230 @@ impl Frontend for Terminal {
     }
 
     fn exit(&mut self) {
+        self.capture_mouse(false);
+        let _ = execute!(stdout(), SetCursorStyle::DefaultUserShape);
         execute!(stdout(), MoveTo(0, 0), ResetColor, Clear(ClearType::All)).unwrap();
+        // The terminal no longer shows the last frame
+        self.front = Screen::default();
     }
 
+    /// Draw the editor, writing only what changed since the last frame.
+    /// With `flush`, the whole screen is written again.
     fn render(&mut self, editor: &Editor, flush: bool) -> Result<(), String> {
         (self.screen_cols, self.screen_rows) = match size() {
             Ok((cols, rows)) => (cols as usize, rows as usize - 1),
             Err(_) => (80, 23),
         };
+        if (self.back.cols(), self.back.rows()) != (self.screen_cols, self.screen_rows + 1) {
+            self.back = Screen::new(self.screen_cols, self.screen_rows + 1);
+        }
+        if flush {
+            self.front = Screen::default();
+        }
+
+        self.theme = editor.theme().clone();
+        self.back.clear(self.style("normal"));
+
+        self.capture_mouse(editor.mouse());
+        self.tab_bar = editor.tab_bar();
+        let top = self.tab_bar as usize;
+        if self.tab_bar {
+            self.print_tab_bar(editor);
+        }
 
-        execute!(stdout(), SetBackgroundColor(Self::BACKGROUND_COLOR), Clear(ClearType::All), MoveTo(0, 0)).unwrap();
-        // if flush {
-        //     execute!(stdout(), SetBackgroundColor(Self::BACKGROUND_COLOR), Clear(ClearType::All), MoveTo(0, 0)).unwrap();
-        // }
-        
         if let Some(buf) = editor.cur_buf() {
-            let (cur_row, cur_col) = buf.cur_pos();
-            while cur_row >= self.screen_rows + self.screen_start_row {
-                self.screen_start_row += 1
-            }
+            let view = editor.view();
+            let (start_row, end_row) = view.visible_rows();
+            let start_col = view.start_col();
+            let width = view.gutter_width() - 1;
+            let text_cols = view.text_cols();
+            let selection = buf.selection_range();
+            let normal = self.style("normal");
+            let cursor_line = self.theme.get("cursor-line").over(normal);
 
-            while cur_row < self.screen_start_row {
-                self.screen_start_row -= 1
-            }
+            // Block comments and strings may start above the view, so the
+            // buffer highlights from its first line, caching what it can
+            let highlights = match editor.syntax(buf) {
+                Some(syntax) => buf.highlight(syntax, start_row, end_row),
+                None => vec![],
+            };
 
-            let width = (self.screen_start_row + self.screen_rows).to_string().len();
-
-            if let Some((start_select, end_select)) = buf.selection_range() {
-                let (start_row, start_col) = start_select;
-                let (end_row, end_col) = end_select;
-
-                for (i, line) in buf
-                    .get_lines(
-                        self.screen_start_row,
-                        self.screen_start_row + self.screen_rows,
-                    )
-                    .iter()
-                    .enumerate()
-                {
-                    let max_len = std::cmp::min(line.len(), self.screen_cols - width - 1);
-                    // execute!(
-                    //     stdout(),
-                    //     MoveTo(0, i as u16),
-                    //     Print(format!(
-                    //         "{:<width$?} {}",
-                    //         self.screen_start_row + i + 1,
-                    //         &line[..max_len]
-                    //     ))
-                    // )
-                    // .unwrap();
-                    self.print_line_with_highlighting(i as u16, &
-                        &format!("{:<width$?} {}", self.screen_start_row + i + 1, &line[..max_len]), 0, max_len + width + 1
-                    );
-
-
-                    let row = i + self.screen_start_row;
-                    if start_row <= row && row <= end_row {
-                        let mut line = line.clone();
-                        let mut start = width + 1;
-                        if end_row == row {
-                            line = line[..end_col].to_string();
-                        }
-                        if start_row == row {
-                            start += start_col;
-                            line = line[start_col..].to_string();
+            for (i, line) in buf.get_lines(start_row, end_row).iter().enumerate() {
+                let row = start_row + i;
+                let spans = highlights.get(i).map_or(&[][..], Vec::as_slice);
+                let gutter = self.back.print(top + i, 0, &format!("{:<width$?} ", row + 1), self.style("line-number"));
+                let base = if row == buf.cursor_row { cursor_line } else { normal };
+                self.print_line_with_highlighting((top + i, gutter), line, spans, start_col, text_cols, base);
+
+                if let Some(((sel_start_row, sel_start_col), (sel_end_row, sel_end_col))) = selection {
+                    if sel_start_row <= row && row <= sel_end_row {
+                        let from = if sel_start_row == row { display_col(line, sel_start_col) } else { 0 };
+                        let to = if sel_end_row == row { display_col(line, sel_end_col) } else { display_width(line) };
+                        let from = from.max(start_col);
+                        let to = to.min(start_col + text_cols);
+                        if from > to {
+                            // The selected part of this line is scrolled off screen
+                            continue;
                         }
-                        let max_len = std::cmp::min(line.len(), self.screen_cols - width - 1);
-                        execute!(
-                            stdout(),
-                            SetBackgroundColor(Color::White),
-                            SetForegroundColor(Color::Black),
-                            MoveTo(start as u16, i as u16),
-                            Print(if line.is_empty() {
-                                " "
-                            } else {
-                                &line[..max_len]
-                            }),
-                            ResetColor
-                        )
-                        .unwrap();
+                        // An empty selection still shows as one cell
+                        let to = to.max(from + 1);
+                        self.back.restyle(top + i, from - start_col + width + 1, to - start_col + width + 1, self.style("selection"));
                     }
                 }
-            } else if !flush && self.last_buffer_id != editor.cur_buf_id() || self.last_get_lines != Some((self.screen_start_row, self.screen_start_row + self.screen_rows - 1)) {
-                execute!(stdout(), Clear(ClearType::All), MoveTo(0, 0)).unwrap();
-                self.last_get_lines = Some((self.screen_start_row, self.screen_start_row + self.screen_rows));
-                for (i, line) in buf
-                    .get_lines(
-                        self.screen_start_row,
-                        self.screen_start_row + self.screen_rows,
-                    )
-                    .iter()
-                    .enumerate()
-                {
-                    let max_len = std::cmp::min(line.len(), self.screen_cols - width - 1);
-                    // execute!(
-                    //     stdout(),
-                    //     MoveTo(0, i as u16),
-                    //     Print(format!(
-                    //         "{:<width$?} {}",
-                    //         self.screen_start_row + i + 1,
-                    //         &line[..max_len]
-                    //     ))
-                    // )
-                    // .unwrap();
-                    self.print_line_with_highlighting(i as u16, &
-                        &format!("{:<width$?} {}", self.screen_start_row + i + 1, &line[..max_len]), 0, max_len + width + 1
-                    );
-
-                }
             }
-            
+
             // Draw over the status line
-            execute!(stdout(), SetAttribute(Attribute::Italic), MoveTo(0, self.screen_rows as u16), SetBackgroundColor(Self::STATUS_COLOR), Clear(ClearType::CurrentLine))
-                .unwrap();
-            execute!(stdout(), MoveTo(0, self.screen_rows as u16), Print(&self.status), ResetColor).unwrap();
-
-            execute!(
-                stdout(),
-                MoveTo(
-                    (cur_col + width + 1) as u16,
-                    (cur_row - self.screen_start_row) as u16
-                )
-            )
-            .unwrap();
+            self.back.clear_row(self.screen_rows, self.style("status-bar"));
+            self.back.print(self.screen_rows, 0, &self.status, self.style("status-bar"));
+
+            // Show the mode the keys are in, unless it's the usual one
+            let mode = editor.keymap().mode();
+            if mode != DEFAULT_MODE {
+                let indicator = format!(" -- {} -- ", mode.to_uppercase());
+                let col = self.screen_cols.saturating_sub(indicator.len());
+                self.back.print(self.screen_rows, col, &indicator, self.style("status-bar"));
+            }
+            self.back.set_cursor_shape(match mode {
+                DEFAULT_MODE => CursorShape::Default,
+                "insert" => CursorShape::Bar,
+                _ => CursorShape::Block,
+            });
+
+            // The cursor may have been scrolled off screen
+            self.back.set_cursor(view.to_screen(buf.cur_screen_pos()).map(|(row, col)| (top + row, col)));
         }
-        Ok(())
+        self.present()
     }
     fn set_status(&mut self, status: &str) -> Result<(), String> {
         self.status = status.to_string();
         Ok(())
     }
-    fn wait_for_input(&mut self, editor: &Editor) -> Result<Input, String> {
-        loop {
-            self.render(editor, false)?;
-            // Wait up to 1s for another event
-            if poll(std::time::Duration::from_millis(1_000)).is_ok() {
-                // It's guaranteed that read() won't block if `poll` returns `Ok(true)`
-                if let Ok(event) = read() {
-                    match event {
-                        Event::Key(key_event) => {
-                            let mut result = match key_event.code {
-                                KeyCode::Backspace => Input::Backspace,
-                                KeyCode::Delete => Input::Delete,
-                                KeyCode::Left => Input::Left,
-                                KeyCode::Right => Input::Right,
-                                KeyCode::Up => Input::Up,
-                                KeyCode::Down => Input::Down,
-                                KeyCode::Enter => Input::Enter,
-                                KeyCode::Tab => Input::Tab,
-                                KeyCode::Esc => Input::Esc,
-                                KeyCode::Home => Input::Home,
-                                KeyCode::End => Input::End,
-                                KeyCode::PageUp => Input::PageUp,
-                                KeyCode::PageDown => Input::PageDown,
-                                KeyCode::Char(ch) => Input::Char(ch),
-                                _ => continue,
-                            };
-                            if key_event.modifiers.contains(KeyModifiers::SHIFT) {
-                                result = Input::Shift(Box::new(result));
-                            }
-                            if key_event.modifiers.contains(KeyModifiers::CONTROL) {
-                                result = Input::Control(Box::new(result));
-                            }
-                            if key_event.modifiers.contains(KeyModifiers::ALT) {
-                                result = Input::Alt(Box::new(result));
-                            }
-                            return Ok(result);
-                        }
-                        Event::Mouse(_) => {}
-                        Event::Resize(cols, rows) => {
-                            self.screen_cols = cols as usize;
-                            self.screen_rows = rows as usize;
-                            self.render(editor, true)?;
-                        }
-                        _ => {}
-                    }
-                }
-            }
-        }
+    fn wait_for_events(&mut self, deadline: Option<Instant>) -> Result<Vec<Event>, String> {
+        let events = self.events.wait(deadline);
+        Ok(events.into_iter().filter_map(|event| self.to_view(event)).collect())
     }
 
     fn prompt(&mut self, text: &str, pre_input: Option<String>) -> Result<String, String> {
         // Go to the status line and ask the question
-        execute!(stdout(), MoveTo(0, self.screen_rows as u16), SetBackgroundColor(Self::STATUS_COLOR), Clear(ClearType::CurrentLine))
-            .unwrap();
-        let mut input = pre_input.unwrap_or(String::new());
-        execute!(
-            stdout(),
-            MoveTo(0, self.screen_rows as u16),
-            SetBackgroundColor(Self::STATUS_COLOR), 
-            Clear(ClearType::CurrentLine),
-            Print(text),
-            Print(&input),
-            ResetColor
-        ).unwrap();
+        let mut input = pre_input.unwrap_or_default();
+        self.print_status_line(&format!("{text}{input}"))?;
         loop {
-            if poll(std::time::Duration::from_millis(1_000)).is_ok() {
-                if let Ok(event) = read() {
-                    match event {
-                        Event::Key(key_event) => {
-                            match key_event.code {
-                                KeyCode::Char(ch) => {
-                                    input.push(ch);
-                                    // Draw the input
-                                    execute!(
-                                        stdout(),
-                                        MoveTo(0, self.screen_rows as u16),
-                                        SetBackgroundColor(Self::STATUS_COLOR), 
-                                        Clear(ClearType::CurrentLine),
-                                        Print(text),
-                                        Print(&input),
-                                        ResetColor
-                                    ).unwrap();
-                                }
-                                KeyCode::Enter => {
-                                    return Ok(input);
-                                }
-                                KeyCode::Esc => {
-                                    return Err("User cancelled".to_string());
-                                }
-                                KeyCode::Backspace => {
-                                    input.pop();
-                                    execute!(
-                                        stdout(),
-                                        MoveTo(0, self.screen_rows as u16),
-                                        SetBackgroundColor(Self::STATUS_COLOR), 
-                                        Clear(ClearType::CurrentLine),
-                                        Print(text),
-                                        Print(&input),
-                                        ResetColor
-                                    ).unwrap();
-                                }
-                                _ => continue,
-                            }
-                        }
-                        _ => continue,
-                    }
+            match self.next_key()? {
+                Input::Char(ch) => {
+                    input.push(ch);
+                    // Draw the input
+                    self.print_status_line(&format!("{text}{input}"))?;
+                }
+                Input::Enter => {
+                    return Ok(input);
+                }
+                Input::Esc => {
+                    return Err("User cancelled".to_string());
+                }
+                Input::Backspace => {
+                    input.pop();
+                    self.print_status_line(&format!("{text}{input}"))?;
                 }
+                Input::Paste(pasted) => {
+                    // The input is a single line
+                    input.push_str(&pasted.replace('\n', " "));
+                    self.print_status_line(&format!("{text}{input}"))?;
+                }
+                _ => continue,
             }
         }
-        // Ok(String::new())
     }
     fn ask(&mut self, prompt: &str, yes: &str, no: &str) -> Result<bool, String> {
-        // Ok(false)
         // Go to the status line and ask the question
-        execute!(stdout(), MoveTo(0, self.screen_rows as u16), SetBackgroundColor(Self::STATUS_COLOR), Clear(ClearType::CurrentLine))
-            .unwrap();
-        execute!(stdout(), MoveTo(0, self.screen_rows as u16), Print(prompt), Print(format!(" {} / {}", yes, no)), ResetColor).unwrap();
+        self.print_status_line(&format!("{} {} / {}", prompt, yes, no))?;
         loop {
-            if poll(std::time::Duration::from_millis(1_000)).is_ok() {
-                if let Ok(event) = read() {
-                    match event {
-                        Event::Key(key_event) => {
-                            match key_event.code {
-                                KeyCode::Char('y') => return Ok(true),
-                                KeyCode::Char('n') => return Ok(false),
-                                _ => continue,
-                            }
-                        }
-                        _ => continue,
-                    }
-                }
+            match self.next_key()? {
+                Input::Char('y') => return Ok(true),
+                Input::Char('n') => return Ok(false),
+                _ => continue,
             }
         }
     }
     fn choose(&mut self, prompt: &str, options: Vec<String>) -> Result<String, String> {
-        // Ok(options.into_iter().next().unwrap())
         // Go to the status line and ask the question
-        execute!(stdout(), MoveTo(0, self.screen_rows as u16), SetBackgroundColor(Self::STATUS_COLOR), Clear(ClearType::CurrentLine))
-            .unwrap();
-        execute!(stdout(), MoveTo(0, self.screen_rows as u16 - 1 - options.len() as u16), Print(prompt), ResetColor).unwrap();
+        let first = self.screen_rows.saturating_sub(options.len() + 1);
+        self.back.print(first, 0, prompt, self.style("normal"));
         for (i, option) in options.iter().enumerate() {
-            execute!(
-                stdout(),
-                MoveTo(0, self.screen_rows as u16 - options.len() as u16 + i as u16 - 1),
-                Print(format!("{}: {}", i + 1, option))
-            )
-            .unwrap();
+            self.back.print(first + i + 1, 0, &format!("{}: {}", i + 1, option), self.style("normal"));
         }
+        self.print_status_line("")?;
         loop {
-            if poll(std::time::Duration::from_millis(1_000)).is_ok() {
-                if let Ok(event) = read() {
-                    match event {
-                        Event::Key(key_event) => {
-                            if let KeyCode::Char(ch) = key_event.code {
-                                if let Some(index) = ch.to_digit(10) {
-                                    if index as usize <= options.len() {
-                                        return Ok(options[index as usize - 1].clone());
-                                    }
-                                }
-                            }
-                        }
-                        _ => continue,
+            if let Input::Char(ch) = self.next_key()? {
+                if let Some(index) = ch.to_digit(10) {
+                    if index as usize <= options.len() {
+                        return Ok(options[index as usize - 1].clone());
                     }
                 }
             }
         }
     }
+    fn pick_with_preview(
+        &mut self,
+        prompt: &str,
+        options: &[String],
+        preview: &dyn Fn(usize) -> Vec<String>,
+    ) -> Result<usize, String> {
+        let mut filter = String::new();
+        let mut selected = 0;
+        loop {
+            let matches = fuzzy_filter(&filter, options);
+            selected = selected.min(matches.len().saturating_sub(1));
+            let preview = matches.get(selected).map(|&index| preview(index)).unwrap_or_default();
+            self.print_picker(prompt, &filter, options, &matches, selected, &preview)?;
+
+            match self.next_key()? {
+                Input::Char(ch) => {
+                    filter.push(ch);
+                    selected = 0;
+                }
+                Input::Backspace => {
+                    filter.pop();
+                    selected = 0;
+                }
+                Input::Up => selected = selected.saturating_sub(1),
+                Input::Down => selected += 1,
+                Input::Enter => {
+                    if let Some(&index) = matches.get(selected) {
+                        return Ok(index);
+                    }
+                }
+                Input::Esc => {
+                    return Err("User cancelled".to_string());
+                }
+                _ => continue,
+            }
+        }
+    }
     fn get_num(&mut self, prompt: &str) -> Result<isize, String> {
-        // Ok(0)
         // Go to the status line and ask the question
-        execute!(stdout(), MoveTo(0, self.screen_rows as u16), SetBackgroundColor(Self::STATUS_COLOR), Clear(ClearType::CurrentLine))
-            .unwrap();
-        execute!(stdout(), MoveTo(0, self.screen_rows as u16), Print(prompt), ResetColor).unwrap();
         let mut num = String::new();
+        self.print_status_line(prompt)?;
         loop {
-            if poll(std::time::Duration::from_millis(1_000)).is_ok() {
-                if let Ok(event) = read() {
-                    match event {
-                        Event::Key(key_event) => {
-                            match key_event.code {
-                                KeyCode::Char(ch) => {
-                                    if let Some(_digit) = ch.to_digit(10) {
-                                        num.push(ch);
-                                    }
-                                }
-                                KeyCode::Enter => {
-                                    if let Ok(num) = num.parse() {
-                                        return Ok(num);
-                                    }
-                                }
-                                KeyCode::Esc => {
-                                    return Err("User cancelled".to_string());
-                                }
-                                KeyCode::Backspace => {
-                                    num.pop();
-                                }
-                                _ => continue,
-                            }
-                        }
-                        _ => continue,
+            match self.next_key()? {
+                Input::Char(ch) => {
+                    if let Some(_digit) = ch.to_digit(10) {
+                        num.push(ch);
+                        self.print_status_line(&format!("{prompt}{num}"))?;
+                    }
+                }
+                Input::Enter => {
+                    if let Ok(num) = num.parse() {
+                        return Ok(num);
                     }
                 }
+                Input::Esc => {
+                    return Err("User cancelled".to_string());
+                }
+                Input::Backspace => {
+                    num.pop();
+                    self.print_status_line(&format!("{prompt}{num}"))?;
+                }
+                _ => continue,
             }
         }
     }
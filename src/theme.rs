@@ -0,0 +1,446 @@
+use super::Scope;
+use crossterm::style::Color;
+use serde_yaml::Value;
+use std::{collections::BTreeMap, path::Path};
+
+/// The parts of the screen a theme can style. The first eight are the
+/// highlighting scopes; `normal` is also the background of the buffer.
+pub const THEME_ELEMENTS: &[&str] = &[
+    "normal",
+    "keyword",
+    "type",
+    "builtin",
+    "operator",
+    "string",
+    "comment",
+    "number",
+    "selection",
+    "line-number",
+    "cursor-line",
+    "status-bar",
+    "tab-bar",
+];
+
+/// How many colors a terminal can show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorDepth {
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+/// Approximate RGB values of the 16 ANSI colors, for matching colors to them.
+const ANSI_16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (128, 0, 0)),
+    (Color::DarkGreen, (0, 128, 0)),
+    (Color::DarkYellow, (128, 128, 0)),
+    (Color::DarkBlue, (0, 0, 128)),
+    (Color::DarkMagenta, (128, 0, 128)),
+    (Color::DarkCyan, (0, 128, 128)),
+    (Color::Grey, (192, 192, 192)),
+    (Color::DarkGrey, (128, 128, 128)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (0, 0, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// The levels of each channel in the 6x6x6 color cube of 256-color terminals.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+fn nearest_level(value: u8) -> usize {
+    (0..CUBE_LEVELS.len())
+        .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - value as i32).abs())
+        .unwrap()
+}
+
+/// The RGB value of a color in the 256-color palette.
+fn ansi_256_rgb(value: u8) -> (u8, u8, u8) {
+    match value {
+        0..=15 => ANSI_16[value as usize].1,
+        16..=231 => {
+            let i = value - 16;
+            (CUBE_LEVELS[(i / 36) as usize], CUBE_LEVELS[(i / 6 % 6) as usize], CUBE_LEVELS[(i % 6) as usize])
+        }
+        _ => {
+            let level = 8 + (value - 232) * 10;
+            (level, level, level)
+        }
+    }
+}
+
+impl ColorDepth {
+    /// Guess what the terminal supports from `COLORTERM` and `TERM`.
+    pub fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+
+    /// The closest color to `color` that the terminal can show.
+    pub fn fit(self, color: Color) -> Color {
+        match (self, color) {
+            (ColorDepth::TrueColor, _) => color,
+            (ColorDepth::Ansi256, Color::Rgb { r, g, b }) => {
+                let (ri, gi, bi) = (nearest_level(r), nearest_level(g), nearest_level(b));
+                let cube = (16 + 36 * ri + 6 * gi + bi) as u8;
+                // Grays are often closer to the grayscale ramp than to the cube
+                let gray = ((r as u32 + g as u32 + b as u32) / 3).clamp(8, 238);
+                let ramp = (232 + (gray - 8) / 10) as u8;
+                [cube, ramp]
+                    .into_iter()
+                    .min_by_key(|&value| distance(ansi_256_rgb(value), (r, g, b)))
+                    .map_or(color, Color::AnsiValue)
+            }
+            (ColorDepth::Ansi16, Color::Rgb { r, g, b }) => Self::nearest_16((r, g, b)),
+            (ColorDepth::Ansi16, Color::AnsiValue(value)) => Self::nearest_16(ansi_256_rgb(value)),
+            _ => color,
+        }
+    }
+
+    fn nearest_16(rgb: (u8, u8, u8)) -> Color {
+        ANSI_16.iter().min_by_key(|(_, value)| distance(*value, rgb)).unwrap().0
+    }
+}
+
+/// Parse a color: a name such as `dark_magenta`, a 256-color palette
+/// number, `#rrggbb`, or `default` for the terminal's own color.
+pub fn parse_color(text: &str) -> Result<Color, String> {
+    if text == "default" {
+        return Ok(Color::Reset);
+    }
+    if let Some(hex) = text.strip_prefix('#') {
+        let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Color::Rgb { r, g, b }),
+            _ => Err(format!("invalid color {text}")),
+        };
+    }
+    if let Ok(value) = text.parse::<u8>() {
+        return Ok(Color::AnsiValue(value));
+    }
+    Color::try_from(text).map_err(|_| format!("unknown color {text}"))
+}
+
+/// How to draw a piece of text. Colors that aren't set are taken from
+/// the style underneath.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Style {
+    /// Parse a style written like `white on dark_magenta bold`: an optional
+    /// foreground color, `on` and a background color, and any of `bold`,
+    /// `italic` and `underline`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut style = Self::default();
+        let mut words = spec.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "bold" => style.bold = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "on" => {
+                    let color = words.next().ok_or("expected a background color after `on`")?;
+                    style.bg = Some(parse_color(color)?);
+                }
+                color => style.fg = Some(parse_color(color)?),
+            }
+        }
+        Ok(style)
+    }
+
+    /// This style drawn over `base`.
+    pub fn over(self, base: Style) -> Style {
+        Style {
+            fg: self.fg.or(base.fg),
+            bg: self.bg.or(base.bg),
+            bold: self.bold || base.bold,
+            italic: self.italic || base.italic,
+            underline: self.underline || base.underline,
+        }
+    }
+}
+
+/// A named set of styles for the elements in `THEME_ELEMENTS`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Theme {
+    pub name: String,
+    styles: BTreeMap<String, Style>,
+}
+
+impl Theme {
+    /// A theme from `(element, style)` pairs. An `inherit` element names
+    /// a theme in `themes` to start from; otherwise the theme starts from
+    /// the built-in dark theme.
+    pub fn from_specs(name: &str, specs: &[(String, String)], themes: &Themes) -> Result<Self, String> {
+        let base = match specs.iter().find(|(element, _)| element == "inherit") {
+            Some((_, parent)) => themes.get(parent).ok_or(format!("unknown theme {parent}"))?,
+            None => &themes.themes[0],
+        };
+        let mut theme = Theme {
+            name: name.to_string(),
+            styles: base.styles.clone(),
+        };
+        for (element, spec) in specs.iter().filter(|(element, _)| element != "inherit") {
+            theme.set(element, Style::parse(spec).map_err(|e| format!("{element}: {e}"))?)?;
+        }
+        Ok(theme)
+    }
+
+    pub fn set(&mut self, element: &str, style: Style) -> Result<(), String> {
+        if !THEME_ELEMENTS.contains(&element) {
+            return Err(format!("unknown theme element {element}"));
+        }
+        self.styles.insert(element.to_string(), style);
+        Ok(())
+    }
+
+    /// The style of an element as the theme defines it.
+    pub fn get(&self, element: &str) -> Style {
+        self.styles.get(element).copied().unwrap_or_default()
+    }
+
+    /// The style of an element drawn over normal text.
+    pub fn style(&self, element: &str) -> Style {
+        self.get(element).over(self.get("normal"))
+    }
+
+    pub fn scope(&self, scope: Scope) -> Style {
+        self.style(scope.name())
+    }
+
+    fn builtin(name: &str, specs: &[(&str, &str)]) -> Self {
+        let mut theme = Theme {
+            name: name.to_string(),
+            styles: BTreeMap::new(),
+        };
+        for (element, spec) in specs {
+            theme.set(element, Style::parse(spec).unwrap()).unwrap();
+        }
+        theme
+    }
+}
+
+/// The themes the editor knows, and which one is in use.
+#[derive(Clone, Debug)]
+pub struct Themes {
+    themes: Vec<Theme>,
+    current: usize,
+}
+
+impl Default for Themes {
+    fn default() -> Self {
+        let dark = Theme::builtin(
+            "dark",
+            &[
+                ("normal", "white on black"),
+                ("keyword", "magenta"),
+                ("type", "blue"),
+                ("builtin", "cyan"),
+                ("operator", "yellow"),
+                ("string", "green"),
+                ("comment", "dark_grey"),
+                ("number", "red"),
+                ("selection", "black on white"),
+                ("line-number", "white"),
+                ("status-bar", "white on dark_magenta italic"),
+                ("tab-bar", "white on dark_grey"),
+            ],
+        );
+        let light = Theme::builtin(
+            "light",
+            &[
+                ("normal", "black on white"),
+                ("keyword", "dark_magenta bold"),
+                ("type", "dark_blue"),
+                ("builtin", "dark_cyan"),
+                ("operator", "dark_yellow"),
+                ("string", "dark_green"),
+                ("comment", "dark_grey italic"),
+                ("number", "dark_red"),
+                ("selection", "white on dark_blue"),
+                ("line-number", "dark_grey"),
+                ("cursor-line", "on grey"),
+                ("status-bar", "white on dark_magenta italic"),
+                ("tab-bar", "black on grey"),
+            ],
+        );
+        Self {
+            themes: vec![dark, light],
+            current: 0,
+        }
+    }
+}
+
+impl Themes {
+    pub fn current(&self) -> &Theme {
+        &self.themes[self.current]
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.themes.iter().map(|theme| theme.name.as_str()).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Theme> {
+        self.themes.iter().find(|theme| theme.name == name)
+    }
+
+    /// Switch to the theme with this name.
+    pub fn set(&mut self, name: &str) -> Result<(), String> {
+        self.current = self
+            .themes
+            .iter()
+            .position(|theme| theme.name == name)
+            .ok_or(format!("unknown theme {name}"))?;
+        Ok(())
+    }
+
+    /// Add a theme, replacing any with the same name.
+    pub fn add(&mut self, theme: Theme) {
+        match self.themes.iter().position(|t| t.name == theme.name) {
+            Some(i) => self.themes[i] = theme,
+            None => self.themes.push(theme),
+        }
+    }
+
+    /// Load a theme from a YAML file of `element: style` entries, plus
+    /// an optional `name` (which defaults to the file's name) and `inherit`.
+    pub fn load(&mut self, file: impl AsRef<Path>) -> Result<&Theme, String> {
+        let file = file.as_ref();
+        let text = std::fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+        let doc: Value = serde_yaml::from_str(&text).map_err(|e| format!("{}: {}", file.display(), e))?;
+        let entries = doc.as_mapping().ok_or(format!("{}: expected a mapping", file.display()))?;
+
+        let mut name = file.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut specs = vec![];
+        for (key, value) in entries {
+            let (Some(key), Some(value)) = (key.as_str(), value.as_str()) else {
+                return Err(format!("{}: entries must be strings", file.display()));
+            };
+            if key == "name" {
+                name = value.to_string();
+            } else {
+                specs.push((key.to_string(), value.to_string()));
+            }
+        }
+
+        let theme = Theme::from_specs(&name, &specs, self).map_err(|e| format!("{}: {}", file.display(), e))?;
+        self.add(theme);
+        Ok(self.get(&name).unwrap())
+    }
+
+    /// Load every `.yaml` theme in a directory. Returns the number loaded,
+    /// and an error message for each file that failed.
+    pub fn load_dir(&mut self, dir: impl AsRef<Path>) -> (usize, Vec<String>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return (0, vec![]);
+        };
+        let mut files: Vec<_> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml"))
+            .collect();
+        files.sort();
+
+        let mut loaded = 0;
+        let mut errors = vec![];
+        for file in files {
+            match self.load(&file) {
+                Ok(_) => loaded += 1,
+                Err(e) => errors.push(e),
+            }
+        }
+        (loaded, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colors_and_styles() {
+        assert_eq!(parse_color("dark_magenta"), Ok(Color::DarkMagenta));
+        assert_eq!(parse_color("#ff8000"), Ok(Color::Rgb { r: 255, g: 128, b: 0 }));
+        assert_eq!(parse_color("208"), Ok(Color::AnsiValue(208)));
+        assert_eq!(parse_color("default"), Ok(Color::Reset));
+        assert!(parse_color("#ff80").is_err());
+        assert!(parse_color("#gg0000").is_err());
+        assert!(parse_color("mauve").is_err());
+
+        let style = Style::parse("white on #000000 bold underline").unwrap();
+        assert_eq!(style.fg, Some(Color::White));
+        assert_eq!(style.bg, Some(Color::Rgb { r: 0, g: 0, b: 0 }));
+        assert!(style.bold && style.underline && !style.italic);
+        assert!(Style::parse("red on").is_err());
+
+        let over = Style::parse("italic").unwrap().over(style);
+        assert_eq!((over.fg, over.italic, over.bold), (Some(Color::White), true, true));
+    }
+
+    #[test]
+    fn fits_colors_to_the_terminal() {
+        let orange = Color::Rgb { r: 255, g: 135, b: 0 };
+        assert_eq!(ColorDepth::TrueColor.fit(orange), orange);
+        assert_eq!(ColorDepth::Ansi256.fit(orange), Color::AnsiValue(208));
+        assert_eq!(ColorDepth::Ansi256.fit(Color::Rgb { r: 128, g: 128, b: 128 }), Color::AnsiValue(244));
+        assert_eq!(ColorDepth::Ansi16.fit(orange), Color::Yellow);
+        assert_eq!(ColorDepth::Ansi16.fit(Color::AnsiValue(196)), Color::Red);
+        assert_eq!(ColorDepth::Ansi16.fit(Color::Blue), Color::Blue);
+    }
+
+    #[test]
+    fn builds_and_loads_themes() {
+        let mut themes = Themes::default();
+        let specs = [
+            ("inherit".to_string(), "light".to_string()),
+            ("keyword".to_string(), "red".to_string()),
+        ];
+        let theme = Theme::from_specs("mine", &specs, &themes).unwrap();
+        assert_eq!(theme.get("keyword").fg, Some(Color::Red));
+        assert_eq!(theme.get("type"), themes.get("light").unwrap().get("type"));
+        // Elements are drawn over normal text
+        assert_eq!(theme.style("keyword").bg, Some(Color::White));
+
+        let bad = |element: &str, spec: &str| Theme::from_specs("bad", &[(element.to_string(), spec.to_string())], &themes);
+        assert_eq!(bad("inherit", "nope").unwrap_err(), "unknown theme nope");
+        assert_eq!(bad("gutter", "red").unwrap_err(), "unknown theme element gutter");
+        assert_eq!(bad("keyword", "mauve").unwrap_err(), "keyword: unknown color mauve");
+
+        let dir = std::env::temp_dir().join(format!("lite-themes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("solar.yaml"), "inherit: dark\nstring: \"#b58900\"\n").unwrap();
+        std::fs::write(dir.join("named.yml"), "name: other\nnumber: blue\n").unwrap();
+        std::fs::write(dir.join("broken.yaml"), "keyword: [red]\n").unwrap();
+        let (loaded, errors) = themes.load_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, 2);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].ends_with("entries must be strings"));
+        assert_eq!(themes.names(), vec!["dark", "light", "other", "solar"]);
+        themes.set("solar").unwrap();
+        assert_eq!(themes.current().get("string").fg, Some(Color::Rgb { r: 0xb5, g: 0x89, b: 0 }));
+        assert!(themes.set("missing").is_err());
+    }
+}
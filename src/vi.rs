@@ -0,0 +1,728 @@
+use super::{Direction, Editor, Input, DEFAULT_MODE};
+
+/// The keys of vi's normal mode. Keys held with Ctrl or Alt that aren't
+/// bound here do what they do in the default mode.
+pub const VI_NORMAL_BINDINGS: &[(&str, &str)] = &[
+    ("Esc", "vi-normal"),
+    ("i", "vi-insert"),
+    ("a", "vi-append"),
+    ("I", "vi-insert-start"),
+    ("A", "vi-append-end"),
+    ("o", "vi-open-below"),
+    ("O", "vi-open-above"),
+    ("v", "vi-visual"),
+    ("d", "vi-delete"),
+    ("c", "vi-change"),
+    ("y", "vi-yank"),
+    ("x", "vi-delete-char"),
+    ("D", "vi-delete-to-end"),
+    ("C", "vi-change-to-end"),
+    ("p", "vi-paste-after"),
+    ("P", "vi-paste-before"),
+    (".", "vi-repeat"),
+    ("u", "undo"),
+    ("Ctrl-r", "redo"),
+    (":", "eval"),
+    ("0", "vi-zero"),
+    ("1", "vi-count-1"),
+    ("2", "vi-count-2"),
+    ("3", "vi-count-3"),
+    ("4", "vi-count-4"),
+    ("5", "vi-count-5"),
+    ("6", "vi-count-6"),
+    ("7", "vi-count-7"),
+    ("8", "vi-count-8"),
+    ("9", "vi-count-9"),
+    ("h", "vi-left"),
+    ("j", "vi-down"),
+    ("k", "vi-up"),
+    ("l", "vi-right"),
+    ("Left", "vi-left"),
+    ("Down", "vi-down"),
+    ("Up", "vi-up"),
+    ("Right", "vi-right"),
+    ("w", "vi-word"),
+    ("b", "vi-back-word"),
+    ("e", "vi-word-end"),
+    ("^", "vi-line-start"),
+    ("$", "vi-line-end"),
+    ("Home", "vi-zero"),
+    ("End", "vi-line-end"),
+    ("g g", "vi-first-line"),
+    ("G", "vi-last-line"),
+    ("PageUp", "page-up"),
+    ("PageDown", "page-down"),
+];
+
+/// The keys of vi's visual mode, where motions extend the selection.
+pub const VI_VISUAL_BINDINGS: &[(&str, &str)] = &[
+    ("Esc", "vi-normal"),
+    ("v", "vi-normal"),
+    ("d", "vi-delete"),
+    ("x", "vi-delete"),
+    ("c", "vi-change"),
+    ("y", "vi-yank"),
+    ("0", "vi-zero"),
+    ("1", "vi-count-1"),
+    ("2", "vi-count-2"),
+    ("3", "vi-count-3"),
+    ("4", "vi-count-4"),
+    ("5", "vi-count-5"),
+    ("6", "vi-count-6"),
+    ("7", "vi-count-7"),
+    ("8", "vi-count-8"),
+    ("9", "vi-count-9"),
+    ("h", "vi-left"),
+    ("j", "vi-down"),
+    ("k", "vi-up"),
+    ("l", "vi-right"),
+    ("Left", "vi-left"),
+    ("Down", "vi-down"),
+    ("Up", "vi-up"),
+    ("Right", "vi-right"),
+    ("w", "vi-word"),
+    ("b", "vi-back-word"),
+    ("e", "vi-word-end"),
+    ("^", "vi-line-start"),
+    ("$", "vi-line-end"),
+    ("g g", "vi-first-line"),
+    ("G", "vi-last-line"),
+];
+
+/// The keys of vi's insert mode. Every other key does what it does in
+/// the default mode.
+pub const VI_INSERT_BINDINGS: &[(&str, &str)] = &[("Esc", "vi-normal")];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+/// A motion from one position to another.
+type Step = fn(&[String], (usize, usize)) -> (usize, usize);
+
+/// How much of the text between the cursor and the end of a motion an
+/// operator works on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Extent {
+    /// Up to the end of the motion.
+    Exclusive,
+    /// Up to and including the character at the end of the motion.
+    Inclusive,
+    /// Every line from the cursor's to the motion's.
+    Linewise,
+}
+
+/// The state of vi-style modal editing: the count and operator typed so
+/// far, the register text is yanked into, and the keys of the last change
+/// for `.` to repeat.
+///
+/// Keys are looked up in the `normal`, `insert` and `visual` modes of the
+/// keymap, and the `vi-` actions they're bound to are run here.
+#[derive(Clone, Debug, Default)]
+pub struct Vi {
+    count: Option<usize>,
+    /// The operator waiting for a motion, with the count typed before it.
+    operator: Option<(Operator, usize)>,
+    register: String,
+    /// Whether the register holds whole lines.
+    linewise: bool,
+    /// The keys of the command being typed.
+    keys: Vec<Input>,
+    /// Whether the command being typed changed the buffer.
+    changed: bool,
+    last_change: Vec<Input>,
+    /// Whether `.` is replaying the last change, which isn't recorded again.
+    replaying: bool,
+}
+
+impl Vi {
+    /// Whether the editor is in one of vi's modes.
+    pub fn is_active(editor: &Editor) -> bool {
+        editor.keymap().mode() != DEFAULT_MODE
+    }
+
+    /// Whether typed characters are commands rather than text, as they
+    /// are in normal and visual mode.
+    pub fn is_command_mode(editor: &Editor) -> bool {
+        Self::is_active(editor) && editor.keymap().mode() != "insert"
+    }
+
+    /// Record a key of the command being typed, so `.` can repeat it.
+    pub fn record(&mut self, input: &Input) {
+        if !self.replaying {
+            self.keys.push(input.clone());
+        }
+    }
+
+    /// Drop the count and operator typed so far, after a key that isn't bound.
+    pub fn cancel(&mut self, editor: &Editor) {
+        if editor.keymap().mode() == "normal" {
+            self.count = None;
+            self.operator = None;
+            self.finish(editor);
+        }
+    }
+
+    /// Note that a command outside of vi's actions ran.
+    pub fn finish(&mut self, editor: &Editor) {
+        if self.count.is_some() || self.operator.is_some() || editor.keymap().mode() != "normal" {
+            // The command isn't over yet
+            return;
+        }
+        let keys = std::mem::take(&mut self.keys);
+        if std::mem::take(&mut self.changed) && !self.replaying {
+            self.last_change = keys;
+        }
+    }
+
+    /// The keys of the last change and how many times to repeat them, for `.`.
+    /// Until `end_repeat`, the keys replayed aren't recorded.
+    pub fn start_repeat(&mut self) -> (Vec<Input>, usize) {
+        let count = self.count.take().unwrap_or(1);
+        self.keys.clear();
+        self.replaying = true;
+        (self.last_change.clone(), count)
+    }
+
+    pub fn end_repeat(&mut self) {
+        self.replaying = false;
+    }
+
+    /// Run one of the `vi-` actions.
+    pub fn run(&mut self, action: &str, editor: &mut Editor) {
+        let mode = editor.keymap().mode().to_string();
+        if let Some(digit) = action.strip_prefix("vi-count-").and_then(|digit| digit.parse::<usize>().ok()) {
+            self.count = Some(self.count.unwrap_or(0) * 10 + digit);
+            return;
+        }
+        if action == "vi-zero" && self.count.is_some() {
+            self.count = self.count.map(|count| count * 10);
+            return;
+        }
+        let given = self.count.take();
+        let count = given.unwrap_or(1);
+
+        match action {
+            "vi-normal" => {
+                self.operator = None;
+                if mode == "insert" {
+                    // Leaving insert mode steps back onto the last character typed
+                    if editor.cur_buf().is_some_and(|buf| buf.cur_pos().1 > 0) {
+                        editor.move_cur(Direction::Left);
+                    }
+                } else if mode == "visual" {
+                    editor.unselect();
+                }
+                self.set_mode(editor, "normal");
+            }
+            "vi-insert" => self.insert(editor),
+            "vi-append" => {
+                let (row, col) = self.pos(editor);
+                let len = self.line(editor, row).len();
+                editor.goto_cur((row, next_char(&self.line(editor, row), col).min(len)));
+                self.insert(editor);
+            }
+            "vi-insert-start" => {
+                let (row, _) = self.pos(editor);
+                editor.goto_cur((row, first_non_blank(&self.line(editor, row))));
+                self.insert(editor);
+            }
+            "vi-append-end" => {
+                let (row, _) = self.pos(editor);
+                editor.goto_cur((row, self.line(editor, row).len()));
+                self.insert(editor);
+            }
+            "vi-open-below" => {
+                let (row, _) = self.pos(editor);
+                editor.goto_cur((row, self.line(editor, row).len()));
+                editor.insert('\n');
+                self.insert(editor);
+            }
+            "vi-open-above" => {
+                let (row, _) = self.pos(editor);
+                editor.goto_cur((row, 0));
+                editor.insert('\n');
+                editor.goto_cur((row, 0));
+                self.insert(editor);
+            }
+            "vi-visual" => {
+                editor.unselect();
+                editor.select();
+                self.set_mode(editor, "visual");
+            }
+            "vi-delete" | "vi-change" | "vi-yank" => {
+                let operator = match action {
+                    "vi-delete" => Operator::Delete,
+                    "vi-change" => Operator::Change,
+                    _ => Operator::Yank,
+                };
+                if mode == "visual" {
+                    if let Some((start, end)) = editor.selection_range() {
+                        editor.unselect();
+                        self.set_mode(editor, "normal");
+                        // The selection takes in the character under the cursor
+                        self.apply(editor, operator, start, end, Extent::Inclusive);
+                    }
+                } else {
+                    match self.operator.take() {
+                        // Doubling an operator, as in `dd`, works on whole lines
+                        Some((pending, before)) if pending == operator => {
+                            let (row, col) = self.pos(editor);
+                            let last = (row + before * count - 1).min(self.line_count(editor) - 1);
+                            self.apply(editor, operator, (row, col), (last, 0), Extent::Linewise);
+                        }
+                        _ => {
+                            self.operator = Some((operator, count));
+                            return;
+                        }
+                    }
+                }
+            }
+            "vi-delete-char" => {
+                let (row, col) = self.pos(editor);
+                let line = self.line(editor, row);
+                let mut end = col;
+                for _ in 0..count {
+                    end = next_char(&line, end);
+                }
+                if end > col {
+                    self.apply(editor, Operator::Delete, (row, col), (row, end.min(line.len())), Extent::Exclusive);
+                }
+            }
+            "vi-delete-to-end" | "vi-change-to-end" => {
+                let (row, col) = self.pos(editor);
+                let end = (row, self.line(editor, row).len());
+                let operator = if action == "vi-delete-to-end" { Operator::Delete } else { Operator::Change };
+                self.apply(editor, operator, (row, col), end, Extent::Exclusive);
+            }
+            "vi-paste-after" | "vi-paste-before" => {
+                self.paste(editor, action == "vi-paste-after", count);
+            }
+            _ => {
+                // A count before the operator multiplies the one before the motion
+                let count = count * self.operator.map_or(1, |(_, before)| before);
+                let Some((target, extent)) = self.motion(action, editor, count, given.is_some()) else {
+                    return;
+                };
+                match self.operator.take() {
+                    Some((operator, _)) => {
+                        let pos = self.pos(editor);
+                        self.apply(editor, operator, pos, target, extent);
+                    }
+                    None => editor.goto_cur(target),
+                }
+            }
+        }
+        self.finish(editor);
+    }
+
+    fn set_mode(&mut self, editor: &mut Editor, mode: &str) {
+        if editor.keymap().mode() == "insert" && mode == "normal" {
+            // Everything typed in insert mode is part of the change
+            self.changed = true;
+        }
+        let _ = editor.keymap_mut().set_mode(mode);
+    }
+
+    fn insert(&mut self, editor: &mut Editor) {
+        self.set_mode(editor, "insert");
+    }
+
+    fn pos(&self, editor: &Editor) -> (usize, usize) {
+        editor.cur_buf().map_or((0, 0), |buf| buf.cur_pos())
+    }
+
+    fn line(&self, editor: &Editor, row: usize) -> String {
+        editor.cur_buf().and_then(|buf| buf.content().get(row).cloned()).unwrap_or_default()
+    }
+
+    fn line_count(&self, editor: &Editor) -> usize {
+        editor.cur_buf().map_or(1, |buf| buf.content().len())
+    }
+
+    /// Where a motion moves the cursor to, repeated `count` times, and
+    /// how much an operator would work on. `given` is whether the count
+    /// was typed, since `G` goes to the last line but `1G` to the first.
+    fn motion(&self, action: &str, editor: &Editor, count: usize, given: bool) -> Option<((usize, usize), Extent)> {
+        let buf = editor.cur_buf()?;
+        let lines = buf.content();
+        let (row, col) = buf.cur_pos();
+        let last_row = lines.len() - 1;
+        let repeat = |step: Step| {
+            (0..count).fold((row, col), |pos, _| step(lines, pos))
+        };
+        Some(match action {
+            "vi-left" => ((row, (0..count).fold(col, |col, _| char_start(&lines[row], col.saturating_sub(1)))), Extent::Exclusive),
+            "vi-right" => ((row, (0..count).fold(col, |col, _| next_char(&lines[row], col))), Extent::Exclusive),
+            "vi-up" => ((row.saturating_sub(count), col), Extent::Linewise),
+            "vi-down" => (((row + count).min(last_row), col), Extent::Linewise),
+            // As in vi, `cw` changes to the end of the word rather than up to the next one
+            "vi-word" if self.operator.is_some_and(|(operator, _)| operator == Operator::Change) => {
+                (repeat(word_end), Extent::Inclusive)
+            }
+            "vi-word" => (repeat(next_word), Extent::Exclusive),
+            "vi-back-word" => (repeat(prev_word), Extent::Exclusive),
+            "vi-word-end" => (repeat(word_end), Extent::Inclusive),
+            "vi-zero" => ((row, 0), Extent::Exclusive),
+            "vi-line-start" => ((row, first_non_blank(&lines[row])), Extent::Exclusive),
+            "vi-line-end" => {
+                let row = (row + count - 1).min(last_row);
+                ((row, lines[row].len()), Extent::Exclusive)
+            }
+            "vi-first-line" => (((count - 1).min(last_row), 0), Extent::Linewise),
+            "vi-last-line" if given => (((count - 1).min(last_row), 0), Extent::Linewise),
+            "vi-last-line" => ((last_row, 0), Extent::Linewise),
+            _ => return None,
+        })
+    }
+
+    /// Delete, change or yank the text between two positions.
+    fn apply(&mut self, editor: &mut Editor, operator: Operator, from: (usize, usize), to: (usize, usize), extent: Extent) {
+        let Some(lines) = editor.cur_buf().map(|buf| buf.content().to_vec()) else {
+            return;
+        };
+        let (mut start, mut end) = if from <= to { (from, to) } else { (to, from) };
+        match extent {
+            Extent::Exclusive => {}
+            Extent::Inclusive => end = (end.0, next_char(&lines[end.0], end.1)),
+            Extent::Linewise if operator == Operator::Change => {
+                // Changing lines keeps one empty line to type on
+                start = (start.0, 0);
+                end = (end.0, lines[end.0].len());
+            }
+            Extent::Linewise if end.0 + 1 < lines.len() => {
+                start = (start.0, 0);
+                end = (end.0 + 1, 0);
+            }
+            Extent::Linewise if start.0 > 0 => {
+                // The last line has no newline after it, so take the one before it
+                start = (start.0 - 1, lines[start.0 - 1].len());
+                end = (end.0, lines[end.0].len());
+            }
+            Extent::Linewise => {
+                start = (0, 0);
+                end = (end.0, lines[end.0].len());
+            }
+        }
+
+        let text = text_between(&lines, start, end);
+        self.register = text.clone();
+        self.linewise = extent == Extent::Linewise;
+        if self.linewise && !self.register.ends_with('\n') {
+            self.register = self.register.trim_start_matches('\n').to_string() + "\n";
+        }
+
+        match operator {
+            Operator::Yank => editor.goto_cur(start),
+            Operator::Delete | Operator::Change => {
+                editor.unselect();
+                editor.goto_cur(end);
+                editor.delete(text.chars().count());
+                if extent == Extent::Linewise && operator == Operator::Delete {
+                    let (row, _) = self.pos(editor);
+                    editor.goto_cur((row, first_non_blank(&self.line(editor, row))));
+                }
+                self.changed = true;
+                if operator == Operator::Change {
+                    self.insert(editor);
+                }
+            }
+        }
+    }
+
+    /// Put the register after or before the cursor, `count` times.
+    fn paste(&mut self, editor: &mut Editor, after: bool, count: usize) {
+        if self.register.is_empty() {
+            return;
+        }
+        let text = self.register.repeat(count);
+        let (row, col) = self.pos(editor);
+        if self.linewise {
+            if after {
+                editor.goto_cur((row, self.line(editor, row).len()));
+                editor.insert(format!("\n{}", text.strip_suffix('\n').unwrap_or(&text)));
+                editor.goto_cur((row + 1, 0));
+            } else {
+                editor.goto_cur((row, 0));
+                editor.insert(&text);
+                editor.goto_cur((row, 0));
+            }
+        } else {
+            if after {
+                editor.goto_cur((row, next_char(&self.line(editor, row), col)));
+            }
+            editor.insert(&text);
+        }
+        self.changed = true;
+    }
+}
+
+/// The column after the character at `col`, or `col` at the end of the line.
+fn next_char(line: &str, col: usize) -> usize {
+    line.get(col..).and_then(|rest| rest.chars().next()).map_or(col, |ch| col + ch.len_utf8())
+}
+
+fn first_non_blank(line: &str) -> usize {
+    line.find(|ch: char| !ch.is_whitespace()).unwrap_or(line.len())
+}
+
+/// Whether a character is whitespace, part of a word, or punctuation.
+/// The end of a line reads as whitespace.
+fn class(ch: Option<char>) -> u8 {
+    match ch {
+        None => 0,
+        Some(ch) if ch.is_whitespace() => 0,
+        Some(ch) if ch.is_alphanumeric() || ch == '_' => 1,
+        Some(_) => 2,
+    }
+}
+
+fn char_at(lines: &[String], (row, col): (usize, usize)) -> Option<char> {
+    lines[row].get(col..).and_then(|rest| rest.chars().next())
+}
+
+/// The position after `pos`, wrapping onto the next line.
+fn forward(lines: &[String], (row, col): (usize, usize)) -> Option<(usize, usize)> {
+    if col < lines[row].len() {
+        Some((row, next_char(&lines[row], col)))
+    } else if row + 1 < lines.len() {
+        Some((row + 1, 0))
+    } else {
+        None
+    }
+}
+
+/// The position before `pos`, wrapping onto the previous line.
+fn backward(lines: &[String], (row, col): (usize, usize)) -> Option<(usize, usize)> {
+    if col > 0 {
+        Some((row, char_start(&lines[row], col - 1)))
+    } else if row > 0 {
+        Some((row - 1, lines[row - 1].len()))
+    } else {
+        None
+    }
+}
+
+/// The start of the next word, as `w` moves.
+fn next_word(lines: &[String], pos: (usize, usize)) -> (usize, usize) {
+    let mut pos = pos;
+    let start = class(char_at(lines, pos));
+    while start != 0 && class(char_at(lines, pos)) == start {
+        match forward(lines, pos) {
+            Some(next) if next.0 == pos.0 => pos = next,
+            _ => break,
+        }
+    }
+    while class(char_at(lines, pos)) == 0 {
+        match forward(lines, pos) {
+            Some(next) => pos = next,
+            None => break,
+        }
+    }
+    pos
+}
+
+/// The start of this word or the one before it, as `b` moves.
+fn prev_word(lines: &[String], pos: (usize, usize)) -> (usize, usize) {
+    let mut pos = match backward(lines, pos) {
+        Some(pos) => pos,
+        None => return pos,
+    };
+    while class(char_at(lines, pos)) == 0 {
+        match backward(lines, pos) {
+            Some(prev) => pos = prev,
+            None => return pos,
+        }
+    }
+    let class_here = class(char_at(lines, pos));
+    while let Some(prev) = backward(lines, pos).filter(|prev| prev.0 == pos.0) {
+        if class(char_at(lines, prev)) != class_here {
+            break;
+        }
+        pos = prev;
+    }
+    pos
+}
+
+/// The last character of this word or the next one, as `e` moves.
+fn word_end(lines: &[String], pos: (usize, usize)) -> (usize, usize) {
+    let mut pos = match forward(lines, pos) {
+        Some(pos) => pos,
+        None => return pos,
+    };
+    while class(char_at(lines, pos)) == 0 {
+        match forward(lines, pos) {
+            Some(next) => pos = next,
+            None => return pos,
+        }
+    }
+    let class_here = class(char_at(lines, pos));
+    while let Some(next) = forward(lines, pos).filter(|next| next.0 == pos.0) {
+        if class(char_at(lines, next)) != class_here {
+            break;
+        }
+        pos = next;
+    }
+    pos
+}
+
+/// The text from one position up to another, with newlines between lines.
+/// Columns inside a character are taken back to its start.
+fn text_between(lines: &[String], start: (usize, usize), end: (usize, usize)) -> String {
+    let (start_col, end_col) = (char_start(&lines[start.0], start.1), char_start(&lines[end.0], end.1));
+    if start.0 == end.0 {
+        return lines[start.0][start_col..end_col.max(start_col)].to_string();
+    }
+    let mut text = lines[start.0][start_col..].to_string();
+    for line in &lines[start.0 + 1..end.0] {
+        text.push('\n');
+        text.push_str(line);
+    }
+    text.push('\n');
+    text.push_str(&lines[end.0][..end_col]);
+    text
+}
+
+/// The start of the character at `col`, which is at most the end of the line.
+fn char_start(line: &str, col: usize) -> usize {
+    let mut col = col.min(line.len());
+    while !line.is_char_boundary(col) {
+        col -= 1;
+    }
+    col
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_keys, Command, KeyPress};
+
+    /// An editor in normal mode on some text, with the cursor at the start.
+    fn normal(text: &str) -> (Editor, Vi) {
+        let mut editor = Editor::default();
+        editor.insert(text);
+        editor.goto_cur((0, 0));
+        editor.keymap_mut().set_mode("normal").unwrap();
+        (editor, Vi::default())
+    }
+
+    /// Type keys the way the main loop handles them.
+    fn type_keys(editor: &mut Editor, vi: &mut Vi, keys: &str) {
+        for input in parse_keys(keys).unwrap() {
+            press(editor, vi, input);
+        }
+    }
+
+    fn press(editor: &mut Editor, vi: &mut Vi, input: Input) {
+        vi.record(&input);
+        match editor.keymap_mut().press(input) {
+            KeyPress::Run(Command::Action(action)) if action == "vi-repeat" => {
+                let (keys, count) = vi.start_repeat();
+                for key in std::iter::repeat_n(keys, count).flatten() {
+                    press(editor, vi, key);
+                }
+                vi.end_repeat();
+            }
+            KeyPress::Run(Command::Action(action)) => vi.run(&action, editor),
+            KeyPress::Unbound(keys) => match &keys[..] {
+                [Input::Char(ch)] if !Vi::is_command_mode(editor) => editor.insert(*ch),
+                _ => vi.cancel(editor),
+            },
+            _ => {}
+        }
+    }
+
+    fn text(editor: &Editor) -> String {
+        editor.cur_buf().unwrap().content().join("\n")
+    }
+
+    fn pos(editor: &Editor) -> (usize, usize) {
+        editor.cur_buf().unwrap().cur_pos()
+    }
+
+    #[test]
+    fn moves_by_motions_and_counts() {
+        let (mut editor, mut vi) = normal("one two_three, four\nfive\nsix");
+        type_keys(&mut editor, &mut vi, "w");
+        assert_eq!(pos(&editor), (0, 4));
+        type_keys(&mut editor, &mut vi, "w");
+        assert_eq!(pos(&editor), (0, 13));
+        type_keys(&mut editor, &mut vi, "b e");
+        assert_eq!(pos(&editor), (0, 12));
+        type_keys(&mut editor, &mut vi, "$");
+        assert_eq!(pos(&editor), (0, 19));
+        type_keys(&mut editor, &mut vi, "0 3 l");
+        assert_eq!(pos(&editor), (0, 3));
+        type_keys(&mut editor, &mut vi, "2 j");
+        assert_eq!(pos(&editor), (2, 3));
+        type_keys(&mut editor, &mut vi, "g g");
+        assert_eq!(pos(&editor), (0, 0));
+        type_keys(&mut editor, &mut vi, "G");
+        assert_eq!(pos(&editor), (2, 0));
+        type_keys(&mut editor, &mut vi, "2 G");
+        assert_eq!(pos(&editor), (1, 0));
+        // Counts of more than one digit, with a zero in them
+        type_keys(&mut editor, &mut vi, "g g 1 0 l");
+        assert_eq!(pos(&editor), (0, 10));
+    }
+
+    #[test]
+    fn steps_over_whole_characters() {
+        let (mut editor, mut vi) = normal("héllo");
+        type_keys(&mut editor, &mut vi, "2 l");
+        assert_eq!(pos(&editor), (0, 3));
+        type_keys(&mut editor, &mut vi, "h");
+        assert_eq!(pos(&editor), (0, 1));
+        type_keys(&mut editor, &mut vi, "d l");
+        assert_eq!(text(&editor), "hllo");
+        type_keys(&mut editor, &mut vi, "P");
+        assert_eq!(text(&editor), "héllo");
+    }
+
+    #[test]
+    fn deletes_and_changes() {
+        let (mut editor, mut vi) = normal("a\nb\nc\nd");
+        type_keys(&mut editor, &mut vi, "d d");
+        assert_eq!(text(&editor), "b\nc\nd");
+        type_keys(&mut editor, &mut vi, "2 d d");
+        assert_eq!(text(&editor), "d");
+        type_keys(&mut editor, &mut vi, "p");
+        assert_eq!(text(&editor), "d\nb\nc");
+
+        let (mut editor, mut vi) = normal("one two three");
+        type_keys(&mut editor, &mut vi, "c w n e w Esc");
+        assert_eq!(text(&editor), "new two three");
+        assert_eq!(editor.keymap().mode(), "normal");
+        type_keys(&mut editor, &mut vi, "w d 2 w");
+        assert_eq!(text(&editor), "new ");
+    }
+
+    #[test]
+    fn repeats_the_last_change() {
+        let (mut editor, mut vi) = normal("a b c d e");
+        type_keys(&mut editor, &mut vi, "d w");
+        assert_eq!(text(&editor), "b c d e");
+        // Moving isn't a change, so `.` still deletes a word
+        type_keys(&mut editor, &mut vi, "l h .");
+        assert_eq!(text(&editor), "c d e");
+        type_keys(&mut editor, &mut vi, "2 .");
+        assert_eq!(text(&editor), "e");
+
+        let (mut editor, mut vi) = normal("x");
+        type_keys(&mut editor, &mut vi, "A y z Esc .");
+        assert_eq!(text(&editor), "xyzyz");
+    }
+
+    #[test]
+    fn visual_operators_take_the_character_under_the_cursor() {
+        let (mut editor, mut vi) = normal("abcdef");
+        type_keys(&mut editor, &mut vi, "l v 2 l d");
+        assert_eq!(text(&editor), "aef");
+        assert_eq!(editor.keymap().mode(), "normal");
+        type_keys(&mut editor, &mut vi, "v y $ p");
+        assert_eq!(text(&editor), "aefe");
+    }
+}
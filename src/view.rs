@@ -0,0 +1,190 @@
+use super::Direction;
+use std::cmp::{max, min};
+
+/// The part of a buffer that is currently visible.
+///
+/// The view is owned by the editor rather than the frontend, so scrolling
+/// behaves the same everywhere and scripts can inspect or change it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct View {
+    buf_id: usize,
+    start_row: usize,
+    start_col: usize,
+    rows: usize,
+    cols: usize,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl View {
+    pub fn new(buf_id: usize) -> Self {
+        Self {
+            buf_id,
+            start_row: 0,
+            start_col: 0,
+            rows: 23,
+            cols: 80,
+        }
+    }
+
+    pub fn buf_id(&self) -> usize {
+        self.buf_id
+    }
+
+    /// Point the view at another buffer, scrolled to where it was left.
+    pub fn show(&mut self, buf_id: usize, (start_row, start_col): (usize, usize)) {
+        self.buf_id = buf_id;
+        self.start_row = start_row;
+        self.start_col = start_col;
+    }
+
+    /// The first visible row and column.
+    pub fn start(&self) -> (usize, usize) {
+        (self.start_row, self.start_col)
+    }
+
+    pub fn start_row(&self) -> usize {
+        self.start_row
+    }
+
+    pub fn start_col(&self) -> usize {
+        self.start_col
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        self.cols = max(cols, 1);
+        self.rows = max(rows, 1);
+    }
+
+    /// The width of the line number gutter, including the space after it.
+    pub fn gutter_width(&self) -> usize {
+        (self.start_row + self.rows).to_string().len() + 1
+    }
+
+    /// The number of columns left for buffer text after the gutter.
+    pub fn text_cols(&self) -> usize {
+        max(self.cols.saturating_sub(self.gutter_width()), 1)
+    }
+
+    /// The range of buffer rows that fit on screen.
+    pub fn visible_rows(&self) -> (usize, usize) {
+        (self.start_row, self.start_row + self.rows)
+    }
+
+    pub fn contains(&self, (row, col): (usize, usize)) -> bool {
+        self.start_row <= row
+            && row < self.start_row + self.rows
+            && self.start_col <= col
+            && col < self.start_col + self.text_cols()
+    }
+
+    /// Translate a buffer position into a screen position, if it is visible.
+    pub fn to_screen(&self, (row, col): (usize, usize)) -> Option<(usize, usize)> {
+        if self.contains((row, col)) {
+            Some((row - self.start_row, col - self.start_col + self.gutter_width()))
+        } else {
+            None
+        }
+    }
+
+    /// Translate a screen position into a buffer position.
+    /// Clicks on the gutter map to the start of the line.
+    pub fn to_buffer(&self, (row, col): (usize, usize)) -> (usize, usize) {
+        (
+            self.start_row + row,
+            self.start_col + col.saturating_sub(self.gutter_width()),
+        )
+    }
+
+    /// Scroll just enough to bring the position on screen.
+    pub fn follow(&mut self, (row, col): (usize, usize)) {
+        if row < self.start_row {
+            self.start_row = row;
+        } else if row >= self.start_row + self.rows {
+            self.start_row = row + 1 - self.rows;
+        }
+
+        let text_cols = self.text_cols();
+        if col < self.start_col {
+            self.start_col = col;
+        } else if col >= self.start_col + text_cols {
+            self.start_col = col + 1 - text_cols;
+        }
+    }
+
+    /// Scroll so that the position sits in the middle of the screen.
+    pub fn center(&mut self, (row, col): (usize, usize)) {
+        self.start_row = row.saturating_sub(self.rows / 2);
+        self.start_col = 0;
+        self.follow((row, col));
+    }
+
+    /// Scroll without moving the cursor. The view never scrolls past
+    /// the last line of the buffer.
+    pub fn scroll(&mut self, dir: Direction, count: usize, line_count: usize) {
+        match dir {
+            Direction::Up => self.start_row = self.start_row.saturating_sub(count),
+            Direction::Down => {
+                self.start_row = min(self.start_row + count, line_count.saturating_sub(1))
+            }
+            Direction::Left => self.start_col = self.start_col.saturating_sub(count),
+            Direction::Right => self.start_col += count,
+            Direction::Nowhere => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(rows: usize, cols: usize) -> View {
+        let mut view = View::new(0);
+        view.resize(cols, rows);
+        view
+    }
+
+    #[test]
+    fn translates_positions() {
+        let mut view = view(10, 20);
+        // A gutter of "10 " for rows up to 10
+        assert_eq!(view.gutter_width(), 3);
+        assert_eq!(view.to_screen((0, 0)), Some((0, 3)));
+        assert_eq!(view.to_screen((9, 16)), Some((9, 19)));
+        assert_eq!(view.to_screen((10, 0)), None);
+        assert_eq!(view.to_screen((0, 17)), None);
+        assert_eq!(view.to_buffer((2, 5)), (2, 2));
+        assert_eq!(view.to_buffer((2, 1)), (2, 0));
+
+        view.follow((95, 30));
+        assert_eq!(view.start(), (86, 14));
+        assert_eq!(view.to_screen((95, 30)), Some((9, 19)));
+        assert_eq!(view.to_buffer((9, 19)), (95, 30));
+    }
+
+    #[test]
+    fn scrolls() {
+        let mut view = view(10, 20);
+        view.center((50, 4));
+        assert_eq!(view.start(), (45, 0));
+        view.scroll(Direction::Down, 100, 60);
+        assert_eq!(view.start(), (59, 0));
+        view.scroll(Direction::Up, 100, 60);
+        view.scroll(Direction::Right, 3, 60);
+        assert_eq!(view.start(), (0, 3));
+        view.follow((0, 0));
+        assert_eq!(view.start(), (0, 0));
+    }
+}
@@ -0,0 +1,92 @@
+//! The editor's state, driven the way the frontend and scripts drive it.
+
+use lite::*;
+
+#[test]
+fn views_keep_their_place() {
+    let mut editor = Editor::default();
+    editor.resize_view(80, 10);
+    editor.insert("line\n".repeat(100));
+    editor.goto_cur((45, 0));
+    editor.scroll_view(Direction::Up, 3);
+    let start = editor.view().start();
+    assert_eq!(start, (42, 0));
+
+    editor.new_buf();
+    editor.set_buf(1);
+    assert_eq!(editor.view().start(), (0, 0));
+    editor.set_buf(0);
+    assert_eq!(editor.view().start(), start);
+
+    // Closing a buffer shows the one before it where it was left
+    editor.set_buf(1);
+    editor.quit_buf(false);
+    assert_eq!((editor.cur_buf_id(), editor.view().start()), (0, start));
+}
+
+#[test]
+fn wide_characters_take_two_cells() {
+    let mut editor = Editor::default();
+    editor.resize_view(20, 5);
+    editor.insert("漢字 ok");
+    assert_eq!(editor.cur_buf().unwrap().cur_screen_pos(), (0, 7));
+    // The gutter is "1 " before the text
+    let gutter = editor.view().gutter_width();
+    editor.click((0, gutter + 3), 1);
+    assert_eq!(editor.cur_buf().unwrap().cur_pos(), (0, 3));
+    editor.click((0, gutter + 5), 1);
+    assert_eq!(editor.cur_buf().unwrap().cur_pos(), (0, 7));
+}
+
+fn code(code: &str) -> Expr {
+    parse(code).unwrap()
+}
+
+#[test]
+fn hooks_get_the_details_of_events() {
+    let mut editor = Editor::default();
+    assert!(editor.add_hook("typo", code("e -> e")).is_err());
+    // Raising the details is a way to see them from outside
+    let id = editor.add_hook("insert", code("e -> raise e")).unwrap();
+    editor.insert("hi");
+    let errors = editor.take_hook_errors();
+    assert_eq!(errors.len(), 1);
+    let Expr::Dict(details) = &errors[0] else {
+        panic!("expected the details, got {:?}", errors[0]);
+    };
+    assert_eq!(details.get(&symbol("event")), Some(&string("insert")));
+    assert_eq!(details.get(&symbol("text")), Some(&string("hi")));
+    assert_eq!(details.get(&symbol("row")), Some(&Expr::Int(0)));
+    assert_eq!(details.get(&symbol("col")), Some(&Expr::Int(0)));
+    assert!(editor.take_hook_errors().is_empty());
+
+    assert!(editor.remove_hook(id));
+    assert!(!editor.remove_hook(id));
+    editor.insert("!");
+    assert!(editor.take_hook_errors().is_empty());
+}
+
+#[test]
+fn hooks_run_only_for_their_event() {
+    let mut editor = Editor::default();
+    editor.add_hook("delete", code("e -> raise e")).unwrap();
+    editor.add_hook("exit", code("e -> raise e")).unwrap();
+    editor.insert("abc");
+    assert!(editor.take_hook_errors().is_empty());
+    editor.run_hooks("exit", &[]);
+    assert_eq!(editor.take_hook_errors(), vec![dict(&[(symbol("event"), string("exit"))])]);
+}
+
+#[test]
+fn hooks_dont_set_off_more_hooks() {
+    let mut editor = Editor::default();
+    editor.env.set(
+        symbol("insert"),
+        Expr::Builtin(Builtin::new("insert", &["text..."], "Insert text", "Insert text", insert)),
+    );
+    // Without a guard, this would insert forever
+    editor.add_hook("insert", code("e -> insert \"!\"")).unwrap();
+    editor.insert("hi");
+    assert!(editor.take_hook_errors().is_empty());
+    assert_eq!(editor.cur_buf().unwrap().content(), ["hi!"]);
+}
@@ -0,0 +1,403 @@
+//! The scripting language, run both by walking the tree with `eval` and on
+//! the VM with `run`, which should always agree.
+
+use lite::*;
+
+type Evaluator = fn(Expr, &mut Editor, &mut Env) -> Result<Expr, Expr>;
+
+/// Check that code gives the expected value, or raises the expected error,
+/// on both paths.
+fn check(code: &str, expected: &str) {
+    check_limited(Limits::default(), code, expected);
+}
+
+fn check_limited(limits: Limits, code: &str, expected: &str) {
+    for (path, result) in evaluate(limits, code) {
+        // Where errors were raised is checked by `locations`
+        let result = match result {
+            Ok(val) => format!("{:?}", without_location(val)),
+            Err(error) => format!("error {:?}", without_location(error)),
+        };
+        assert_eq!(result, expected, "{path} gave the wrong result for {code}");
+    }
+}
+
+/// Check that code raises an error at a location, out of a stack of calls,
+/// on both paths.
+fn check_location(code: &str, at: &str, stack: &[&str]) {
+    for (path, result) in evaluate(Limits::default(), code) {
+        let Err(Expr::Dict(error)) = result else {
+            panic!("{path} didn't raise an error for {code}");
+        };
+        assert_eq!(error.get(&symbol("at")), Some(&string(at)), "{path} gave the wrong location for {code}");
+        let expected = Expr::List(stack.iter().map(string).collect());
+        let stack = error.get(&symbol("stack")).cloned().unwrap_or(Expr::List(vec![]));
+        assert_eq!(stack, expected, "{path} gave the wrong stack for {code}");
+    }
+}
+
+fn without_location(val: Expr) -> Expr {
+    match val {
+        Expr::Dict(mut dict) => {
+            for key in ["at", "source", "stack"] {
+                dict.remove(&symbol(key));
+            }
+            Expr::Dict(dict)
+        }
+        val => val,
+    }
+}
+
+/// Evaluate code on each path.
+fn evaluate(limits: Limits, code: &str) -> Vec<(&'static str, Result<Expr, Expr>)> {
+    let expr = parse(code).unwrap_or_else(|e| panic!("{code} doesn't parse: {e:?}"));
+    let mut results = vec![];
+    for (path, evaluate) in [("eval", eval as Evaluator), ("run", run as Evaluator)] {
+        let mut editor = Editor::default();
+        editor.set_limits(limits);
+        let mut env = Env::new();
+        // Builtins get their arguments unevaluated
+        env.set(
+            symbol("args"),
+            Expr::Builtin(Builtin::new("args", &["a", "[b]"], "", "", |args, _, _| Ok(Expr::List(args)))),
+        );
+        for builtin in string_builtins() {
+            env.set(symbol(&builtin.name), Expr::Builtin(builtin));
+        }
+        // Like pressing Ctrl-C while the script runs
+        env.set(
+            symbol("interrupt"),
+            Expr::Builtin(Builtin::new("interrupt", &[], "", "", |_, editor, _| {
+                editor.interrupt().request();
+                Ok(Expr::None)
+            })),
+        );
+        results.push((path, evaluate(expr.clone(), &mut editor, &mut env)));
+    }
+    results
+}
+
+#[test]
+fn literals() {
+    check("1", "1");
+    check("2.5", "2.5");
+    check("\"hi\"", "\"hi\"");
+    check("True", "True");
+    check("None", "None");
+    check("[1, [2, 3]]", "[1, [2, 3]]");
+    check("{a: 1 + 1, b: [3]}", "{a: 2, b: [3]}");
+    check("'(1 + x)", "(1 + x)");
+    check("{}", "{}");
+}
+
+#[test]
+fn operators() {
+    check("1 + 2 * 3", "7");
+    check("2 ^ 3 ^ 2", "512");
+    check("let x = 2; [-x ^ 2, 2.0 ^ -(x - 1), -x * 3]", "[-4, 0.5, -6]");
+    check("7 % 3 - -1", "2");
+    check("1.5 + 1", "2.5");
+    check("\"a\" + \"b\" + 1", "\"ab1\"");
+    check("[1] + [2] * 2", "[1, 2, 2]");
+    check("-\"abc\"", "\"cba\"");
+    check("0 to 3", "[0, 1, 2]");
+    check("[1 < 2, 2 <= 1, 1 == 1.0, \"a\" != \"b\"]", "[True, False, True, True]");
+    check("!(1 > 2) & (True | False)", "True");
+    check("1 + \"a\"", "error {expr: 1 + \"a\", kind: \"InvalidAdd\"}");
+    check("1 < \"a\"", "error {expr: 1 < \"a\", kind: \"InvalidCompare\"}");
+    check("!1", "error {expr: !1, kind: \"InvalidNot\"}");
+}
+
+#[test]
+fn arithmetic_errors() {
+    check("10 / 0", "error {expr: 10 / 0, kind: \"DivideByZero\"}");
+    check("10 % 0", "error {expr: 10 % 0, kind: \"DivideByZero\"}");
+    check("10.0 / 0", "inf");
+    check("9223372036854775807 + 1", "error {expr: 9223372036854775807 + 1, kind: \"Overflow\"}");
+    check("-9223372036854775807 - 2", "error {expr: -9223372036854775807 - 2, kind: \"Overflow\"}");
+    check("4611686018427387904 * 2", "error {expr: 4611686018427387904 * 2, kind: \"Overflow\"}");
+    check("(-9223372036854775807 - 1) / -1", "error {expr: -9223372036854775808 / -1, kind: \"Overflow\"}");
+    check("-(-9223372036854775807 - 1)", "error {expr: --9223372036854775808, kind: \"Overflow\"}");
+    check("2 ^ 62", "4611686018427387904");
+    check("2 ^ 70", "error {expr: 2 ^ 70, kind: \"Overflow\"}");
+    check("2 ^ 4294967296", "error {expr: 2 ^ 4294967296, kind: \"Overflow\"}");
+    check("2 ^ -1", "error {expr: 2 ^ -1, kind: \"InvalidPow\"}");
+    check("2.0 ^ -1", "0.5");
+    check("\"a\" * -1", "error {expr: \"a\" * -1, kind: \"InvalidMul\"}");
+    check("[1] * -1", "error {expr: [1] * -1, kind: \"InvalidMul\"}");
+    check("try (1 / 0) catch e -> e@kind", "\"DivideByZero\"");
+}
+
+#[test]
+fn comments() {
+    check("1 +  # c\n2", "3");
+    check("[1, # one\n 2 # two\n]", "[1, 2]");
+    check("# first\nlet x = 1; # x\nx # last", "1");
+    check("{ # start\n (1 # one\n) }", "1");
+}
+
+#[test]
+fn short_circuit() {
+    check("False & undefined", "False");
+    check("True | undefined", "True");
+    check("True & undefined", "error {expr: undefined, kind: \"SymbolNotDefined\"}");
+    check("1 & True", "error {expr: 1 & True, kind: \"InvalidAnd\"}");
+    check("True & 1", "error {expr: True & 1, kind: \"InvalidAnd\"}");
+    check("False | 2", "error {expr: False | 2, kind: \"InvalidOr\"}");
+}
+
+#[test]
+fn indexing() {
+    check("[1, 2, 3]@1", "2");
+    check("[1, 2]@5", "None");
+    check("\"héllo\"@1", "\"é\"");
+    check("let d = {file: \"a\"}; d@file", "\"a\"");
+    check("let k = \"x\"; {\"x\": 1}@k", "1");
+    check("[1]@\"a\"", "error {expr: [1]@\"a\", kind: \"InvalidGet\"}");
+}
+
+#[test]
+fn variables_and_scopes() {
+    check("let x = 1; x + 1", "2");
+    check("let x = 1 in x + 1", "2");
+    check("let x = 1; let y = (let x = 5 in x); [x, y]", "[1, 5]");
+    check("x", "error {expr: x, kind: \"SymbolNotDefined\"}");
+    check("{ let a = 1; let b = 2; a + b }", "3");
+    check("{}", "{}");
+}
+
+#[test]
+fn functions() {
+    check("let f = (a, b) -> a + b; f 1 2", "3");
+    check("let f = () -> 5; f ()", "5");
+    check("let f = _ -> 3; [f (), f 1]", "[3, 3]");
+    check("let f = (a, b = a * 2) -> [a, b]; [f 1, f 1 7]", "[[1, 2], [1, 7]]");
+    check("let f = (a, ...r) -> [a, r]; [f 1, f 1 2 3]", "[[1, []], [1, [2, 3]]]");
+    check(
+        "let f = (a, b) -> a; f 1",
+        "error {expr: \"f takes 2 arguments but was given 1\", kind: \"ArityMismatch\"}",
+    );
+    check(
+        "let f = (a, ...b) -> a; f ()",
+        "error {expr: \"f takes at least 1 argument but was given 0\", kind: \"ArityMismatch\"}",
+    );
+    check("5 1", "error {expr: 5, kind: \"InvalidFn\"}");
+    check("(x -> x * 2) 4", "8");
+    for code in ["(...r, a) -> a", "(a = 1, b) -> b", "(...a, ...b) -> a"] {
+        let Err(Expr::Dict(error)) = parse(code) else {
+            panic!("{code} should be a syntax error");
+        };
+        assert_eq!(error.get(&symbol("kind")), Some(&string("InvalidSyntax")));
+    }
+}
+
+#[test]
+fn closures() {
+    check("let make = n -> (() -> n); let a = make 1; let b = make 2; [a (), b ()]", "[1, 2]");
+    check("let x = 1; let f = () -> { let x = 2; x }; [f (), x]", "[2, 1]");
+    check("let later = () -> defined-later; let defined-later = 7; later ()", "7");
+    check("let fact = n -> if n <= 1 then 1 else n * fact (n - 1); fact 10", "3628800");
+    check("let f = x -> x; f == f", "True");
+}
+
+#[test]
+fn procs_and_macros() {
+    check("let p = (a, b) => a * b; p 3 4", "12");
+    check("let g = 1; let p = () => g; p ()", "error {expr: g, kind: \"SymbolNotDefined\"}");
+    check("let m = v ~> let w = v; m 3; w", "3");
+    check("let m = () ~> x; let x = 4; m ()", "4");
+}
+
+#[test]
+fn builtins() {
+    check("args (1 + 2) x", "[(1 + 2), x]");
+    check("args ()", "error {expr: \"args takes 1 to 2 arguments but was given 0\", kind: \"ArityMismatch\"}");
+    check("let f = args; f y", "[y]");
+}
+
+#[test]
+fn conditionals_and_loops() {
+    check("if 1 < 2 then \"yes\" else \"no\"", "\"yes\"");
+    check("if 1 then 2 else 3", "error {expr: 1, kind: \"InvalidCond\"}");
+    check("let i = 0; let s = 0; while i < 5 do { let s = s + i; let i = i + 1 }; s", "10");
+    check("while False do 1", "None");
+    check("let s = 0; for x in [1, 2, 3] do let s = s + x; s", "6");
+    check("let s = \"\"; for c in \"abc\" do let s = c + s; s", "\"cba\"");
+    check("let s = []; for [k, v] in {a: 1, b: 2} do let s = s + [k, v]; s", "[a, 1, b, 2]");
+    check("for x in 5 do x", "error {expr: 5, kind: \"InvalidIter\"}");
+    check("for x in [] do x", "None");
+}
+
+#[test]
+fn patterns() {
+    check("let [a, b] = [1, 2]; a + b", "3");
+    check("let [a, ...r] = [1, 2, 3]; r", "[2, 3]");
+    check("let {x, y: why} = {x: 1, y: 2, z: 3}; [x, why]", "[1, 2]");
+    check(
+        "let [a, b] = [1]; a",
+        "error {expr: {pattern: [a, b], value: [1]}, kind: \"PatternMismatch\"}",
+    );
+    check(
+        "let f = x -> match x with { 0 -> \"zero\", Int n -> n * 10, String s -> s + \"!\", [] -> \"empty\", [h, ...t] -> t, {kind} -> kind, _ -> \"other\" }; \
+         [f 0, f 3, f \"hi\", f [], f [1, 2, 3], f {kind: \"k\"}, f 2.5]",
+        "[\"zero\", 30, \"hi!\", \"empty\", [2, 3], \"k\", \"other\"]",
+    );
+    check(
+        "match 5 with { 1 -> 2 }",
+        "error {expr: {patterns: [1], value: 5}, kind: \"PatternMismatch\"}",
+    );
+    check("let f = x -> match x with { Number -> 1, Fn -> 2, Bool b -> b }; [f 1.5, f f, f False]", "[1, 2, False]");
+    // An arm's bindings don't leak out of it, and calls in it are still tail calls
+    check("let n = 1; match 2 with { n -> n }; n", "1");
+    check("let n = 1; [match 2 with { n -> n }, n]", "[2, 1]");
+    check("let f = n -> match n with { 0 -> \"done\", _ -> f (n - 1) }; f 5000", "\"done\"");
+}
+
+#[test]
+fn errors() {
+    check("raise 5", "error 5");
+    check("try (raise 5) catch e -> e + 1", "6");
+    check("try nope catch e -> e", "{expr: nope, kind: \"SymbolNotDefined\"}");
+    check("try 1 catch e -> 2", "1");
+    check("let f = () -> raise \"deep\"; try (f ()) catch e -> [e]", "[\"deep\"]");
+    check("let x = 1; try (let x = 2 in raise x) catch e -> [e, x]", "[2, 1]");
+    check("try (try (raise 1) catch e -> raise (e + 1)) catch e -> e * 10", "20");
+    check("[1, try (raise 2) catch e -> e, 3]", "[1, 2, 3]");
+    check("try (raise 1) catch args", "['1]");
+}
+
+#[test]
+fn limits() {
+    let limits = Limits {
+        steps: 10_000,
+        depth: 20,
+        memory: 1_000_000,
+    };
+    check_limited(
+        limits,
+        "while True do 1",
+        "error {expr: \"The script ran for more than 10000 steps\", kind: \"StepLimit\"}",
+    );
+    check_limited(
+        limits,
+        "try (while True do 1) catch e -> 0",
+        "error {expr: \"The script ran for more than 10000 steps\", kind: \"StepLimit\"}",
+    );
+    check_limited(
+        limits,
+        "let f = n -> 1 + f n; f 1",
+        "error {expr: \"Functions were called more than 20 deep\", kind: \"DepthLimit\"}",
+    );
+    check_limited(limits, "let f = n -> if n == 0 then 0 else 1 + f (n - 1); f 15", "15");
+    check_limited(limits, "let f = n -> 1 + f n; try (f 1) catch e -> e@kind", "\"DepthLimit\"");
+    check_limited(
+        limits,
+        "\"ab\" * 1000000",
+        "error {expr: \"The script used more than 1000000 bytes of memory\", kind: \"MemoryLimit\"}",
+    );
+    check_limited(
+        limits,
+        "0 to 1000000",
+        "error {expr: \"The script used more than 1000000 bytes of memory\", kind: \"MemoryLimit\"}",
+    );
+    check(
+        "interrupt (); try (while True do 1) catch e -> 0",
+        "error {expr: \"The script was stopped with Ctrl-C\", kind: \"Interrupted\"}",
+    );
+}
+
+#[test]
+fn deep_recursion() {
+    // Evaluating nested expressions and the arguments of builtins recurses,
+    // so calls within the depth limit can still go deep into the stack
+    let results = with_script_stack(|| {
+        let kind = |result: Result<Expr, Expr>| match result {
+            Ok(val) => format!("{val:?}"),
+            Err(Expr::Dict(error)) => format!("{:?}", error.get(&symbol("kind"))),
+            Err(error) => format!("{error:?}"),
+        };
+        [
+            "let f = n -> if n == 0 then 0 else 1 + f (n - 1); f 990",
+            "let f = n -> if n == 0 then 0 else 1 + f (n - 1); f 5000",
+            "let f = n -> match n with { 0 -> 0, _ -> { let x = n; try (if x > 0 then 1 + (f (n - 1)) else 0) catch e -> raise e } }; to-string (f 5000)",
+        ]
+        .map(|code| evaluate(Limits::default(), code).into_iter().map(|(path, result)| (path, kind(result))).collect::<Vec<_>>())
+    });
+    let depth_limit = format!("{:?}", Some(string("DepthLimit")));
+    for (path, result) in &results[0] {
+        assert_eq!(result, "990", "{path} gave the wrong result");
+    }
+    for (path, result) in results[1].iter().chain(&results[2]) {
+        assert_eq!(result, &depth_limit, "{path} didn't stop at a limit");
+    }
+}
+
+#[test]
+fn tail_calls() {
+    // Calls that are the last thing a function does don't count toward the depth
+    let limits = Limits {
+        depth: 10,
+        ..Limits::default()
+    };
+    check_limited(limits, "let count = n -> if n == 0 then \"done\" else count (n - 1); count 100000", "\"done\"");
+    check_limited(
+        limits,
+        "let even = n -> match n with { 0 -> True, _ -> odd (n - 1) }; let odd = n -> match n with { 0 -> False, _ -> even (n - 1) }; even 10001",
+        "False",
+    );
+    check_limited(limits, "let sum = (n, acc) -> { let m = n - 1; if n == 0 then acc else sum m (acc + n) }; sum 1000 0", "500500");
+}
+
+#[test]
+fn locations() {
+    check_location("1 +\n  undefined", "input:2:3", &[]);
+    check_location("[1, 2] + 3 * \"a\"", "input:1:12", &[]);
+    check_location("if 1 then 2 else 3", "input:1:1", &[]);
+    check_location(
+        "let f = x -> 1 + nope;\nlet g = () -> 1 + f 1;\ng ()",
+        "input:1:18",
+        &["f called at input:2:19", "g called at input:3:1"],
+    );
+    // A call that's the last thing a function does takes the place of the function
+    check_location("let f = x -> 1 + nope;\nlet g = () -> f 1;\ng ()", "input:1:18", &["f called at input:2:15"]);
+    check_location("let f = (a, b) -> a;\n[f 1]", "input:2:2", &[]);
+    check_location("let f = () -> raise {kind: \"Mine\", expr: 1};\n  f ()", "input:1:15", &["f called at input:2:3"]);
+    // Recursion shows each call site once, with how many times it repeats
+    check_location(
+        "let f = n -> if n == 0 then nope else 1 + f (n - 1);\nf 3",
+        "input:1:29",
+        &["f called at input:1:43 (×3)", "f called at input:2:1"],
+    );
+
+    let error = parse("let x = (1 +\n2").unwrap_err();
+    let Expr::Dict(error) = error else {
+        panic!("syntax errors are dicts");
+    };
+    assert_eq!(error.get(&symbol("kind")), Some(&string("InvalidSyntax")));
+    assert_eq!(error.get(&symbol("at")), Some(&string("input:2:2")));
+    assert_eq!(error.get(&symbol("source")), Some(&string("2")));
+}
+
+#[test]
+fn strings() {
+    check("length \"héllo 👋\"", "7");
+    check("[length [1, 2], length {a: 1}]", "[2, 1]");
+    check("split \"  a b\n c \"", "[\"a\", \"b\", \"c\"]");
+    check("split \"a,,b\" \",\"", "[\"a\", \"\", \"b\"]");
+    check("split \"né\" \"\"", "[\"n\", \"é\"]");
+    check("join [1, \"b\", True] \", \"", "\"1, b, True\"");
+    check("join (split \"a b\")", "\"ab\"");
+    check("trim \"\t  hi \n\"", "\"hi\"");
+    check("replace \"a-b-c\" \"-\" \"–\"", "\"a–b–c\"");
+    check("[contains \"naïve\" \"ïv\", contains [1, 2] 3, contains {a: 1} a]", "[True, False, True]");
+    check("[starts-with \"über\" \"ü\", ends-with \"über\" \"er\", ends-with \"a\" \"ab\"]", "[True, True, False]");
+    check("[upper \"straße\", lower \"ÀB\"]", "[\"STRASSE\", \"àb\"]");
+    check("substring \"héllo\" 1 3", "\"él\"");
+    check("[substring \"héllo\" -3, substring \"héllo\" 2 100, substring \"abc\" 2 1]", "[\"llo\", \"llo\", \"\"]");
+    check("let x = 2; format \"{} + {x} = {x + 2} {{}}\" \"two\"", "\"two + 2 = 4 {}\"");
+    check("[to-number \" 42 \", to-number \"-1.5\", to-number 3]", "[42, -1.5, 3]");
+    check("to-string [1, \"a\"]", "\"[1, \\\"a\\\"]\"");
+    check("to-number \"12abc\"", "error {expr: \"12abc\", kind: \"InvalidNumber\"}");
+    check("upper 1", "error {expr: \"upper expected a string, not 1\", kind: \"TypeMismatch\"}");
+    check("format \"{} {}\" 1", "error {expr: \"Not enough values for \\\"{} {}\\\"\", kind: \"InvalidFormat\"}");
+}